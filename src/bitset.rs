@@ -47,7 +47,7 @@ impl<'a, 'b> AndIter<'a, 'b> {
     fn fetch_chunk(a: &BitSet, b: &BitSet, chunk_idx: usize) -> Option<usize> {
         a.data
             .get(chunk_idx)
-            .and_then(|x| b.data.get(0).map(|y| x & y))
+            .and_then(|x| b.data.get(chunk_idx).map(|y| x & y))
     }
 }
 impl<'a, 'b> Iterator for AndIter<'a, 'b> {
@@ -64,7 +64,7 @@ impl<'a, 'b> Iterator for AndIter<'a, 'b> {
                     let val = (1 << self.min) & x;
                     self.min += 1;
                     if val != 0 {
-                        return Some(self.maj * BitSet::BITS_PER_CHUNK + self.min);
+                        return Some(self.maj * BitSet::BITS_PER_CHUNK + (self.min - 1));
                     }
                 }
                 None => return None,
@@ -12,9 +12,11 @@ macro_rules! rule {
                             $getter
                         ),*
                     ],
+                    transform: None,
                 }
                 ),*
             ],
+            priority: 0,
         }
     }};
 }
@@ -159,3 +161,258 @@ impl<T: Iterator + Sized> WithFirst for T {
         WithFirstIter { t: self, b: true }
     }
 }
+
+/// Implemented by the locations declared with `proto!`, which become types.
+pub trait NamedLoc {
+    const ID: crate::LocId;
+}
+/// Locations that may act as the putter of an action.
+pub trait CanPut: NamedLoc {}
+/// Locations that may act as a getter of an action.
+pub trait CanGet: NamedLoc {}
+pub fn putter_id<N: CanPut>() -> crate::LocId {
+    N::ID
+}
+pub fn getter_id<N: CanGet>() -> crate::LocId {
+    N::ID
+}
+
+/// Declares a protocol inline, expanding to a struct implementing `Proto`.
+/// The syntax mirrors the textual format of `parse_proto`, preceded by
+/// optional attributes (e.g. doc comments) for the struct:
+/// ```
+/// # #![feature(specialization)]
+/// # use reo_rs::{proto, proto::{traits::Proto, definition::Formula}};
+/// fn double(r: &mut std::mem::MaybeUninit<u32>, x: *const u32) {
+///     *r = std::mem::MaybeUninit::new(unsafe { *x } * 2);
+/// }
+/// proto! {
+///     pub Alternator<T> {
+///         putter a, b: T;
+///         getter c: T;
+///         mem m: T;
+///         mem n: u32 = 5;
+///         fn double/1 = double;
+///         rule { a => c; b => m; }
+///         rule (Formula::MemIsNull(m)) { m => c; }
+///         rule { double(n) => ; }
+///     }
+/// }
+/// let (mut a, mut b, mut c) = Alternator::<u32>::instantiate_and_claim();
+/// ```
+/// LocIds are assigned in order of declaration, starting at 0. Within guards, which
+/// are expressions of type `Formula`, every location name stands for its LocId.
+/// A rule without a guard is always enabled. As in the textual format, `priority n` after
+/// `rule` sets the rule's priority, which is 0 otherwise. Functions are declared with their arity,
+/// and defined by the expression after `=`. The `Interface` contains the ports in
+/// order of declaration.
+///
+/// Actions naming undeclared locations, or using a location in a role it cannot
+/// play, do not compile:
+/// ```compile_fail
+/// # #![feature(specialization)]
+/// # use reo_rs::proto;
+/// proto! { P { putter a: u32; getter b: u32; rule { b => a; } } }
+/// ```
+/// ```compile_fail
+/// # #![feature(specialization)]
+/// # use reo_rs::proto;
+/// proto! { P { putter a: u32; getter b: u32; rule { a => x; } } }
+/// ```
+#[macro_export]
+macro_rules! proto {
+    // declarations are munched into accumulators of locations, functions and rules
+    ($(#[$attr:meta])* $vis:vis $name:ident $(< $($tp:ident),* >)? { $($body:tt)* }) => {
+        $crate::proto!(@munch [$(#[$attr])* $vis $name [$($($tp),*)?]] [] [] [] $($body)*);
+    };
+    (@munch $hdr:tt [$($locs:tt)*] $funcs:tt $rules:tt
+        putter $($loc:ident),+ : $t:ty ; $($rest:tt)*
+    ) => {
+        $crate::proto!(@munch $hdr [$($locs)* $((PortPutter $loc $t ;))+] $funcs $rules $($rest)*);
+    };
+    (@munch $hdr:tt [$($locs:tt)*] $funcs:tt $rules:tt
+        getter $($loc:ident),+ : $t:ty ; $($rest:tt)*
+    ) => {
+        $crate::proto!(@munch $hdr [$($locs)* $((PortGetter $loc $t ;))+] $funcs $rules $($rest)*);
+    };
+    (@munch $hdr:tt [$($locs:tt)*] $funcs:tt $rules:tt
+        mem $($loc:ident),+ : $t:ty = $init:expr ; $($rest:tt)*
+    ) => {
+        $crate::proto!(@munch $hdr [$($locs)* $((MemInitialized $loc $t ; $init))+] $funcs $rules $($rest)*);
+    };
+    (@munch $hdr:tt [$($locs:tt)*] $funcs:tt $rules:tt
+        mem $($loc:ident),+ : $t:ty ; $($rest:tt)*
+    ) => {
+        $crate::proto!(@munch $hdr [$($locs)* $((MemUninitialized $loc $t ;))+] $funcs $rules $($rest)*);
+    };
+    (@munch $hdr:tt $locs:tt [$($funcs:tt)*] $rules:tt
+        fn $fname:ident / $arity:tt = $func:expr ; $($rest:tt)*
+    ) => {
+        $crate::proto!(@munch $hdr $locs [$($funcs)* ($fname $arity $func)] $rules $($rest)*);
+    };
+    (@munch $hdr:tt $locs:tt $funcs:tt [$($rules:tt)*]
+        rule priority $priority:literal ($guard:expr) { $($actions:tt)* } $($rest:tt)*
+    ) => {
+        $crate::proto!(@munch $hdr $locs $funcs [$($rules)* (($guard) $priority $($actions)*)] $($rest)*);
+    };
+    (@munch $hdr:tt $locs:tt $funcs:tt [$($rules:tt)*]
+        rule priority $priority:literal { $($actions:tt)* } $($rest:tt)*
+    ) => {
+        $crate::proto!(@munch $hdr $locs $funcs [$($rules)* ((Formula::True) $priority $($actions)*)] $($rest)*);
+    };
+    (@munch $hdr:tt $locs:tt $funcs:tt [$($rules:tt)*]
+        rule ($guard:expr) { $($actions:tt)* } $($rest:tt)*
+    ) => {
+        $crate::proto!(@munch $hdr $locs $funcs [$($rules)* (($guard) 0 $($actions)*)] $($rest)*);
+    };
+    (@munch $hdr:tt $locs:tt $funcs:tt [$($rules:tt)*]
+        rule { $($actions:tt)* } $($rest:tt)*
+    ) => {
+        $crate::proto!(@munch $hdr $locs $funcs [$($rules)* ((Formula::True) 0 $($actions)*)] $($rest)*);
+    };
+    (@munch [$(#[$attr:meta])* $vis:vis $name:ident [$($tp:ident),*]]
+        [$(($kind:ident $loc:ident $t:ty ; $($init:expr)?))*]
+        [$(($fname:ident $arity:tt $func:expr))*]
+        [$((($guard:expr) $priority:literal $($actions:tt)*))*]
+    ) => {
+        $(#[$attr])*
+        $vis struct $name<$($tp: 'static),*> {
+            phantom: std::marker::PhantomData<($($tp,)*)>,
+        }
+        const _: () = {
+            #[allow(unused_imports)]
+            use $crate::{
+                helper::{getter_id, putter_id, CanGet, CanPut, NamedLoc},
+                proto::{
+                    definition::{
+                        ActionDef, BehaviourDef, Formula, LocKind, RuleDef, Term, TypelessProtoDef,
+                    },
+                    reflection::TypeInfo,
+                    traits::{
+                        FuncDefPromise, HasUnclaimedPorts, MemFillPromise, PromiseFulfilled, Proto,
+                    },
+                    Getter, Putter,
+                },
+                LocId,
+            };
+            #[allow(non_camel_case_types)]
+            enum __Ids {
+                $($loc),*
+            }
+            // each location is both a type (for checking roles) and a LocId (for guards)
+            $(
+                #[allow(non_camel_case_types, dead_code)]
+                struct $loc {}
+                impl NamedLoc for $loc {
+                    const ID: LocId = __Ids::$loc as LocId;
+                }
+                #[allow(non_upper_case_globals, dead_code)]
+                const $loc: LocId = __Ids::$loc as LocId;
+                $crate::proto!(@roles $kind $loc);
+            )*
+            impl<$($tp: 'static),*> Proto for $name<$($tp),*> {
+                fn typeless_proto_def() -> &'static TypelessProtoDef {
+                    $crate::lazy_static::lazy_static! {
+                        static ref DEF: TypelessProtoDef = TypelessProtoDef {
+                            behaviour: BehaviourDef {
+                                rules: vec![$(
+                                    RuleDef {
+                                        guard: $guard,
+                                        actions: $crate::proto!(@actions [] $($actions)*),
+                                        priority: $priority,
+                                    }
+                                ),*],
+                            },
+                            loc_kinds: vec![$(($loc, LocKind::$kind)),*].into_iter().collect(),
+                            loc_names: vec![$(($loc, stringify!($loc).to_owned())),*]
+                                .into_iter()
+                                .collect(),
+                            families: Default::default(),
+                        };
+                    }
+                    &DEF
+                }
+                #[allow(unused_variables, unreachable_patterns)]
+                fn fill_memory(__loc_id: LocId, __p: MemFillPromise) -> Option<PromiseFulfilled> {
+                    match __loc_id {
+                        $($(
+                            __id if __id == $loc => __p.fill_memory::<$t>($init).ok(),
+                        )?)*
+                        _ => None,
+                    }
+                }
+                #[allow(unused_variables)]
+                fn def_func(__name: &'static str, __p: FuncDefPromise) -> Option<PromiseFulfilled> {
+                    match __name {
+                        $(
+                            stringify!($fname) => Some($crate::proto!(@define __p $arity $func)),
+                        )*
+                        _ => None,
+                    }
+                }
+                fn loc_type(__loc_id: LocId) -> Option<TypeInfo> {
+                    $(
+                        if __loc_id == $loc {
+                            return Some(TypeInfo::new::<$t>());
+                        }
+                    )*
+                    None
+                }
+                type Interface = $crate::proto!(@iface_ty [] $(($kind $t))*);
+                fn instantiate_and_claim() -> Self::Interface {
+                    let __p = Self::instantiate();
+                    $crate::proto!(@iface_claim __p [] $(($kind $loc))*)
+                }
+            }
+        };
+    };
+    (@roles PortPutter $loc:ident) => { impl CanPut for $loc {} };
+    (@roles PortGetter $loc:ident) => { impl CanGet for $loc {} };
+    (@roles $mem:ident $loc:ident) => {
+        impl CanPut for $loc {}
+        impl CanGet for $loc {}
+    };
+    (@actions [$($acc:expr),*]) => { vec![$($acc),*] };
+    (@actions [$($acc:expr),*] $f:ident ($p:ident) => $($g:ident),* ; $($rest:tt)*) => {
+        $crate::proto!(@actions [$($acc,)* ActionDef {
+            putter: putter_id::<$p>(),
+            getters: vec![$(getter_id::<$g>()),*],
+            transform: Some(stringify!($f)),
+        }] $($rest)*)
+    };
+    (@actions [$($acc:expr),*] $p:ident => $($g:ident),* ; $($rest:tt)*) => {
+        $crate::proto!(@actions [$($acc,)* ActionDef {
+            putter: putter_id::<$p>(),
+            getters: vec![$(getter_id::<$g>()),*],
+            transform: None,
+        }] $($rest)*)
+    };
+    (@define $p:ident 0 $f:expr) => { $p.define_arity0($f) };
+    (@define $p:ident 1 $f:expr) => { $p.define_arity1($f) };
+    (@define $p:ident 2 $f:expr) => { $p.define_arity2($f) };
+    (@define $p:ident 3 $f:expr) => { $p.define_arity3($f) };
+    // the interface consists of the ports, in order of declaration
+    (@iface_ty [$($acc:ty),*]) => { ($($acc),*) };
+    (@iface_ty [$($acc:ty),*] (PortPutter $t:ty) $($rest:tt)*) => {
+        $crate::proto!(@iface_ty [$($acc,)* Putter<$t>] $($rest)*)
+    };
+    (@iface_ty [$($acc:ty),*] (PortGetter $t:ty) $($rest:tt)*) => {
+        $crate::proto!(@iface_ty [$($acc,)* Getter<$t>] $($rest)*)
+    };
+    (@iface_ty [$($acc:ty),*] ($mem:ident $t:ty) $($rest:tt)*) => {
+        $crate::proto!(@iface_ty [$($acc),*] $($rest)*)
+    };
+    (@iface_claim $p:ident [$($acc:ident),*]) => {{
+        use std::convert::TryInto as _;
+        ($($p.claim($acc).try_into().expect("BAD CLAIM")),*)
+    }};
+    (@iface_claim $p:ident [$($acc:ident),*] (PortPutter $loc:ident) $($rest:tt)*) => {
+        $crate::proto!(@iface_claim $p [$($acc,)* $loc] $($rest)*)
+    };
+    (@iface_claim $p:ident [$($acc:ident),*] (PortGetter $loc:ident) $($rest:tt)*) => {
+        $crate::proto!(@iface_claim $p [$($acc,)* $loc] $($rest)*)
+    };
+    (@iface_claim $p:ident [$($acc:ident),*] ($mem:ident $loc:ident) $($rest:tt)*) => {
+        $crate::proto!(@iface_claim $p [$($acc),*] $($rest)*)
+    };
+}
@@ -5,11 +5,18 @@ use std::sync::Arc;
 /// generalizes over port and memory cell "name"
 pub type LocId = usize;
 pub type RuleId = usize;
+/// names of functions in protocol definitions. `'static` as definitions usually are
+pub type Name = &'static str;
 pub type ProtoHandle = Arc<proto::ProtoAll>;
 
+// used by generated code, which cannot assume the dependency
+#[doc(hidden)]
+pub use lazy_static;
+
 #[macro_use]
 pub mod helper;
 pub mod bitset;
+pub mod primitives;
 pub mod proto;
 // pub mod rbpa; // TODO FIX
 pub mod tokens;
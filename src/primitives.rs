@@ -0,0 +1,460 @@
+//! Ready-made protocols for the primitive connectors of Reo.
+//! Each is generic over the type of its data, and its `Interface` holds its
+//! ports in order of declaration. Connectors with several inputs or outputs
+//! are given two, from which larger ones can be composed.
+
+use crate::{
+    proto::{
+        definition::{ActionDef, BehaviourDef, Formula, LocKind, RuleDef, Term, TypelessProtoDef},
+        reflection::TypeInfo,
+        traits::{FuncDefPromise, HasUnclaimedPorts, MemFillPromise, PromiseFulfilled, Proto},
+        Getter, Putter,
+    },
+    LocId,
+};
+use hashbrown::HashMap;
+use parking_lot::Mutex;
+use std::mem::MaybeUninit;
+
+proto! {
+    /// Synchronously passes data from `a` to `b`.
+    pub SyncChannel<T> {
+        putter a: T;
+        getter b: T;
+        rule { a => b; }
+    }
+}
+
+proto! {
+    /// Passes data from `a` to `b` if `b` is ready, and loses it otherwise.
+    pub LossySync<T> {
+        putter a: T;
+        getter b: T;
+        rule priority 1 { a => b; }
+        rule { a => ; }
+    }
+}
+
+proto! {
+    /// Consumes the data of `a` and `b`, which must put synchronously.
+    pub SyncDrain<T> {
+        putter a, b: T;
+        rule { a => ; b => ; }
+    }
+}
+
+proto! {
+    /// Consumes the data of `a` and `b`, which never put synchronously.
+    pub AsyncDrain<T> {
+        putter a, b: T;
+        rule { a => ; }
+        rule { b => ; }
+    }
+}
+
+proto! {
+    /// Buffers one datum put by `a` until it is taken by `b`.
+    pub Fifo1<T> {
+        putter a: T;
+        getter b: T;
+        mem m: T;
+        rule { a => m; }
+        rule { m => b; }
+    }
+}
+
+proto! {
+    /// Passes the data of either `a` or `b` to `c`, one at a time.
+    pub Merger<T> {
+        putter a, b: T;
+        getter c: T;
+        rule { a => c; }
+        rule { b => c; }
+    }
+}
+
+proto! {
+    /// Synchronously passes (clones of) the data of `a` to both `b` and `c`.
+    pub Replicator<T> {
+        putter a: T;
+        getter b, c: T;
+        rule { a => b, c; }
+    }
+}
+
+proto! {
+    /// Passes the data of `a` to exactly one of `b` or `c`.
+    pub Router<T> {
+        putter a: T;
+        getter b, c: T;
+        rule { a => b; }
+        rule { a => c; }
+    }
+}
+
+proto! {
+    /// Consumes the data of `a` and `b`, which put strictly in turns, starting with `a`.
+    pub Sequencer<T> {
+        putter a, b: T;
+        mem turn_a: bool = true;
+        mem turn_b: bool;
+        rule { a => ; turn_a => turn_b; }
+        rule { b => ; turn_b => turn_a; }
+    }
+}
+
+proto! {
+    /// Takes the data of `a` and `b` synchronously, passing them to `c` in that order.
+    pub Alternator<T> {
+        putter a, b: T;
+        getter c: T;
+        mem m: T;
+        rule { a => c; b => m; }
+        rule { m => c; }
+    }
+}
+
+/// Buffers up to `N` data put by `a` until they are taken by `b`, in order of arrival.
+/// Locations 0 and 1 are the ports `a` and `b`. The buffer consists of the memory
+/// cells that follow them, through which data flows in ascending order.
+pub struct FifoN<T: 'static, const N: usize> {
+    phantom: std::marker::PhantomData<(T,)>,
+}
+impl<T: 'static, const N: usize> FifoN<T, N> {
+    fn def() -> TypelessProtoDef {
+        let a_to_b = (0..=N).map(|i| {
+            let putter = if i == 0 { 0 } else { i + 1 };
+            let getter = if i == N { 1 } else { i + 2 };
+            rule![Formula::True; putter => getter]
+        });
+        let mems = (2..N + 2).map(|id| (id, LocKind::MemUninitialized));
+        TypelessProtoDef {
+            behaviour: BehaviourDef {
+                rules: a_to_b.collect(),
+            },
+            loc_kinds: vec![(0, LocKind::PortPutter), (1, LocKind::PortGetter)]
+                .into_iter()
+                .chain(mems)
+                .collect(),
+            loc_names: map! {
+                0 => "a".to_owned(),
+                1 => "b".to_owned(),
+            },
+            families: Default::default(),
+        }
+    }
+}
+impl<T: 'static, const N: usize> Proto for FifoN<T, N> {
+    fn typeless_proto_def() -> &'static TypelessProtoDef {
+        // unlike the other primitives, the definition depends on a parameter
+        lazy_static::lazy_static! {
+            static ref DEFS: Mutex<HashMap<usize, &'static TypelessProtoDef>> = Default::default();
+        }
+        DEFS.lock()
+            .entry(N)
+            .or_insert_with(|| Box::leak(Box::new(Self::def())))
+    }
+    fn fill_memory(_loc_id: LocId, _p: MemFillPromise) -> Option<PromiseFulfilled> {
+        None
+    }
+    fn def_func(_name: &'static str, _p: FuncDefPromise) -> Option<PromiseFulfilled> {
+        None
+    }
+    fn loc_type(loc_id: LocId) -> Option<TypeInfo> {
+        if loc_id < N + 2 {
+            Some(TypeInfo::new::<T>())
+        } else {
+            None
+        }
+    }
+    type Interface = (Putter<T>, Getter<T>);
+    fn instantiate_and_claim() -> Self::Interface {
+        let p = Self::instantiate();
+        putters_getters![p => 0, 1]
+    }
+}
+
+/// Decides which data are let through by a `Filter`.
+pub trait FilterPredicate<T> {
+    fn accepts(datum: &T) -> bool;
+}
+
+/// Passes the data of `a` to `b` if they satisfy the predicate `P`, and loses them otherwise.
+pub struct Filter<T: 'static, P: FilterPredicate<T> + 'static> {
+    phantom: std::marker::PhantomData<(T, P)>,
+}
+fn filter_accepts<T, P: FilterPredicate<T>>(r: &mut MaybeUninit<bool>, datum: *const T) {
+    *r = MaybeUninit::new(P::accepts(unsafe { &*datum }));
+}
+impl<T: 'static, P: FilterPredicate<T> + 'static> Proto for Filter<T, P> {
+    fn typeless_proto_def() -> &'static TypelessProtoDef {
+        lazy_static::lazy_static! {
+            static ref DEF: TypelessProtoDef = {
+                let accepts = Formula::FuncDeclaration {
+                    name: "accepts",
+                    args: vec![Term::Value(0)],
+                };
+                TypelessProtoDef {
+                    behaviour: BehaviourDef {
+                        rules: vec![
+                            rule![accepts.clone(); 0=>1],
+                            rule![Formula::None(vec![accepts]); 0=>],
+                        ]
+                    },
+                    loc_kinds: map! {
+                        0 => LocKind::PortPutter,
+                        1 => LocKind::PortGetter,
+                    },
+                    loc_names: map! {
+                        0 => "a".to_owned(),
+                        1 => "b".to_owned(),
+                    },
+                    families: Default::default(),
+                }
+            };
+        }
+        &DEF
+    }
+    fn fill_memory(_loc_id: LocId, _p: MemFillPromise) -> Option<PromiseFulfilled> {
+        None
+    }
+    fn def_func(name: &'static str, p: FuncDefPromise) -> Option<PromiseFulfilled> {
+        match name {
+            "accepts" => Some(p.define_arity1(filter_accepts::<T, P>)),
+            _ => None,
+        }
+    }
+    fn loc_type(loc_id: LocId) -> Option<TypeInfo> {
+        Some(match loc_id {
+            0..=1 => TypeInfo::new::<T>(),
+            _ => return None,
+        })
+    }
+    type Interface = (Putter<T>, Getter<T>);
+    fn instantiate_and_claim() -> Self::Interface {
+        let p = Self::instantiate();
+        putters_getters![p => 0, 1]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::mpsc::{channel, RecvTimeoutError},
+        time::Duration,
+    };
+
+    // long enough for a blocked put to have been wrongly released
+    const PATIENCE: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn sync() {
+        let (mut a, mut b) = SyncChannel::<u32>::instantiate_and_claim();
+        crossbeam::scope(|s| {
+            s.spawn(move |_| (0..5).for_each(|i| assert!(a.put(i).is_none())));
+            (0..5).for_each(|i| assert_eq!(b.get(), i));
+        })
+        .expect("Crashed!");
+    }
+
+    #[test]
+    fn lossy_sync() {
+        // delivering is preferred over losing, whichever rule selector is used
+        let rules = &LossySync::<u32>::typeless_proto_def().behaviour.rules;
+        assert!(rules[0].priority > rules[1].priority);
+        let (mut a, mut b) = LossySync::<u32>::instantiate_and_claim();
+        // nobody is getting. the datum is lost
+        assert_eq!(a.put(0), Some(0));
+        crossbeam::scope(|s| {
+            let got = s.spawn(move |_| b.get());
+            // lost until the getter is ready
+            let mut i = 1;
+            while a.put(i).is_some() {
+                i += 1;
+            }
+            assert_eq!(got.join().unwrap(), i);
+        })
+        .expect("Crashed!");
+    }
+
+    #[test]
+    fn sync_drain() {
+        let (mut a, mut b) = SyncDrain::<u32>::instantiate_and_claim();
+        let (tx, rx) = channel();
+        crossbeam::scope(|s| {
+            s.spawn(move |_| {
+                assert_eq!(a.put(0), Some(0));
+                tx.send(()).unwrap();
+            });
+            assert_eq!(rx.recv_timeout(PATIENCE), Err(RecvTimeoutError::Timeout));
+            assert_eq!(b.put(1), Some(1));
+            rx.recv().unwrap();
+        })
+        .expect("Crashed!");
+    }
+
+    #[test]
+    fn async_drain() {
+        let (mut a, mut b) = AsyncDrain::<u32>::instantiate_and_claim();
+        assert_eq!(a.put(0), Some(0));
+        assert_eq!(a.put(1), Some(1));
+        assert_eq!(b.put(2), Some(2));
+    }
+
+    #[test]
+    fn fifo1() {
+        let (mut a, mut b) = Fifo1::<u32>::instantiate_and_claim();
+        // buffered without a getter
+        assert!(a.put(0).is_none());
+        let (tx, rx) = channel();
+        crossbeam::scope(|s| {
+            s.spawn(move |_| {
+                assert!(a.put(1).is_none());
+                tx.send(()).unwrap();
+            });
+            // the buffer is full
+            assert_eq!(rx.recv_timeout(PATIENCE), Err(RecvTimeoutError::Timeout));
+            assert_eq!(b.get(), 0);
+            rx.recv().unwrap();
+            assert_eq!(b.get(), 1);
+        })
+        .expect("Crashed!");
+    }
+
+    #[test]
+    fn fifo_n() {
+        let (mut a, mut b) = FifoN::<u32, 3>::instantiate_and_claim();
+        for i in 0..3 {
+            assert!(a.put(i).is_none());
+        }
+        let (tx, rx) = channel();
+        crossbeam::scope(|s| {
+            s.spawn(move |_| {
+                assert!(a.put(3).is_none());
+                tx.send(()).unwrap();
+            });
+            assert_eq!(rx.recv_timeout(PATIENCE), Err(RecvTimeoutError::Timeout));
+            for i in 0..4 {
+                assert_eq!(b.get(), i);
+            }
+            rx.recv().unwrap();
+        })
+        .expect("Crashed!");
+        // definitions are cached per capacity
+        assert_eq!(
+            FifoN::<u32, 0>::typeless_proto_def().behaviour.rules.len(),
+            1
+        );
+        assert_eq!(
+            FifoN::<u8, 3>::typeless_proto_def().behaviour.rules.len(),
+            4
+        );
+    }
+
+    #[test]
+    fn fifo_n_empty() {
+        let (mut a, mut b) = FifoN::<u32, 0>::instantiate_and_claim();
+        crossbeam::scope(|s| {
+            s.spawn(move |_| assert!(a.put(7).is_none()));
+            assert_eq!(b.get(), 7);
+        })
+        .expect("Crashed!");
+    }
+
+    #[test]
+    fn merger() {
+        let (mut a, mut b, mut c) = Merger::<u32>::instantiate_and_claim();
+        crossbeam::scope(|s| {
+            s.spawn(move |_| (0..3).for_each(|i| assert!(a.put(i).is_none())));
+            s.spawn(move |_| (10..13).for_each(|i| assert!(b.put(i).is_none())));
+            let mut got: Vec<u32> = (0..6).map(|_| c.get()).collect();
+            got.sort();
+            assert_eq!(got, vec![0, 1, 2, 10, 11, 12]);
+        })
+        .expect("Crashed!");
+    }
+
+    #[test]
+    fn replicator() {
+        let (mut a, mut b, mut c) = Replicator::<String>::instantiate_and_claim();
+        crossbeam::scope(|s| {
+            s.spawn(move |_| assert!(a.put("hi".to_owned()).is_none()));
+            s.spawn(move |_| assert_eq!(b.get(), "hi"));
+            assert_eq!(c.get(), "hi");
+        })
+        .expect("Crashed!");
+    }
+
+    #[test]
+    fn router() {
+        let (mut a, mut b, mut c) = Router::<u32>::instantiate_and_claim();
+        crossbeam::scope(|s| {
+            // only one getter is ready at a time
+            let got = s.spawn(move |_| b.get());
+            assert!(a.put(0).is_none());
+            assert_eq!(got.join().unwrap(), 0);
+            let got = s.spawn(move |_| c.get());
+            assert!(a.put(1).is_none());
+            assert_eq!(got.join().unwrap(), 1);
+        })
+        .expect("Crashed!");
+    }
+
+    struct Even;
+    impl FilterPredicate<u32> for Even {
+        fn accepts(datum: &u32) -> bool {
+            datum.is_multiple_of(2)
+        }
+    }
+
+    #[test]
+    fn filter() {
+        let (mut a, mut b) = Filter::<u32, Even>::instantiate_and_claim();
+        crossbeam::scope(|s| {
+            s.spawn(move |_| {
+                for i in 1..=4 {
+                    // odd data are lost
+                    assert_eq!(a.put(i).is_some(), !i.is_multiple_of(2));
+                }
+            });
+            assert_eq!(b.get(), 2);
+            assert_eq!(b.get(), 4);
+        })
+        .expect("Crashed!");
+    }
+
+    #[test]
+    fn sequencer() {
+        let (mut a, mut b) = Sequencer::<u32>::instantiate_and_claim();
+        let (tx, rx) = channel();
+        crossbeam::scope(|s| {
+            s.spawn(move |_| {
+                for i in 0..2 {
+                    a.put(i);
+                    tx.send(i).unwrap();
+                }
+            });
+            assert_eq!(rx.recv(), Ok(0));
+            // a must wait for b to take its turn
+            assert_eq!(rx.recv_timeout(PATIENCE), Err(RecvTimeoutError::Timeout));
+            b.put(0);
+            assert_eq!(rx.recv(), Ok(1));
+        })
+        .expect("Crashed!");
+    }
+
+    #[test]
+    fn alternator() {
+        let (mut a, mut b, mut c) = Alternator::<u32>::instantiate_and_claim();
+        crossbeam::scope(|s| {
+            s.spawn(move |_| (0..3).for_each(|i| assert!(a.put(i).is_none())));
+            s.spawn(move |_| (10..13).for_each(|i| assert!(b.put(i).is_none())));
+            for i in 0..3 {
+                assert_eq!(c.get(), i);
+                assert_eq!(c.get(), i + 10);
+            }
+        })
+        .expect("Crashed!");
+    }
+}
@@ -0,0 +1,598 @@
+use super::*;
+use crate::proto::{
+    definition::RuleDef,
+    parse::{parse_proto, ParseError, ParsedProto},
+};
+use std::{fmt, fmt::Write as _, path::Path};
+
+/// Generates the source of a Rust module that defines a `Proto` implementor for
+/// the given protocol, so that it need not be written by hand:
+/// ```ignore
+/// // build.rs
+/// reo_rs::proto::codegen::generate_file("fifo.reo", out_dir.join("fifo_proto.rs"), &Default::default())?;
+/// // lib.rs
+/// include!(concat!(env!("OUT_DIR"), "/fifo_proto.rs"));
+/// let (a, b) = Fifo::<u32>::instantiate_and_claim();
+/// ```
+/// The struct is generic over the protocol's type parameters. Initial memory values
+/// are parsed with `Parsable` when the protocol is instantiated. Every function used
+/// in a guard or transform is expected as a `fn` of the same name in the module at
+/// `CodegenOptions::funcs_path`, with the signature `define_arityN` requires.
+pub fn generate(p: &ParsedProto, options: &CodegenOptions) -> String {
+    let mut g = Generator {
+        p,
+        options,
+        out: String::new(),
+    };
+    g.write_all().expect("writing to a String failed");
+    g.out
+}
+
+/// Reads a protocol in the textual format of `parse_proto` from `src`, and writes
+/// the output of `generate` to `dest`.
+pub fn generate_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dest: Q,
+    options: &CodegenOptions,
+) -> Result<(), CodegenError> {
+    let text = std::fs::read_to_string(src).map_err(|e| CodegenError::Io(e.to_string()))?;
+    let parsed = parse_proto(&text).map_err(CodegenError::Parse)?;
+    std::fs::write(dest, generate(&parsed, options)).map_err(|e| CodegenError::Io(e.to_string()))
+}
+
+#[derive(Debug, Clone)]
+pub struct CodegenOptions {
+    /// Path of the crate providing the runtime, as seen from the generated module.
+    pub reo_path: String,
+    /// Path of the module defining the functions used by the protocol.
+    pub funcs_path: String,
+}
+impl Default for CodegenOptions {
+    fn default() -> Self {
+        Self {
+            reo_path: "reo_rs".into(),
+            funcs_path: "super".into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodegenError {
+    Io(String),
+    Parse(ParseError),
+}
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodegenError::Io(e) => write!(f, "io error: {}", e),
+            CodegenError::Parse(e) => write!(f, "parse error: {}", e),
+        }
+    }
+}
+impl std::error::Error for CodegenError {}
+
+struct Generator<'a> {
+    p: &'a ParsedProto,
+    options: &'a CodegenOptions,
+    out: String,
+}
+impl Generator<'_> {
+    fn write_all(&mut self) -> fmt::Result {
+        let reo = &self.options.reo_path;
+        writeln!(
+            self.out,
+            "// Generated by {}::proto::codegen from protocol `{}`. Do not edit.",
+            reo, self.p.name
+        )?;
+        writeln!(self.out, "#[allow(unused_imports)]")?;
+        writeln!(self.out, "use {}::{{", reo)?;
+        writeln!(self.out, "    proto::{{")?;
+        writeln!(self.out, "        definition::{{")?;
+        writeln!(
+            self.out,
+            "            ActionDef, BehaviourDef, Formula, LocKind, RuleDef, Term, TypelessProtoDef,"
+        )?;
+        writeln!(self.out, "        }},")?;
+        writeln!(self.out, "        reflection::TypeInfo,")?;
+        writeln!(self.out, "        traits::{{")?;
+        writeln!(
+            self.out,
+            "            FuncDefPromise, HasUnclaimedPorts, MemFillPromise, Parsable, PromiseFulfilled,"
+        )?;
+        writeln!(self.out, "            Proto,")?;
+        writeln!(self.out, "        }},")?;
+        writeln!(self.out, "        Getter, Putter,")?;
+        writeln!(self.out, "    }},")?;
+        writeln!(self.out, "    LocId,")?;
+        writeln!(self.out, "}};")?;
+        writeln!(self.out)?;
+        self.write_struct()?;
+        self.write_impl()
+    }
+
+    /// Generic parameters as they appear after the struct's name.
+    fn params(&self, bounds: &str) -> String {
+        if self.p.type_params.is_empty() {
+            return String::new();
+        }
+        let ps: Vec<String> = self
+            .p
+            .type_params
+            .iter()
+            .map(|t| format!("{}{}", t, bounds))
+            .collect();
+        format!("<{}>", ps.join(", "))
+    }
+
+    fn loc_ids_sorted(&self) -> Vec<LocId> {
+        let mut ids: Vec<LocId> = self.p.def.loc_kinds.keys().copied().collect();
+        ids.sort();
+        ids
+    }
+
+    fn port_ids(&self) -> Vec<LocId> {
+        self.loc_ids_sorted()
+            .into_iter()
+            .filter(|id| !self.p.def.loc_kinds[id].is_mem())
+            .collect()
+    }
+
+    fn loc_name(&self, id: LocId) -> &str {
+        self.p
+            .loc_ids
+            .iter()
+            .find(|(_, &i)| i == id)
+            .map(|(name, _)| name.as_str())
+            .unwrap_or("?")
+    }
+
+    fn write_struct(&mut self) -> fmt::Result {
+        let name = &self.p.name;
+        writeln!(self.out, "/// Locations of `{}`:", name)?;
+        for id in self.loc_ids_sorted() {
+            let kind = match self.p.def.loc_kinds[&id] {
+                LocKind::PortPutter => "putter",
+                LocKind::PortGetter => "getter",
+                LocKind::MemInitialized | LocKind::MemUninitialized => "mem",
+            };
+            let line = format!(
+                "/// - {}: {} {}: {}",
+                id,
+                kind,
+                self.loc_name(id),
+                self.p.loc_types[&id]
+            );
+            writeln!(self.out, "{}", line)?;
+        }
+        if self.p.type_params.is_empty() {
+            writeln!(self.out, "pub struct {};", name)
+        } else {
+            writeln!(
+                self.out,
+                "pub struct {}{} {{",
+                name,
+                self.params(": 'static")
+            )?;
+            writeln!(
+                self.out,
+                "    phantom: std::marker::PhantomData<({},)>,",
+                self.p.type_params.join(", ")
+            )?;
+            writeln!(self.out, "}}")
+        }
+    }
+
+    fn write_impl(&mut self) -> fmt::Result {
+        // initialized memory of generic types must be parsable
+        let mut parsable_bounds: Vec<&str> = vec![];
+        for (id, _) in self.p.init_values.iter() {
+            let t = self.p.loc_types[id].as_str();
+            if self.mentions_type_param(t) && !parsable_bounds.contains(&t) {
+                parsable_bounds.push(t);
+            }
+        }
+        parsable_bounds.sort();
+        write!(
+            self.out,
+            "impl{} Proto for {}{}",
+            self.params(": 'static"),
+            self.p.name,
+            self.params("")
+        )?;
+        if parsable_bounds.is_empty() {
+            writeln!(self.out, " {{")?;
+        } else {
+            writeln!(self.out, "\nwhere")?;
+            for t in parsable_bounds {
+                writeln!(self.out, "    {}: Parsable,", t)?;
+            }
+            writeln!(self.out, "{{")?;
+        }
+        self.write_def()?;
+        self.write_fill_memory()?;
+        self.write_def_func()?;
+        self.write_loc_type()?;
+        self.write_interface()?;
+        writeln!(self.out, "}}")
+    }
+
+    fn mentions_type_param(&self, t: &str) -> bool {
+        t.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .any(|word| self.p.type_params.iter().any(|p| p == word))
+    }
+
+    fn write_def(&mut self) -> fmt::Result {
+        let reo = &self.options.reo_path;
+        writeln!(
+            self.out,
+            "    fn typeless_proto_def() -> &'static TypelessProtoDef {{"
+        )?;
+        writeln!(self.out, "        {}::lazy_static::lazy_static! {{", reo)?;
+        writeln!(
+            self.out,
+            "            static ref DEF: TypelessProtoDef = TypelessProtoDef {{"
+        )?;
+        writeln!(self.out, "                behaviour: BehaviourDef {{")?;
+        writeln!(self.out, "                    rules: vec![")?;
+        for rule in self.p.def.behaviour.rules.iter() {
+            let rule = rule_expr(rule);
+            writeln!(self.out, "                        {},", rule)?;
+        }
+        writeln!(self.out, "                    ],")?;
+        writeln!(self.out, "                }},")?;
+        writeln!(self.out, "                loc_kinds: vec![")?;
+        for id in self.loc_ids_sorted() {
+            let kind = self.p.def.loc_kinds[&id];
+            writeln!(
+                self.out,
+                "                    ({}, LocKind::{:?}),",
+                id, kind
+            )?;
+        }
+        writeln!(self.out, "                ]")?;
+        writeln!(self.out, "                .into_iter()")?;
+        writeln!(self.out, "                .collect(),")?;
+        writeln!(self.out, "                loc_names: vec![")?;
+        for id in self.loc_ids_sorted() {
+            if let Some(name) = self.p.def.loc_names.get(&id) {
+                writeln!(self.out, "                    ({}, {:?}),", id, name)?;
+            }
+        }
+        writeln!(self.out, "                ]")?;
+        writeln!(self.out, "                .into_iter()")?;
+        writeln!(
+            self.out,
+            "                .map(|(id, name)| (id, String::from(name)))"
+        )?;
+        writeln!(self.out, "                .collect(),")?;
+        if self.p.def.families.is_empty() {
+            writeln!(self.out, "                families: Default::default(),")?;
+        } else {
+            let mut families: Vec<&String> = self.p.def.families.iter().collect();
+            families.sort();
+            writeln!(self.out, "                families: vec!{:?}", families)?;
+            writeln!(self.out, "                .into_iter()")?;
+            writeln!(self.out, "                .map(String::from)")?;
+            writeln!(self.out, "                .collect(),")?;
+        }
+        writeln!(self.out, "            }};")?;
+        writeln!(self.out, "        }}")?;
+        writeln!(self.out, "        &DEF")?;
+        writeln!(self.out, "    }}")
+    }
+
+    fn write_fill_memory(&mut self) -> fmt::Result {
+        let mut inits: Vec<(&LocId, &String)> = self.p.init_values.iter().collect();
+        inits.sort();
+        if inits.is_empty() {
+            writeln!(
+                self.out,
+                "    fn fill_memory(_loc_id: LocId, _p: MemFillPromise) -> Option<PromiseFulfilled> {{"
+            )?;
+            writeln!(self.out, "        None")?;
+            return writeln!(self.out, "    }}");
+        }
+        writeln!(
+            self.out,
+            "    fn fill_memory(loc_id: LocId, p: MemFillPromise) -> Option<PromiseFulfilled> {{"
+        )?;
+        writeln!(self.out, "        match loc_id {{")?;
+        for (id, value) in inits {
+            let t = &self.p.loc_types[id];
+            writeln!(
+                self.out,
+                "            {} => p.fill_memory(<{} as Parsable>::try_parse({:?})?).ok(),",
+                id, t, value
+            )?;
+        }
+        writeln!(self.out, "            _ => None,")?;
+        writeln!(self.out, "        }}")?;
+        writeln!(self.out, "    }}")
+    }
+
+    /// Names of the functions used by the protocol with the arity of their first use.
+    fn funcs(&self) -> Vec<(Name, usize)> {
+        fn visit(f: &Formula, funcs: &mut Vec<(Name, usize)>) {
+            use Formula::*;
+            let term = |t: &Term, funcs: &mut Vec<(Name, usize)>| {
+                if let Term::Boolean(f) = t {
+                    visit(f, funcs)
+                }
+            };
+            match f {
+                And(fs) | Or(fs) | None(fs) => fs.iter().for_each(|f| visit(f, funcs)),
+                Not(f) => visit(f, funcs),
+                ValueEq(a, b) | Lt(a, b) | Le(a, b) | Gt(a, b) | Ge(a, b) => {
+                    term(a, funcs);
+                    term(b, funcs);
+                }
+                TermVal(t) => term(t, funcs),
+                FuncDeclaration { name, args } => {
+                    args.iter().for_each(|t| term(t, funcs));
+                    if funcs.iter().all(|(n, _)| n != name) {
+                        funcs.push((name, args.len()));
+                    }
+                }
+                True | False | MemIsNull(_) => (),
+            }
+        }
+        let mut funcs = vec![];
+        for rule in self.p.def.behaviour.rules.iter() {
+            visit(&rule.guard, &mut funcs);
+            for name in rule.actions.iter().filter_map(|a| a.transform) {
+                if funcs.iter().all(|(n, _)| *n != name) {
+                    funcs.push((name, 1));
+                }
+            }
+        }
+        funcs
+    }
+
+    fn write_def_func(&mut self) -> fmt::Result {
+        let funcs = self.funcs();
+        if funcs.is_empty() {
+            writeln!(
+                self.out,
+                "    fn def_func(_name: &'static str, _p: FuncDefPromise) -> Option<PromiseFulfilled> {{"
+            )?;
+            writeln!(self.out, "        None")?;
+            return writeln!(self.out, "    }}");
+        }
+        writeln!(
+            self.out,
+            "    fn def_func(name: &'static str, p: FuncDefPromise) -> Option<PromiseFulfilled> {{"
+        )?;
+        writeln!(self.out, "        match name {{")?;
+        for (name, arity) in funcs {
+            writeln!(
+                self.out,
+                "            {:?} => Some(p.define_arity{}({}::{})),",
+                name, arity, self.options.funcs_path, name
+            )?;
+        }
+        writeln!(self.out, "            _ => None,")?;
+        writeln!(self.out, "        }}")?;
+        writeln!(self.out, "    }}")
+    }
+
+    fn write_loc_type(&mut self) -> fmt::Result {
+        // group locations by type, in order of their first location
+        let mut groups: Vec<(&str, Vec<LocId>)> = vec![];
+        for id in self.loc_ids_sorted() {
+            let t = self.p.loc_types[&id].as_str();
+            match groups.iter_mut().find(|(t2, _)| *t2 == t) {
+                Some((_, ids)) => ids.push(id),
+                Option::None => groups.push((t, vec![id])),
+            }
+        }
+        writeln!(
+            self.out,
+            "    fn loc_type(loc_id: LocId) -> Option<TypeInfo> {{"
+        )?;
+        writeln!(self.out, "        Some(match loc_id {{")?;
+        for (t, ids) in groups {
+            let pattern: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+            writeln!(
+                self.out,
+                "            {} => TypeInfo::new::<{}>(),",
+                pattern.join(" | "),
+                t
+            )?;
+        }
+        writeln!(self.out, "            _ => return None,")?;
+        writeln!(self.out, "        }})")?;
+        writeln!(self.out, "    }}")
+    }
+
+    fn write_interface(&mut self) -> fmt::Result {
+        let ports = self.port_ids();
+        let (types, names): (Vec<String>, Vec<&str>) = ports
+            .iter()
+            .map(|id| {
+                let role = match self.p.def.loc_kinds[id] {
+                    LocKind::PortPutter => "Putter",
+                    _ => "Getter",
+                };
+                let t = format!("{}<{}>", role, self.p.loc_types[id]);
+                (t, self.loc_name(*id))
+            })
+            .unzip();
+        let ids: Vec<String> = ports.iter().map(|id| id.to_string()).collect();
+        let tuple = |xs: &[String]| match xs.len() {
+            1 => xs[0].clone(),
+            _ => format!("({})", xs.join(", ")),
+        };
+        writeln!(self.out, "    /// ports ({})", names.join(", "))?;
+        writeln!(self.out, "    type Interface = {};", tuple(&types))?;
+        writeln!(
+            self.out,
+            "    fn instantiate_and_claim() -> Self::Interface {{"
+        )?;
+        if ports.is_empty() {
+            writeln!(self.out, "        let _ = Self::instantiate();")?;
+            writeln!(self.out, "    }}")
+        } else {
+            writeln!(self.out, "        let p = Self::instantiate();")?;
+            writeln!(
+                self.out,
+                "        {}::putters_getters![p => {}]",
+                self.options.reo_path,
+                ids.join(",")
+            )?;
+            writeln!(self.out, "    }}")
+        }
+    }
+}
+
+fn rule_expr(rule: &RuleDef) -> String {
+    let actions: Vec<String> = rule
+        .actions
+        .iter()
+        .map(|a| {
+            let getters: Vec<String> = a.getters.iter().map(|g| g.to_string()).collect();
+            let transform = match a.transform {
+                Some(name) => format!("Some({:?})", name),
+                Option::None => "None".to_string(),
+            };
+            format!(
+                "ActionDef {{ putter: {}, getters: vec![{}], transform: {} }}",
+                a.putter,
+                getters.join(", "),
+                transform
+            )
+        })
+        .collect();
+    format!(
+        "RuleDef {{ guard: {}, actions: vec![{}], priority: {} }}",
+        formula_expr(&rule.guard),
+        actions.join(", "),
+        rule.priority
+    )
+}
+
+fn formula_expr(f: &Formula) -> String {
+    use Formula::*;
+    let fs = |fs: &[Formula]| {
+        let fs: Vec<String> = fs.iter().map(formula_expr).collect();
+        format!("vec![{}]", fs.join(", "))
+    };
+    let pair = |op: &str, a: &Term, b: &Term| {
+        format!("Formula::{}({}, {})", op, term_expr(a), term_expr(b))
+    };
+    match f {
+        True => "Formula::True".into(),
+        False => "Formula::False".into(),
+        And(x) => format!("Formula::And({})", fs(x)),
+        Or(x) => format!("Formula::Or({})", fs(x)),
+        None(x) => format!("Formula::None({})", fs(x)),
+        Not(x) => format!("Formula::Not(Box::new({}))", formula_expr(x)),
+        ValueEq(a, b) => pair("ValueEq", a, b),
+        Lt(a, b) => pair("Lt", a, b),
+        Le(a, b) => pair("Le", a, b),
+        Gt(a, b) => pair("Gt", a, b),
+        Ge(a, b) => pair("Ge", a, b),
+        MemIsNull(id) => format!("Formula::MemIsNull({})", id),
+        TermVal(t) => format!("Formula::TermVal({})", term_expr(t)),
+        FuncDeclaration { name, args } => {
+            let args: Vec<String> = args.iter().map(term_expr).collect();
+            format!(
+                "Formula::FuncDeclaration {{ name: {:?}, args: vec![{}] }}",
+                name,
+                args.join(", ")
+            )
+        }
+    }
+}
+
+fn term_expr(t: &Term) -> String {
+    match t {
+        Term::Boolean(f) => format!("Term::Boolean(Box::new({}))", formula_expr(f)),
+        Term::Value(id) => format!("Term::Value({})", id),
+        Term::Const(c) => format!("Term::Const({:?}.to_owned())", c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::{traits::Proto, Getter, Putter};
+    use std::mem::MaybeUninit;
+
+    const SAMPLER: &str = "
+        protocol Sampler<T> {
+            putter a: T;
+            putter n: u32;
+            getter b: T;
+            getter c: String;
+            mem m: T;
+            mem k: u32 = 3;
+            rule null(m) { a => m; }
+            rule n < k & is_small(n) { m => b; to_string(n) => c; }
+            rule !(n < k) { n => ; }
+        }";
+
+    fn is_small(r: &mut MaybeUninit<bool>, x: *const u32) {
+        *r = MaybeUninit::new(unsafe { *x } < 100);
+    }
+    fn to_string(r: &mut MaybeUninit<String>, x: *const u32) {
+        *r = MaybeUninit::new(unsafe { *x }.to_string());
+    }
+
+    fn options() -> CodegenOptions {
+        CodegenOptions {
+            reo_path: "crate".into(),
+            funcs_path: "super".into(),
+        }
+    }
+
+    // the output of `generate` for SAMPLER. kept in the tree so that it is compiled.
+    mod sampler {
+        include!("codegen/sampler_proto.rs");
+    }
+
+    #[test]
+    fn generated_matches_checked_in() {
+        let generated = generate(&parse_proto(SAMPLER).unwrap(), &options());
+        assert_eq!(generated, include_str!("codegen/sampler_proto.rs"));
+    }
+
+    #[test]
+    fn generated_runs() {
+        let (mut a, mut n, mut b, mut c): (
+            Putter<char>,
+            Putter<u32>,
+            Getter<char>,
+            Getter<String>,
+        ) = sampler::Sampler::<char>::instantiate_and_claim();
+        crossbeam::scope(|s| {
+            s.spawn(move |_| {
+                a.put('x');
+                a.put('y');
+            });
+            s.spawn(move |_| {
+                // 5 is not below k, so it is discarded
+                n.put(5);
+                n.put(1);
+                n.put(2);
+            });
+            s.spawn(move |_| assert_eq!((b.get(), b.get()), ('x', 'y')));
+            s.spawn(move |_| assert_eq!((c.get(), c.get()), ("1".into(), "2".into())));
+        })
+        .expect("Crashed!");
+    }
+
+    #[test]
+    fn generate_without_type_params() {
+        let p = parse_proto(
+            "protocol Fifo { putter a: u32; getter b: u32; mem m: u32 = 7;
+            rule true { a => m; } rule true { m => b; } }",
+        )
+        .unwrap();
+        let s = generate(&p, &Default::default());
+        assert!(s.contains("pub struct Fifo;"));
+        assert!(s.contains("impl Proto for Fifo {"));
+        assert!(s.contains("2 => p.fill_memory(<u32 as Parsable>::try_parse(\"7\")?).ok(),"));
+        assert!(s.contains("type Interface = (Putter<u32>, Getter<u32>);"));
+        assert!(s.contains("reo_rs::putters_getters![p => 0,1]"));
+    }
+}
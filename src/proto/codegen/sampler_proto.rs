@@ -0,0 +1,92 @@
+// Generated by crate::proto::codegen from protocol `Sampler`. Do not edit.
+#[allow(unused_imports)]
+use crate::{
+    proto::{
+        definition::{
+            ActionDef, BehaviourDef, Formula, LocKind, RuleDef, Term, TypelessProtoDef,
+        },
+        reflection::TypeInfo,
+        traits::{
+            FuncDefPromise, HasUnclaimedPorts, MemFillPromise, Parsable, PromiseFulfilled,
+            Proto,
+        },
+        Getter, Putter,
+    },
+    LocId,
+};
+
+/// Locations of `Sampler`:
+/// - 0: putter a: T
+/// - 1: putter n: u32
+/// - 2: getter b: T
+/// - 3: getter c: String
+/// - 4: mem m: T
+/// - 5: mem k: u32
+pub struct Sampler<T: 'static> {
+    phantom: std::marker::PhantomData<(T,)>,
+}
+impl<T: 'static> Proto for Sampler<T> {
+    fn typeless_proto_def() -> &'static TypelessProtoDef {
+        crate::lazy_static::lazy_static! {
+            static ref DEF: TypelessProtoDef = TypelessProtoDef {
+                behaviour: BehaviourDef {
+                    rules: vec![
+                        RuleDef { guard: Formula::MemIsNull(4), actions: vec![ActionDef { putter: 0, getters: vec![4], transform: None }], priority: 0 },
+                        RuleDef { guard: Formula::And(vec![Formula::Lt(Term::Value(1), Term::Value(5)), Formula::FuncDeclaration { name: "is_small", args: vec![Term::Value(1)] }]), actions: vec![ActionDef { putter: 4, getters: vec![2], transform: None }, ActionDef { putter: 1, getters: vec![3], transform: Some("to_string") }], priority: 0 },
+                        RuleDef { guard: Formula::Not(Box::new(Formula::Lt(Term::Value(1), Term::Value(5)))), actions: vec![ActionDef { putter: 1, getters: vec![], transform: None }], priority: 0 },
+                    ],
+                },
+                loc_kinds: vec![
+                    (0, LocKind::PortPutter),
+                    (1, LocKind::PortPutter),
+                    (2, LocKind::PortGetter),
+                    (3, LocKind::PortGetter),
+                    (4, LocKind::MemUninitialized),
+                    (5, LocKind::MemInitialized),
+                ]
+                .into_iter()
+                .collect(),
+                loc_names: vec![
+                    (0, "a"),
+                    (1, "n"),
+                    (2, "b"),
+                    (3, "c"),
+                    (4, "m"),
+                    (5, "k"),
+                ]
+                .into_iter()
+                .map(|(id, name)| (id, String::from(name)))
+                .collect(),
+                families: Default::default(),
+            };
+        }
+        &DEF
+    }
+    fn fill_memory(loc_id: LocId, p: MemFillPromise) -> Option<PromiseFulfilled> {
+        match loc_id {
+            5 => p.fill_memory(<u32 as Parsable>::try_parse("3")?).ok(),
+            _ => None,
+        }
+    }
+    fn def_func(name: &'static str, p: FuncDefPromise) -> Option<PromiseFulfilled> {
+        match name {
+            "is_small" => Some(p.define_arity1(super::is_small)),
+            "to_string" => Some(p.define_arity1(super::to_string)),
+            _ => None,
+        }
+    }
+    fn loc_type(loc_id: LocId) -> Option<TypeInfo> {
+        Some(match loc_id {
+            0 | 2 | 4 => TypeInfo::new::<T>(),
+            1 | 5 => TypeInfo::new::<u32>(),
+            3 => TypeInfo::new::<String>(),
+            _ => return None,
+        })
+    }
+    /// ports (a, n, b, c)
+    type Interface = (Putter<T>, Putter<u32>, Getter<T>, Getter<String>);
+    fn instantiate_and_claim() -> Self::Interface {
+        let p = Self::instantiate();
+        crate::putters_getters![p => 0,1,2,3]
+    }
+}
@@ -0,0 +1,483 @@
+use super::*;
+use crate::proto::definition::{ActionDef, BehaviourDef, RuleDef};
+use hashbrown::HashSet;
+
+/// The result of composing two protocol definitions. The locations of both
+/// operands are renumbered densely; `a_ids` and `b_ids` map the LocIds of each
+/// operand to their LocIds in `def`. Glued (hidden) ports do not appear in these maps.
+#[derive(Debug, Clone)]
+pub struct Composition {
+    pub def: TypelessProtoDef,
+    pub a_ids: HashMap<LocId, LocId>,
+    pub b_ids: HashMap<LocId, LocId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ComposeError {
+    /// The operand has no location with this name.
+    UnknownName {
+        name: String,
+    },
+    /// A rule of an operand involves this location, which the operand does not declare.
+    UnknownLoc {
+        loc_id: LocId,
+    },
+    NotAGetter {
+        loc_id: LocId,
+    },
+    NotAPutter {
+        loc_id: LocId,
+    },
+    GluedTwice {
+        loc_id: LocId,
+    },
+    /// The datum passing through this glued getter is transformed in a way that
+    /// cannot be expressed by a single action, or is observed by a guard.
+    UnsupportedTransform {
+        loc_id: LocId,
+    },
+    /// A guard refers to this glued port, though its rule involves it in no action.
+    /// Hidden ports have no value of their own to observe.
+    GuardOnHiddenLoc {
+        loc_id: LocId,
+    },
+}
+
+/// Composes protocol definitions `a` and `b` into one. Each `(getter, putter)` pair of names
+/// in `glue` identifies the getter port of `a` with the putter port of `b` so named, such
+/// that data flows through them synchronously. The glued ports are hidden in the result.
+///
+/// Rules of `a` that get from no glued port, and rules of `b` that put to no glued port
+/// are kept as they are. Every other rule of `a` is synchronised with each rule of `b` that
+/// puts to exactly the glued ports it gets from. In the resulting rule, data flows
+/// directly from the putter in `a` to the getters in `b`. Rules with no such partner
+/// can never fire and are discarded. A transform on either side of a glued port is
+/// kept, unless doing so would change what some other getter or guard observes.
+/// Synchronised rules take the sum of their priorities.
+pub fn compose(
+    a: &TypelessProtoDef,
+    b: &TypelessProtoDef,
+    glue: &[(&str, &str)],
+) -> Result<Composition, ComposeError> {
+    use ComposeError::*;
+    declares_all(a)?;
+    declares_all(b)?;
+    let glue = glue
+        .iter()
+        .map(|&(g, p)| Ok((loc_named(a, g)?, loc_named(b, p)?)))
+        .collect::<Result<Vec<(LocId, LocId)>, ComposeError>>()?;
+    let mut glued_getters: HashMap<LocId, usize> = Default::default();
+    let mut glued_putters: HashMap<LocId, usize> = Default::default();
+    for (i, &(g, p)) in glue.iter().enumerate() {
+        if a.loc_kinds.get(&g) != Some(&LocKind::PortGetter) {
+            return Err(NotAGetter { loc_id: g });
+        }
+        if b.loc_kinds.get(&p) != Some(&LocKind::PortPutter) {
+            return Err(NotAPutter { loc_id: p });
+        }
+        if glued_getters.insert(g, i).is_some() {
+            return Err(GluedTwice { loc_id: g });
+        }
+        if glued_putters.insert(p, i).is_some() {
+            return Err(GluedTwice { loc_id: p });
+        }
+    }
+
+    // renumber the remaining locations. those of `a` precede those of `b`.
+    // names are kept, unless already taken by a location of `a`
+    let mut loc_kinds = HashMap::default();
+    let mut loc_names: HashMap<LocId, String> = HashMap::default();
+    let mut names_taken: HashSet<String> = HashSet::default();
+    let mut renumber = |def: &TypelessProtoDef, hidden: &HashMap<LocId, usize>| {
+        let mut ids: Vec<LocId> = def
+            .loc_kinds
+            .keys()
+            .copied()
+            .filter(|id| !hidden.contains_key(id))
+            .collect();
+        ids.sort();
+        ids.into_iter()
+            .map(|id| {
+                let new_id = loc_kinds.len();
+                loc_kinds.insert(new_id, def.loc_kinds[&id]);
+                if let Some(name) = def.loc_names.get(&id) {
+                    if names_taken.insert(name.clone()) {
+                        loc_names.insert(new_id, name.clone());
+                    }
+                }
+                (id, new_id)
+            })
+            .collect::<HashMap<LocId, LocId>>()
+    };
+    let a_ids = renumber(a, &glued_getters);
+    let b_ids = renumber(b, &glued_putters);
+
+    // for each rule, the set of glued ports it involves
+    let glued_in = |rule: &RuleDef, putter_side: bool| -> BitSet {
+        let mut set = BitSet::default();
+        for action in rule.actions.iter() {
+            if putter_side {
+                if let Some(&i) = glued_putters.get(&action.putter) {
+                    set.set_to(i, true);
+                }
+            } else {
+                for g in action.getters.iter() {
+                    if let Some(&i) = glued_getters.get(g) {
+                        set.set_to(i, true);
+                    }
+                }
+            }
+        }
+        set.strip_trailing_zeroes();
+        set
+    };
+    let a_glued: Vec<BitSet> = a
+        .behaviour
+        .rules
+        .iter()
+        .map(|r| glued_in(r, false))
+        .collect();
+    let b_glued: Vec<BitSet> = b
+        .behaviour
+        .rules
+        .iter()
+        .map(|r| glued_in(r, true))
+        .collect();
+
+    let mut rules = vec![];
+    for (ra, ga) in a.behaviour.rules.iter().zip(a_glued.iter()) {
+        if ga.is_empty() {
+            guard_avoids(ra, &glued_getters)?;
+            rules.push(ra.map_loc_ids(&|id| a_ids[&id]));
+            continue;
+        }
+        for (rb, gb) in b.behaviour.rules.iter().zip(b_glued.iter()) {
+            if gb.data == ga.data {
+                rules.push(synchronise(ra, rb, &glue, &a_ids, &b_ids)?);
+            }
+        }
+    }
+    for (rb, gb) in b.behaviour.rules.iter().zip(b_glued.iter()) {
+        if gb.is_empty() {
+            guard_avoids(rb, &glued_putters)?;
+            rules.push(rb.map_loc_ids(&|id| b_ids[&id]));
+        }
+    }
+    Ok(Composition {
+        def: TypelessProtoDef {
+            behaviour: BehaviourDef { rules },
+            loc_kinds,
+            loc_names,
+            families: a.families.union(&b.families).cloned().collect(),
+        },
+        a_ids,
+        b_ids,
+    })
+}
+
+fn loc_named(def: &TypelessProtoDef, name: &str) -> Result<LocId, ComposeError> {
+    def.loc_names
+        .iter()
+        .find(|(_, n)| n.as_str() == name)
+        .map(|(&id, _)| id)
+        .ok_or_else(|| ComposeError::UnknownName {
+            name: name.to_owned(),
+        })
+}
+
+/// Fails if a rule of `def` involves a location that `def` does not declare.
+fn declares_all(def: &TypelessProtoDef) -> Result<(), ComposeError> {
+    for rule in def.behaviour.rules.iter() {
+        let mut ids = vec![];
+        rule.guard.visit_loc_ids(&mut |id| ids.push(id));
+        for action in rule.actions.iter() {
+            ids.push(action.putter);
+            ids.extend(action.getters.iter().copied());
+        }
+        if let Some(&loc_id) = ids.iter().find(|id| !def.loc_kinds.contains_key(id)) {
+            return Err(ComposeError::UnknownLoc { loc_id });
+        }
+    }
+    Ok(())
+}
+
+/// Fails if the guard of `rule` refers to one of the `hidden` locations.
+fn guard_avoids(rule: &RuleDef, hidden: &HashMap<LocId, usize>) -> Result<(), ComposeError> {
+    let mut err = None;
+    rule.guard.visit_loc_ids(&mut |id| {
+        if hidden.contains_key(&id) {
+            err = Some(ComposeError::GuardOnHiddenLoc { loc_id: id });
+        }
+    });
+    err.map_or(Ok(()), Err)
+}
+
+fn synchronise(
+    ra: &RuleDef,
+    rb: &RuleDef,
+    glue: &[(LocId, LocId)],
+    a_ids: &HashMap<LocId, LocId>,
+    b_ids: &HashMap<LocId, LocId>,
+) -> Result<RuleDef, ComposeError> {
+    // the (renumbered) putter in `a` that feeds each glued port
+    let mut sources: HashMap<usize, LocId> = Default::default();
+    let mut transformed: HashSet<usize> = Default::default();
+    let mut actions: Vec<ActionDef> = vec![];
+    for action in ra.actions.iter() {
+        let mut getters = vec![];
+        for &g in action.getters.iter() {
+            match glue.iter().position(|&(glued, _)| glued == g) {
+                Some(i) => {
+                    sources.insert(i, a_ids[&action.putter]);
+                    if action.transform.is_some() {
+                        transformed.insert(i);
+                    }
+                }
+                None => getters.push(a_ids[&g]),
+            }
+        }
+        actions.push(ActionDef {
+            putter: a_ids[&action.putter],
+            getters,
+            transform: action.transform,
+        });
+    }
+    for action in rb.actions.iter() {
+        let getters = action.getters.iter().map(|g| b_ids[g]);
+        match glue.iter().position(|&(_, glued)| glued == action.putter) {
+            Some(i) => {
+                let source = sources[&i];
+                let feeding = actions
+                    .iter_mut()
+                    .find(|a| a.putter == source)
+                    .expect("no feeding action");
+                if action.transform.is_some() {
+                    // only expressible if nobody else observes the untransformed datum
+                    if feeding.transform.is_some() || !feeding.getters.is_empty() {
+                        return Err(ComposeError::UnsupportedTransform { loc_id: glue[i].0 });
+                    }
+                    feeding.transform = action.transform;
+                    transformed.insert(i);
+                }
+                feeding.getters.extend(getters);
+            }
+            None => actions.push(ActionDef {
+                putter: b_ids[&action.putter],
+                getters: getters.collect(),
+                transform: action.transform,
+            }),
+        }
+    }
+    // values of glued ports are those of their sources. transformed
+    // values have no location, so guards cannot refer to them
+    let mut guard_err = None;
+    let mut check = |i: usize, id: LocId| {
+        if !sources.contains_key(&i) {
+            guard_err = Some(ComposeError::GuardOnHiddenLoc { loc_id: id });
+        } else if transformed.contains(&i) {
+            guard_err = Some(ComposeError::UnsupportedTransform { loc_id: glue[i].0 });
+        }
+    };
+    ra.guard.visit_loc_ids(&mut |id| {
+        if let Some(i) = glue.iter().position(|&(g, _)| g == id) {
+            check(i, id)
+        }
+    });
+    rb.guard.visit_loc_ids(&mut |id| {
+        if let Some(i) = glue.iter().position(|&(_, p)| p == id) {
+            check(i, id)
+        }
+    });
+    if let Some(e) = guard_err {
+        return Err(e);
+    }
+    let source_of = |i: Option<usize>| i.map(|i| sources[&i]);
+    let guard_a = ra.guard.map_loc_ids(&|id| {
+        source_of(glue.iter().position(|&(g, _)| g == id)).unwrap_or_else(|| a_ids[&id])
+    });
+    let guard_b = rb.guard.map_loc_ids(&|id| {
+        source_of(glue.iter().position(|&(_, p)| p == id)).unwrap_or_else(|| b_ids[&id])
+    });
+    let guard = match (guard_a, guard_b) {
+        (Formula::True, g) | (g, Formula::True) => g,
+        (ga, gb) => Formula::And(vec![ga, gb]),
+    };
+    Ok(RuleDef {
+        guard,
+        actions,
+        priority: ra.priority + rb.priority,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::{definition::DynProtoBuilder, parse::parse_proto};
+
+    fn def(src: &str) -> TypelessProtoDef {
+        parse_proto(src).unwrap().def
+    }
+
+    #[test]
+    fn compose_syncs() {
+        let sync = def("protocol S { putter a: T; getter b: T; rule true { a => b; } }");
+        let c = compose(&sync, &sync, &[("b", "a")]).unwrap();
+        assert_eq!(c.def.loc_kinds.len(), 2);
+        assert_eq!(c.a_ids[&0], 0);
+        assert_eq!(c.b_ids[&1], 1);
+        assert!(!c.a_ids.contains_key(&1) && !c.b_ids.contains_key(&0));
+        let rules = &c.def.behaviour.rules;
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].actions.len(), 1);
+        assert_eq!(rules[0].actions[0].putter, 0);
+        assert_eq!(rules[0].actions[0].getters, vec![1]);
+    }
+
+    #[test]
+    fn compose_guards_and_interleaving() {
+        let a = def("protocol A {
+                putter x: T;
+                getter o: T;
+                mem m: T;
+                rule true { x => m; }
+                rule true { m => o; }
+            }");
+        let b = def("protocol B {
+                putter i, k: T;
+                getter y, z: T;
+                rule i == k { i => y; k => ; }
+                rule true { i => z; }
+                rule true { k => z; }
+            }");
+        let c = compose(&a, &b, &[("o", "i")]).unwrap();
+        let (x, m) = (c.a_ids[&0], c.a_ids[&2]);
+        let (k, y, z) = (c.b_ids[&1], c.b_ids[&2], c.b_ids[&3]);
+        let rules = &c.def.behaviour.rules;
+        assert_eq!(rules.len(), 4);
+        assert_eq!(rules[0].actions[0].putter, x);
+        assert_eq!(
+            rules[1].guard,
+            Formula::ValueEq(Term::Value(m), Term::Value(k))
+        );
+        assert_eq!(rules[1].actions[0].putter, m);
+        assert_eq!(rules[1].actions[0].getters, vec![y]);
+        assert_eq!(rules[1].actions[1].putter, k);
+        assert_eq!(rules[2].actions[0].getters, vec![z]);
+        assert_eq!(rules[3].actions[0].putter, k);
+
+        assert_eq!(
+            compose(&a, &b, &[("x", "i")]).err(),
+            Some(ComposeError::NotAGetter { loc_id: 0 })
+        );
+        assert_eq!(
+            compose(&a, &b, &[("o", "y")]).err(),
+            Some(ComposeError::NotAPutter { loc_id: 2 })
+        );
+        assert_eq!(
+            compose(&a, &b, &[("o", "j")]).err(),
+            Some(ComposeError::UnknownName {
+                name: "j".to_owned()
+            })
+        );
+        let mut undeclared = a.clone();
+        undeclared.loc_kinds.remove(&2);
+        assert_eq!(
+            compose(&undeclared, &b, &[("o", "i")]).err(),
+            Some(ComposeError::UnknownLoc { loc_id: 2 })
+        );
+    }
+
+    #[test]
+    fn compose_transforms() {
+        let t = def("protocol T { putter a: T; getter b: T; rule true { f(a) => b; } }");
+        let sync = def("protocol S { putter a: T; getter b, c: T; rule true { a => b, c; } }");
+        let c = compose(&t, &sync, &[("b", "a")]).unwrap();
+        let action = &c.def.behaviour.rules[0].actions[0];
+        assert_eq!(action.transform, Some("f"));
+        assert_eq!(action.getters.len(), 2);
+        // c would observe the untransformed datum
+        assert_eq!(
+            compose(&sync, &t, &[("b", "a")]).err(),
+            Some(ComposeError::UnsupportedTransform { loc_id: 1 })
+        );
+        let sync = def("protocol S { putter a: T; getter b: T; rule true { a => b; } }");
+        let c = compose(&sync, &t, &[("b", "a")]).unwrap();
+        assert_eq!(c.def.behaviour.rules[0].actions[0].transform, Some("f"));
+        assert_eq!(
+            compose(&t, &t, &[("b", "a")]).err(),
+            Some(ComposeError::UnsupportedTransform { loc_id: 1 })
+        );
+    }
+
+    #[test]
+    fn compose_guard_on_hidden() {
+        let sync = def("protocol S { putter a: T; getter b: T; rule true { a => b; } }");
+        // unsynchronised rule of `a` observes its glued getter
+        let a = def("protocol A {
+                putter x, w: T; getter o: T;
+                rule true { x => o; }
+                rule o == w { w => ; }
+            }");
+        assert_eq!(
+            compose(&a, &sync, &[("o", "a")]).err(),
+            Some(ComposeError::GuardOnHiddenLoc { loc_id: 2 })
+        );
+        // unsynchronised rule of `b` observes its glued putter
+        let b = def("protocol B {
+                putter i, k: T; getter y: T;
+                rule true { i => y; }
+                rule i == k { k => ; }
+            }");
+        assert_eq!(
+            compose(&sync, &b, &[("b", "i")]).err(),
+            Some(ComposeError::GuardOnHiddenLoc { loc_id: 0 })
+        );
+        // synchronised rule observes a glued port none of its actions feeds
+        let a = def("protocol A {
+                putter x, w: T; getter o, p: T;
+                rule true { x => o; }
+                rule o == p { w => p; }
+            }");
+        let b = def("protocol B { putter i, k: T; rule true { i => ; } rule true { k => ; } }");
+        assert_eq!(
+            compose(&a, &b, &[("o", "i"), ("p", "k")]).err(),
+            Some(ComposeError::GuardOnHiddenLoc { loc_id: 2 })
+        );
+    }
+
+    #[test]
+    fn composed_fifo_sync_runs() {
+        let fifo = def("protocol F {
+                putter a: T; getter b: T; mem m: T;
+                rule true { a => m; }
+                rule true { m => b; }
+            }");
+        let sync = def("protocol S { putter a: T; getter b: T; rule true { a => b; } }");
+        let c = compose(&fifo, &sync, &[("b", "a")]).unwrap();
+        let loc_types = c
+            .def
+            .loc_kinds
+            .keys()
+            .map(|&id| (id, TypeInfo::new::<u32>()))
+            .collect();
+        let p = DynProtoBuilder::new(c.def.clone(), loc_types)
+            .build()
+            .unwrap();
+        use std::convert::TryInto;
+        let mut a: Putter<u32> = p.claim(c.a_ids[&0]).try_into().unwrap();
+        let mut b: Getter<u32> = p.claim(c.b_ids[&1]).try_into().unwrap();
+        crossbeam::scope(|s| {
+            s.spawn(move |_| {
+                for i in 0..10 {
+                    assert!(a.put(i).is_none());
+                }
+            });
+            s.spawn(move |_| {
+                for i in 0..10 {
+                    assert_eq!(b.get(), i);
+                }
+            });
+        })
+        .expect("Crashed!");
+    }
+}
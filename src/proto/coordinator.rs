@@ -0,0 +1,79 @@
+//! The optional dedicated coordinator thread. By default, the port thread that makes
+//! a location ready goes on to fire whatever rules this enables, which may be a long
+//! chain of memory-to-memory rules. Instances built with `ProtoBuilder::coordinator_thread`
+//! instead leave all rule evaluation and firing to a background thread, so that port
+//! operations only publish their readiness and await their `MsgDropbox`.
+//! ```ignore
+//! let mut b = DynProtoBuilder::new(def, loc_types);
+//! b.coordinator_thread();
+//! let p = b.build()?;
+//! ```
+//! The thread is started when the first port is claimed, and exits once the protocol
+//! instance is dropped.
+
+use super::*;
+use crossbeam::channel::{Receiver, Sender};
+use std::sync::Weak;
+
+/// Wakes the coordinator thread of a protocol instance, naming the region to coordinate.
+pub(crate) struct Coordinator {
+    wake: Sender<usize>,
+    // taken when the thread is started
+    unstarted: Mutex<Option<Receiver<usize>>>,
+}
+impl Coordinator {
+    pub(crate) fn new() -> Self {
+        let (wake, woken) = crossbeam::channel::unbounded();
+        Self {
+            wake,
+            unstarted: Mutex::new(Some(woken)),
+        }
+    }
+}
+
+impl ProtoW {
+    /// Fires rules until none can fire, or has the coordinator thread do so, if there is one.
+    pub(crate) fn coordinate(&mut self, r: &ProtoR) {
+        match &r.coordinator {
+            None => self.exhaust_rules(r),
+            Some(coordinator) => {
+                // the coordinator clears the flag once it has locked this region
+                if !self.coordinator_woken {
+                    self.coordinator_woken = true;
+                    let _ = coordinator.wake.send(self.region);
+                }
+            }
+        }
+    }
+}
+
+impl ProtoAll {
+    /// Starts the coordinator thread, unless the instance has none, or it was already started.
+    pub(crate) fn start_coordinator(self: &Arc<Self>) {
+        let woken = match &self.r.coordinator {
+            Some(coordinator) => coordinator.unstarted.lock().take(),
+            None => None,
+        };
+        if let Some(woken) = woken {
+            let p = Arc::downgrade(self);
+            std::thread::Builder::new()
+                .name("reo coordinator".to_owned())
+                .spawn(move || run(p, woken))
+                .expect("failed to spawn coordinator thread");
+        }
+    }
+}
+
+/// Holds onto the protocol only while coordinating, so as not to keep it alive.
+/// Dropping the protocol drops the `Sender`, which ends the loop.
+fn run(p: Weak<ProtoAll>, woken: Receiver<usize>) {
+    for region in woken.iter() {
+        let p = match p.upgrade() {
+            Some(p) => p,
+            None => return,
+        };
+        let mut w = p.regions[region].lock();
+        w.coordinator_woken = false;
+        w.exhaust_rules(&p.r);
+    }
+}
@@ -1,43 +1,238 @@
 use super::*;
-use crate::proto::traits::FuncDefPromise;
-use crate::proto::traits::MemFillPromise;
+use crate::proto::traits::{FuncDefPromise, MemFillPromise};
+use hashbrown::HashSet;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BehaviourDef {
     pub rules: Vec<RuleDef>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RuleDef {
     pub guard: Formula,
     pub actions: Vec<ActionDef>,
+    /// Of the rules that can fire at once, only those of the highest priority may.
+    #[serde(default)]
+    pub priority: i32,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ActionDef {
     pub putter: usize,
     pub getters: Vec<LocId>,
+    /// If present, names a function of arity 1 that is applied to the putter's
+    /// datum. Getters receive the result instead, so they may have a different type.
+    #[serde(default, deserialize_with = "deserialize_interned_opt_str")]
+    pub transform: Option<Name>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Formula {
     True,
+    False,
     And(Vec<Formula>),
     Or(Vec<Formula>),
     None(Vec<Formula>),
     ValueEq(Term, Term),
+    Lt(Term, Term),
+    Le(Term, Term),
+    Gt(Term, Term),
+    Ge(Term, Term),
+    Not(Box<Formula>),
     MemIsNull(LocId),
     TermVal(Term),
-    FuncDeclaration { name: &'static str, args: Vec<Term> },
+    FuncDeclaration {
+        #[serde(deserialize_with = "deserialize_interned_str")]
+        name: Name,
+        args: Vec<Term>,
+    },
+}
+
+/// Returns the `Name` equal to `name`. Only names not seen before are leaked to obtain one,
+/// so definitions that are loaded or parsed again and again do not leak more memory.
+pub(crate) fn intern_name(name: &str) -> Name {
+    lazy_static::lazy_static! {
+        static ref NAMES: Mutex<HashSet<Name>> = Default::default();
+    }
+    let mut names = NAMES.lock();
+    match names.get(name) {
+        Some(&interned) => interned,
+        None => {
+            let interned: Name = Box::leak(name.to_owned().into_boxed_str());
+            names.insert(interned);
+            interned
+        }
+    }
+}
+fn deserialize_interned_str<'de, D: Deserializer<'de>>(d: D) -> Result<Name, D::Error> {
+    let s = String::deserialize(d)?;
+    Ok(intern_name(&s))
+}
+fn deserialize_interned_opt_str<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Name>, D::Error> {
+    let s = Option::<String>::deserialize(d)?;
+    Ok(s.map(|s| intern_name(&s)))
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Term {
     Boolean(Box<Formula>),
     Value(LocId),
+    /// A literal value. Its type is that of the term it is compared with (or of the
+    /// function parameter it is passed as). The value is obtained by parsing the
+    /// literal, unless the builder was given a typed constant for it.
+    Const(String),
 }
 
-#[derive(Debug, Copy, Clone)]
+impl RuleDef {
+    /// Returns a copy of this rule with every LocId replaced according to `f`.
+    pub fn map_loc_ids(&self, f: &impl Fn(LocId) -> LocId) -> Self {
+        Self {
+            guard: self.guard.map_loc_ids(f),
+            actions: self
+                .actions
+                .iter()
+                .map(|a| ActionDef {
+                    putter: f(a.putter),
+                    getters: a.getters.iter().map(|&g| f(g)).collect(),
+                    transform: a.transform,
+                })
+                .collect(),
+            priority: self.priority,
+        }
+    }
+}
+impl Formula {
+    /// Returns a copy of this formula with every LocId replaced according to `f`.
+    pub fn map_loc_ids(&self, f: &impl Fn(LocId) -> LocId) -> Self {
+        use Formula::*;
+        let fs = |fs: &Vec<Formula>| fs.iter().map(|x| x.map_loc_ids(f)).collect();
+        match self {
+            True => True,
+            False => False,
+            And(x) => And(fs(x)),
+            Or(x) => Or(fs(x)),
+            None(x) => None(fs(x)),
+            ValueEq(a, b) => ValueEq(a.map_loc_ids(f), b.map_loc_ids(f)),
+            Lt(a, b) => Lt(a.map_loc_ids(f), b.map_loc_ids(f)),
+            Le(a, b) => Le(a.map_loc_ids(f), b.map_loc_ids(f)),
+            Gt(a, b) => Gt(a.map_loc_ids(f), b.map_loc_ids(f)),
+            Ge(a, b) => Ge(a.map_loc_ids(f), b.map_loc_ids(f)),
+            Not(x) => Not(Box::new(x.map_loc_ids(f))),
+            MemIsNull(id) => MemIsNull(f(*id)),
+            TermVal(t) => TermVal(t.map_loc_ids(f)),
+            FuncDeclaration { name, args } => FuncDeclaration {
+                name,
+                args: args.iter().map(|t| t.map_loc_ids(f)).collect(),
+            },
+        }
+    }
+}
+impl Formula {
+    /// Calls `f` on every LocId occurring in this formula.
+    pub fn visit_loc_ids(&self, f: &mut impl FnMut(LocId)) {
+        use Formula::*;
+        match self {
+            True | False => (),
+            And(x) | Or(x) | None(x) => x.iter().for_each(|x| x.visit_loc_ids(f)),
+            ValueEq(a, b) | Lt(a, b) | Le(a, b) | Gt(a, b) | Ge(a, b) => {
+                a.visit_loc_ids(f);
+                b.visit_loc_ids(f);
+            }
+            Not(x) => x.visit_loc_ids(f),
+            MemIsNull(id) => f(*id),
+            TermVal(t) => t.visit_loc_ids(f),
+            FuncDeclaration { args, .. } => args.iter().for_each(|t| t.visit_loc_ids(f)),
+        }
+    }
+}
+impl Formula {
+    /// Returns an equivalent formula without trivially reducible nodes: nested `And`s
+    /// and `Or`s are flattened, `True` and `False` operands are folded away, `None` and
+    /// double negations are rewritten, and boolean terms are unwrapped. The result is
+    /// `True` or `False` if this formula is always true or always false.
+    pub fn normalized(self) -> Self {
+        use Formula::*;
+        match self {
+            And(fs) => {
+                let mut out = vec![];
+                for f in fs {
+                    match f.normalized() {
+                        True => (),
+                        False => return False,
+                        And(gs) => out.extend(gs),
+                        g => out.push(g),
+                    }
+                }
+                match out.len() {
+                    0 => True,
+                    1 => out.pop().unwrap(),
+                    _ => And(out),
+                }
+            }
+            Or(fs) => {
+                let mut out = vec![];
+                for f in fs {
+                    match f.normalized() {
+                        False => (),
+                        True => return True,
+                        Or(gs) => out.extend(gs),
+                        g => out.push(g),
+                    }
+                }
+                match out.len() {
+                    0 => False,
+                    1 => out.pop().unwrap(),
+                    _ => Or(out),
+                }
+            }
+            None(fs) => Not(Box::new(Or(fs))).normalized(),
+            Not(f) => match f.normalized() {
+                True => False,
+                False => True,
+                Not(g) => *g,
+                g => Not(Box::new(g)),
+            },
+            TermVal(Term::Boolean(f)) => f.normalized(),
+            TermVal(t) => TermVal(t),
+            ValueEq(a, b) => ValueEq(a.normalized(), b.normalized()),
+            Lt(a, b) => Lt(a.normalized(), b.normalized()),
+            Le(a, b) => Le(a.normalized(), b.normalized()),
+            Gt(a, b) => Gt(a.normalized(), b.normalized()),
+            Ge(a, b) => Ge(a.normalized(), b.normalized()),
+            FuncDeclaration { name, args } => FuncDeclaration {
+                name,
+                args: args.into_iter().map(Term::normalized).collect(),
+            },
+            True | False | MemIsNull(_) => self,
+        }
+    }
+}
+impl Term {
+    pub fn normalized(self) -> Self {
+        match self {
+            Term::Boolean(f) => Term::Boolean(Box::new(f.normalized())),
+            t => t,
+        }
+    }
+    pub fn visit_loc_ids(&self, f: &mut impl FnMut(LocId)) {
+        match self {
+            Term::Boolean(x) => x.visit_loc_ids(f),
+            Term::Value(id) => f(*id),
+            Term::Const(_) => (),
+        }
+    }
+    pub fn map_loc_ids(&self, f: &impl Fn(LocId) -> LocId) -> Self {
+        match self {
+            Term::Boolean(x) => Term::Boolean(Box::new(x.map_loc_ids(f))),
+            Term::Value(id) => Term::Value(f(*id)),
+            Term::Const(c) => Term::Const(c.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ProtoBuildErr {
     UnknownType {
         loc_id: LocId,
@@ -56,10 +251,20 @@ pub enum ProtoBuildErr {
         action_id: usize,
         loc_id_putter: LocId,
         loc_id_getter: LocId,
+        /// the type of the datum, which a transform may have changed
+        putter_type: &'static str,
+        getter_type: &'static str,
     },
     MemoryFillPromiseBroken {
         loc_id: LocId,
     },
+    MemoryFillTypeMismatch {
+        loc_id: LocId,
+    },
+    /// This location was given an initial value, though it is no `MemInitialized` memory cell.
+    MemoryFillNotInitialized {
+        loc_id: LocId,
+    },
     FunctionUndefined {
         name: &'static str,
     },
@@ -67,7 +272,250 @@ pub enum ProtoBuildErr {
         name: &'static str,
         used_arity: usize,
     },
+    FunctionParamTypeMismatch {
+        name: &'static str,
+        param: usize,
+    },
+    FunctionReturnsNonBool {
+        name: &'static str,
+    },
+    /// The operands of a comparison in this rule's guard have different types.
+    GuardTypeMismatch {
+        rule_id: usize,
+    },
+    /// A comparison in this rule's guard is not defined for the type of its operands.
+    GuardOpUndefined {
+        rule_id: usize,
+    },
+    /// A literal in this rule's guard is no valid value of its type.
+    ConstUnparsable {
+        rule_id: usize,
+    },
+    /// The type of a literal in this rule's guard cannot be inferred.
+    ConstTypeUnknown {
+        rule_id: usize,
+    },
+    /// This location has the same name as another.
+    DuplicateLocName {
+        loc_id: LocId,
+    },
+}
+impl ProtoBuildErr {
+    /// The rule in which the error was found, if the error concerns a single rule.
+    pub fn rule_id(&self) -> Option<usize> {
+        use ProtoBuildErr::*;
+        match *self {
+            TypeMismatch { rule_id, .. }
+            | GuardTypeMismatch { rule_id }
+            | GuardOpUndefined { rule_id }
+            | ConstUnparsable { rule_id }
+            | ConstTypeUnknown { rule_id } => Some(rule_id),
+            _ => Option::None,
+        }
+    }
+    /// Replaces every LocId the error refers to with `f` of it.
+    pub fn map_loc_ids(self, f: impl Fn(LocId) -> LocId) -> Self {
+        use ProtoBuildErr::*;
+        match self {
+            UnknownType { loc_id } => UnknownType { loc_id: f(loc_id) },
+            SynchronousFiring { loc_id } => SynchronousFiring { loc_id: f(loc_id) },
+            LocCannotGet { loc_id } => LocCannotGet { loc_id: f(loc_id) },
+            LocCannotPut { loc_id } => LocCannotPut { loc_id: f(loc_id) },
+            TypeMismatch {
+                rule_id,
+                action_id,
+                loc_id_putter,
+                loc_id_getter,
+                putter_type,
+                getter_type,
+            } => TypeMismatch {
+                rule_id,
+                action_id,
+                loc_id_putter: f(loc_id_putter),
+                loc_id_getter: f(loc_id_getter),
+                putter_type,
+                getter_type,
+            },
+            MemoryFillPromiseBroken { loc_id } => MemoryFillPromiseBroken { loc_id: f(loc_id) },
+            MemoryFillTypeMismatch { loc_id } => MemoryFillTypeMismatch { loc_id: f(loc_id) },
+            MemoryFillNotInitialized { loc_id } => MemoryFillNotInitialized { loc_id: f(loc_id) },
+            DuplicateLocName { loc_id } => DuplicateLocName { loc_id: f(loc_id) },
+            other => other,
+        }
+    }
+    /// Describes the error, referring to each location as given by `loc`.
+    fn fmt_with(&self, f: &mut fmt::Formatter, loc: &dyn Fn(LocId) -> String) -> fmt::Result {
+        use ProtoBuildErr::*;
+        match *self {
+            UnknownType { loc_id } => write!(f, "{} has no known type", loc(loc_id)),
+            SynchronousFiring { loc_id } => write!(
+                f,
+                "{} is involved in more than one action of the same rule",
+                loc(loc_id)
+            ),
+            LocCannotGet { loc_id } => write!(f, "{} cannot act as a getter", loc(loc_id)),
+            LocCannotPut { loc_id } => write!(f, "{} cannot act as a putter", loc(loc_id)),
+            TypeMismatch {
+                rule_id,
+                action_id,
+                loc_id_putter,
+                loc_id_getter,
+                putter_type,
+                getter_type,
+            } => write!(
+                f,
+                "action {} of rule {} passes `{}` from {} to {}, which holds `{}`",
+                action_id,
+                rule_id,
+                putter_type,
+                loc(loc_id_putter),
+                loc(loc_id_getter),
+                getter_type
+            ),
+            MemoryFillPromiseBroken { loc_id } => {
+                write!(f, "{} was given no initial value", loc(loc_id))
+            }
+            MemoryFillTypeMismatch { loc_id } => {
+                write!(
+                    f,
+                    "{} was given an initial value of the wrong type",
+                    loc(loc_id)
+                )
+            }
+            MemoryFillNotInitialized { loc_id } => write!(
+                f,
+                "{} was given an initial value, but is no initialized memory cell",
+                loc(loc_id)
+            ),
+            FunctionUndefined { name } => write!(f, "function `{}` is not defined", name),
+            FunctionUsedWithWrongArity { name, used_arity } => write!(
+                f,
+                "function `{}` is applied to {} argument(s), unlike its definition",
+                name, used_arity
+            ),
+            FunctionParamTypeMismatch { name, param } => write!(
+                f,
+                "argument {} of function `{}` has the wrong type",
+                param, name
+            ),
+            FunctionReturnsNonBool { name } => write!(
+                f,
+                "function `{}` is used as a condition, but does not return `bool`",
+                name
+            ),
+            GuardTypeMismatch { rule_id } => write!(
+                f,
+                "the operands of a comparison in the guard of rule {} have different types",
+                rule_id
+            ),
+            GuardOpUndefined { rule_id } => write!(
+                f,
+                "a comparison in the guard of rule {} is not defined for its operands' type",
+                rule_id
+            ),
+            ConstUnparsable { rule_id } => write!(
+                f,
+                "a literal in the guard of rule {} is no valid value of its type",
+                rule_id
+            ),
+            ConstTypeUnknown { rule_id } => write!(
+                f,
+                "the type of a literal in the guard of rule {} cannot be inferred",
+                rule_id
+            ),
+            DuplicateLocName { loc_id } => {
+                write!(f, "{} has the same name as another location", loc(loc_id))
+            }
+        }
+    }
+}
+impl fmt::Display for ProtoBuildErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_with(f, &|loc_id| format!("location {}", loc_id))
+    }
+}
+impl std::error::Error for ProtoBuildErr {}
+
+/// An error found while building a protocol, with the rule and action it was found in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub err: ProtoBuildErr,
+    pub rule_id: Option<usize>,
+    pub action_id: Option<usize>,
+}
+
+/// All errors found while building a protocol, in the order in which they were found.
+/// When displayed, each is described on its own line, referring to locations
+/// by their names (if any) and types.
+#[derive(Debug, Clone)]
+pub struct Diagnostics {
+    pub list: Vec<Diagnostic>,
+    loc_names: HashMap<LocId, String>,
+    loc_type_names: HashMap<LocId, &'static str>,
+}
+impl Diagnostics {
+    pub(crate) fn new(def: &TypelessProtoDef) -> Self {
+        Self {
+            list: vec![],
+            loc_names: def.loc_names.clone(),
+            loc_type_names: Default::default(),
+        }
+    }
+    pub(crate) fn note(
+        &mut self,
+        err: ProtoBuildErr,
+        rule_id: Option<usize>,
+        action_id: Option<usize>,
+    ) {
+        let d = Diagnostic {
+            err,
+            rule_id,
+            action_id,
+        };
+        // the same undefined function may be used by many rules
+        if !self.list.contains(&d) {
+            self.list.push(d);
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+    /// The error that `ProtoBuilder::finish` would have returned.
+    pub fn first(&self) -> ProtoBuildErr {
+        self.list[0].err
+    }
+    fn describe_loc(&self, loc_id: LocId) -> String {
+        let mut s = format!("location {}", loc_id);
+        match (
+            self.loc_names.get(&loc_id),
+            self.loc_type_names.get(&loc_id),
+        ) {
+            (Some(name), Some(t)) => s.push_str(&format!(" (`{}`: `{}`)", name, t)),
+            (Some(name), Option::None) => s.push_str(&format!(" (`{}`)", name)),
+            (Option::None, Some(t)) => s.push_str(&format!(" (`{}`)", t)),
+            (Option::None, Option::None) => {}
+        }
+        s
+    }
+}
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for d in self.list.iter() {
+            write!(f, "error: ")?;
+            if d.err.rule_id().is_none() {
+                match (d.rule_id, d.action_id) {
+                    (Some(r), Some(a)) => write!(f, "action {} of rule {}: ", a, r)?,
+                    (Some(r), Option::None) => write!(f, "rule {}: ", r)?,
+                    _ => {}
+                }
+            }
+            d.err.fmt_with(f, &|loc_id| self.describe_loc(loc_id))?;
+            writeln!(f)?;
+        }
+        Ok(())
+    }
 }
+impl std::error::Error for Diagnostics {}
 
 pub struct FuncDef {
     pub(crate) ret_info: Arc<TypeInfo>,
@@ -79,9 +527,23 @@ pub struct ProtoBuilder {
     mem_storage: Storage,
     init_mems: HashMap<LocId, *mut u8>,
     func_defs: HashMap<&'static str, FuncDef>,
+    consts: HashMap<String, (*mut u8, Arc<TypeInfo>)>,
+    // cells already holding some constant of some type, shared by all guards
+    const_locs: HashMap<(String, TypeId), LocId>,
+    coordinator_thread: bool,
+    selector: Box<dyn RuleSelector>,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// State of `ProtoBuilder::calc_guard` while runnifying the guard of one rule.
+struct GuardCtx<'a> {
+    rule_id: usize,
+    id_2_type_id: &'a HashMap<LocId, TypeId>,
+    type_id_2_info: &'a HashMap<TypeId, Arc<TypeInfo>>,
+    spaces: &'a mut Vec<Space>,
+    temp_mems: Vec<TempMemRunnable>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum LocKind {
     PortPutter,
     PortGetter,
@@ -89,21 +551,21 @@ pub enum LocKind {
     MemUninitialized,
 }
 impl LocKind {
-    fn can_put(self) -> bool {
+    pub(crate) fn can_put(self) -> bool {
         use LocKind::*;
         match self {
             PortGetter => false,
             _ => true,
         }
     }
-    fn can_get(self) -> bool {
+    pub(crate) fn can_get(self) -> bool {
         use LocKind::*;
         match self {
             PortPutter => false,
             _ => true,
         }
     }
-    fn is_mem(self) -> bool {
+    pub(crate) fn is_mem(self) -> bool {
         use LocKind::*;
         match self {
             PortPutter | PortGetter => false,
@@ -112,9 +574,16 @@ impl LocKind {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TypelessProtoDef {
     pub behaviour: BehaviourDef,
     pub loc_kinds: HashMap<LocId, LocKind>,
+    /// Optional, unique names of locations. Ports can be claimed by name.
+    #[serde(default)]
+    pub loc_names: HashMap<LocId, String>,
+    /// Names of the families of ports, which may be empty. See `family`.
+    #[serde(default)]
+    pub families: HashSet<String>,
 }
 
 impl ProtoBuilder {
@@ -123,27 +592,129 @@ impl ProtoBuilder {
             mem_storage: Default::default(),
             func_defs: Default::default(),
             init_mems: Default::default(),
+            consts: Default::default(),
+            const_locs: Default::default(),
+            coordinator_thread: false,
+            selector: Box::new(FirstMatch),
         }
     }
     pub(crate) fn define_func(&mut self, name: &'static str, func_def: FuncDef) {
-        assert!(self.func_defs.insert(name, func_def).is_none())
+        self.func_defs.insert(name, func_def);
     }
     pub(crate) fn define_init_memory<T: 'static>(&mut self, id: LocId, t: T) {
         let ptr = self.mem_storage.move_value_in(t);
-        let was = self.init_mems.insert(id, ptr);
-        assert!(was.is_none());
+        if let Some(was) = self.init_mems.insert(id, ptr) {
+            // replaced a previous value of the same type
+            let info = Arc::new(TypeInfo::new::<T>());
+            unsafe { self.mem_storage.drop_inside(was, &info) }
+        }
     }
-    pub fn finish<P: Proto>(mut self) -> Result<ProtoAll, ProtoBuildErr> {
-        use ProtoBuildErr::*;
+    /// Provides the value of the literal `literal` wherever it occurs in a guard,
+    /// instead of parsing it. This also fixes the literal's type to `T`.
+    pub fn define_const<T: 'static>(&mut self, literal: &str, t: T) {
+        let ptr = self.mem_storage.move_value_in(t);
+        let info = Arc::new(TypeInfo::new::<T>());
+        if let Some((was, was_info)) = self.consts.insert(literal.to_owned(), (ptr, info)) {
+            unsafe { self.mem_storage.drop_inside(was, &was_info) }
+        }
+    }
+    /// Instances built will fire their rules on a dedicated thread, rather than on the
+    /// threads of their ports. See the `coordinator` module.
+    pub fn coordinator_thread(&mut self) {
+        self.coordinator_thread = true;
+    }
+    /// Chooses among the rules that can fire at once. Defaults to `FirstMatch`.
+    /// Each region of instances built gets its own, see `RuleSelector::for_region`.
+    pub fn rule_selector(&mut self, selector: Box<dyn RuleSelector>) {
+        self.selector = selector;
+    }
+    pub fn finish<P: Proto>(self) -> Result<ProtoAll, ProtoBuildErr> {
+        self.finish_diagnosed::<P>().map_err(|d| d.first())
+    }
+
+    /// Like `finish`, but rather than stopping at the first error, collects all
+    /// errors it can find. Rules are only checked if all locations have known types.
+    pub fn finish_diagnosed<P: Proto>(self) -> Result<ProtoAll, Diagnostics> {
+        self.finish_diagnosed_with::<P>(Diagnostics::new(P::typeless_proto_def()))
+    }
+
+    /// Continues the diagnostics of `Proto::try_instantiate_diagnosed`.
+    pub(crate) fn finish_diagnosed_with<P: Proto>(
+        mut self,
+        mut diagnostics: Diagnostics,
+    ) -> Result<ProtoAll, Diagnostics> {
         let typeless_proto_def = P::typeless_proto_def();
-        let max_loc_id = Self::max_loc_id(typeless_proto_def);
+        for (rule_id, rule_def) in typeless_proto_def.behaviour.rules.iter().enumerate() {
+            if let Err(e) = self.define_all_funcs_in::<P>(&rule_def.guard) {
+                diagnostics.note(e, Some(rule_id), Option::None);
+            }
+            for (action_id, action_def) in rule_def.actions.iter().enumerate() {
+                if let Some(name) = action_def.transform {
+                    if let Err(e) = self.define_func_in::<P>(name) {
+                        diagnostics.note(e, Some(rule_id), Some(action_id));
+                    }
+                }
+            }
+        }
+        self.finish_def_diagnosed(typeless_proto_def, P::loc_type, diagnostics)
+    }
+
+    /// Builds a protocol from a definition that need not be static. Functions and
+    /// initial memory values must have been provided to the builder beforehand.
+    pub(crate) fn finish_def(
+        self,
+        typeless_proto_def: &TypelessProtoDef,
+        loc_type: impl Fn(LocId) -> Option<TypeInfo>,
+    ) -> Result<ProtoAll, ProtoBuildErr> {
+        let diagnostics = Diagnostics::new(typeless_proto_def);
+        self.finish_def_diagnosed(typeless_proto_def, loc_type, diagnostics)
+            .map_err(|d| d.first())
+    }
+
+    /// Builds the protocol from a copy of the definition whose LocIds are compacted
+    /// into the dense range `0..n`, such that no spaces or bits are wasted on gaps.
+    /// The protocol translates LocIds of the definition wherever they are user-facing.
+    pub(crate) fn finish_def_diagnosed(
+        mut self,
+        typeless_proto_def: &TypelessProtoDef,
+        loc_type: impl Fn(LocId) -> Option<TypeInfo>,
+        diagnostics: Diagnostics,
+    ) -> Result<ProtoAll, Diagnostics> {
+        let id_map = LocIdMap::compacting(typeless_proto_def);
+        let dense_def = id_map.compact(typeless_proto_def);
+        self.init_mems = std::mem::take(&mut self.init_mems)
+            .into_iter()
+            .filter_map(|(id, ptr)| Some((id_map.internal(id)?, ptr)))
+            .collect();
+        // errors noted before this point already refer to LocIds of the definition
+        let noted = diagnostics.list.len();
+        let loc_type = |id| loc_type(id_map.external(id));
+        self.finish_dense(&dense_def, loc_type, diagnostics, id_map.clone())
+            .map_err(|mut d| {
+                for x in d.list[noted..].iter_mut() {
+                    x.err = x.err.map_loc_ids(|id| id_map.external(id));
+                }
+                d.loc_type_names = id_map.externalize_keys(&d.loc_type_names);
+                d
+            })
+    }
+
+    fn finish_dense(
+        mut self,
+        typeless_proto_def: &TypelessProtoDef,
+        loc_type: impl Fn(LocId) -> Option<TypeInfo>,
+        mut diagnostics: Diagnostics,
+        id_map: LocIdMap,
+    ) -> Result<ProtoAll, Diagnostics> {
+        use ProtoBuildErr::*;
+        let num_locs = id_map.num_locs();
         let mut memory_bits: BitSet = typeless_proto_def
             .loc_kinds
             .iter()
             .filter(|(_, &loc_kinds)| loc_kinds == LocKind::MemInitialized)
             .map(|(&id, _)| id)
             .collect();
-        memory_bits.pad_trailing_zeroes_to_capacity(max_loc_id);
+        memory_bits.pad_trailing_zeroes_to_capacity(num_locs);
 
         let ready: BitSet = typeless_proto_def
             .loc_kinds
@@ -152,11 +723,22 @@ impl ProtoBuilder {
             .map(|(&id, _)| id)
             .collect();
 
+        let mut loc_ids: Vec<LocId> = typeless_proto_def.loc_kinds.keys().copied().collect();
+        loc_ids.sort();
         let (id_2_type_id, type_id_2_info) = {
             let mut id_2_type_id: HashMap<LocId, TypeId> = Default::default();
             let mut type_id_2_info: HashMap<TypeId, Arc<TypeInfo>> = Default::default();
-            for loc_id in typeless_proto_def.loc_kinds.keys().copied() {
-                let type_info = P::loc_type(loc_id).ok_or(UnknownType { loc_id })?;
+            for &loc_id in loc_ids.iter() {
+                let type_info = match loc_type(loc_id) {
+                    Some(type_info) => type_info,
+                    Option::None => {
+                        diagnostics.note(UnknownType { loc_id }, Option::None, Option::None);
+                        continue;
+                    }
+                };
+                diagnostics
+                    .loc_type_names
+                    .insert(loc_id, type_info.type_name);
                 let type_id = type_info.type_id;
                 id_2_type_id.entry(loc_id).or_insert(type_id);
                 type_id_2_info
@@ -171,7 +753,19 @@ impl ProtoBuilder {
             type_id_2_info.get(type_id).unwrap()
         };
 
-        let unclaimed_ports = typeless_proto_def
+        let mut named_ids: Vec<LocId> = typeless_proto_def.loc_names.keys().copied().collect();
+        named_ids.sort();
+        let mut names_seen = HashSet::new();
+        for loc_id in named_ids {
+            if !names_seen.insert(&typeless_proto_def.loc_names[&loc_id]) {
+                diagnostics.note(DuplicateLocName { loc_id }, Option::None, Option::None);
+            }
+        }
+        if !diagnostics.is_empty() {
+            return Err(diagnostics);
+        }
+
+        let mut ports: Vec<PortDesc> = typeless_proto_def
             .loc_kinds
             .iter()
             .filter_map(|(&id, loc_kinds)| {
@@ -180,41 +774,44 @@ impl ProtoBuilder {
                     LocKind::PortGetter => PortRole::Getter,
                     _ => return None,
                 };
-                let info = PortInfo {
+                let type_info = id_2_info(&id);
+                Some(PortDesc {
+                    loc_id: id_map.external(id),
+                    name: typeless_proto_def.loc_names.get(&id).cloned(),
                     role,
-                    type_id: *id_2_type_id.get(&id).unwrap(),
-                };
-                Some((id, info))
+                    type_id: type_info.type_id,
+                    type_name: type_info.type_name,
+                })
             })
             .collect();
-
-        let mem_refs = self
-            .init_mems
+        ports.sort_by_key(|port| port.loc_id);
+        let unclaimed_ports: Vec<(LocId, PortInfo)> = ports
             .iter()
-            .map(|(&loc_id, &ptr)| (ptr, loc_id))
+            .map(|port| {
+                let info = PortInfo {
+                    role: port.role,
+                    type_id: port.type_id,
+                };
+                (id_map.internal(port.loc_id).unwrap(), info)
+            })
             .collect();
 
-        let mut spaces = (0..=max_loc_id)
+        let mut spaces = (0..num_locs)
             .map(|id| {
-                Ok(if let Some(k) = typeless_proto_def.loc_kinds.get(&id) {
+                if let Some(k) = typeless_proto_def.loc_kinds.get(&id) {
                     match k {
                         LocKind::PortPutter => {
                             Space::PoPu(PoPuSpace::new({ id_2_info(&id).clone() }))
                         }
                         LocKind::PortGetter => Space::PoGe(PoGeSpace::new()),
                         LocKind::MemInitialized => Space::Memo({
-                            // TODO promise
                             let type_info = id_2_info(&id).clone();
-                            let promise = MemFillPromise {
-                                type_id_expected: type_info.type_id,
-                                loc_id: id,
-                                builder: &mut self,
-                            };
-                            P::fill_memory(id, promise);
                             if let Some(ptr) = self.init_mems.get(&id) {
                                 MemoSpace::new(*ptr, type_info)
                             } else {
-                                return Err(MemoryFillPromiseBroken { loc_id: id });
+                                let err = MemoryFillPromiseBroken { loc_id: id };
+                                diagnostics.note(err, Option::None, Option::None);
+                                return Space::Unused;
                             }
                         }),
                         LocKind::MemUninitialized => Space::Memo({
@@ -225,42 +822,184 @@ impl ProtoBuilder {
                     }
                 } else {
                     Space::Unused
+                }
+            })
+            .collect::<Vec<Space>>();
+
+        let rules = self.build_rules(
+            typeless_proto_def,
+            &id_2_type_id,
+            &type_id_2_info,
+            &mut spaces,
+            &mut diagnostics,
+        );
+        if !diagnostics.is_empty() {
+            return Err(diagnostics);
+        }
+        let loc_rules = Self::index_rules(&rules, spaces.len());
+        let (rule_region, loc_region) = Self::find_regions(&rules, &loc_rules, &spaces, num_locs);
+        let num_regions = rule_region.iter().max().map(|&k| k + 1).unwrap_or(1);
+
+        // initial values move to the storage of their region. constants remain in region 0
+        let mut storages: Vec<Storage> = (0..num_regions).map(|_| Storage::default()).collect();
+        let mut mem_refs: Vec<HashMap<*mut u8, usize>> = vec![Default::default(); num_regions];
+        for (&id, &ptr) in self.init_mems.iter() {
+            let k = loc_region[id];
+            if k != 0 {
+                self.mem_storage.transfer(ptr, &mut storages[k]);
+            }
+            mem_refs[k].insert(ptr, 1);
+        }
+        storages[0] = self.mem_storage;
+        let selector = self.selector;
+        let mut selectors: Vec<Box<dyn RuleSelector>> =
+            (1..num_regions).map(|k| selector.for_region(k)).collect();
+        selectors.insert(0, selector);
+
+        let in_region = |bits: &BitSet, k: usize| -> BitSet {
+            let mut b: BitSet = bits
+                .iter_sparse()
+                .filter(|&id| loc_region[id] == k)
+                .collect();
+            b.pad_trailing_zeroes_to_capacity(num_locs);
+            b
+        };
+        let regions = storages
+            .into_iter()
+            .zip(mem_refs)
+            .zip(selectors)
+            .enumerate()
+            .map(|(k, ((storage, mem_refs), selector))| {
+                Mutex::new(ProtoW {
+                    region: k,
+                    memory_bits: in_region(&memory_bits, k),
+                    active: ProtoActive {
+                        ready: in_region(&ready, k),
+                        storage,
+                        mem_refs,
+                        observer: None,
+                    },
+                    commitment: None,
+                    ready_tentative: BitSet::default(),
+                    awaiting_states: vec![],
+                    unclaimed_ports: unclaimed_ports
+                        .iter()
+                        .filter(|(id, _)| loc_region[*id] == k)
+                        .copied()
+                        .collect(),
+                    recorder: None,
+                    rules_to_check: (0..rules.len()).filter(|&i| rule_region[i] == k).collect(),
+                    coordinator_woken: false,
+                    selector,
+                    closed: BitSet::default(),
+                    fallible: BitSet::default(),
                 })
             })
-            .collect::<Result<Vec<Space>, ProtoBuildErr>>()?;
-
-        let rules = self.build_rules::<P>(&id_2_type_id, &mut spaces)?;
-        let r = ProtoR { spaces, rules };
-        let w = Mutex::new(ProtoW {
-            memory_bits,
-            active: ProtoActive {
-                ready,
-                storage: self.mem_storage,
-                mem_refs,
+            .collect();
+        let r = ProtoR {
+            loc_rules,
+            loc_region,
+            coordinator: if self.coordinator_thread {
+                Some(Coordinator::new())
+            } else {
+                None
             },
-            commitment: None,
-            ready_tentative: BitSet::default(),
-            awaiting_states: vec![],
-            unclaimed_ports,
-        });
-        Ok(ProtoAll { w, r })
+            spaces,
+            rules,
+            loc_names: id_map.externalize_keys(&typeless_proto_def.loc_names),
+            families: typeless_proto_def.families.clone(),
+            ports,
+            id_map,
+        };
+        Ok(ProtoAll { regions, r })
     }
 
-    fn max_loc_id(typeless_proto_def: &'static TypelessProtoDef) -> LocId {
-        typeless_proto_def
-            .loc_kinds
-            .keys()
-            .copied()
-            .max()
-            .unwrap_or(0)
+    /// Partitions the rules into regions: the connected components of the graph in which
+    /// rules are connected if they depend on the same location. Constants are read-only,
+    /// and so connect nothing. Regions are numbered in order of their first rule.
+    /// Returns the region of each rule and of each location. Temps are in the region of the
+    /// one rule using them. Other locations on which no rule depends are in region 0.
+    fn find_regions(
+        rules: &[RunRule],
+        loc_rules: &[Vec<usize>],
+        spaces: &[Space],
+        num_locs: usize,
+    ) -> (Vec<usize>, Vec<usize>) {
+        // union-find over rules
+        let mut parent: Vec<usize> = (0..rules.len()).collect();
+        fn root(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        let is_const = |id: LocId| id >= num_locs && matches!(spaces[id], Space::Memo(_));
+        for (id, rules) in loc_rules.iter().enumerate() {
+            if is_const(id) {
+                continue;
+            }
+            for pair in rules.windows(2) {
+                let (a, b) = (root(&mut parent, pair[0]), root(&mut parent, pair[1]));
+                parent[a.max(b)] = a.min(b);
+            }
+        }
+        let mut region_of_root: HashMap<usize, usize> = Default::default();
+        let rule_region: Vec<usize> = (0..rules.len())
+            .map(|i| {
+                let next = region_of_root.len();
+                *region_of_root.entry(root(&mut parent, i)).or_insert(next)
+            })
+            .collect();
+        let mut loc_region: Vec<usize> = loc_rules
+            .iter()
+            .enumerate()
+            .map(|(id, rules)| match rules.first() {
+                Some(&i) if !is_const(id) => rule_region[i],
+                _ => 0,
+            })
+            .collect();
+        for (rule, &k) in rules.iter().zip(rule_region.iter()) {
+            let transforms = rule.actions.iter().filter_map(|a| a.transform.as_ref());
+            for t in rule.temp_mems.iter().chain(transforms) {
+                loc_region[t.temp_mem_loc_id] = k;
+            }
+        }
+        (rule_region, loc_region)
+    }
+
+    /// For each location, lists the rules that depend on its readiness, fullness or value.
+    fn index_rules(rules: &[RunRule], num_spaces: usize) -> Vec<Vec<usize>> {
+        let mut loc_rules = vec![vec![]; num_spaces];
+        for (rule_id, rule) in rules.iter().enumerate() {
+            let mut deps = rule.guard_ready.clone();
+            deps.or_with(&rule.guard_mem);
+            let mut dep = |id| {
+                deps.set_to(id, true);
+            };
+            if let Some(guard_pred) = &rule.guard_pred {
+                guard_pred.visit_loc_ids(&mut dep);
+            }
+            for t in rule.temp_mems.iter() {
+                for arg in t.func.args() {
+                    arg.visit_loc_ids(&mut dep);
+                }
+            }
+            for id in deps.iter_sparse() {
+                loc_rules[id].push(rule_id);
+            }
+        }
+        loc_rules
     }
 
-    fn build_rules<P: Proto>(
+    fn build_rules(
         &mut self,
+        typeless_proto_def: &TypelessProtoDef,
         id_2_type_id: &HashMap<LocId, TypeId>,
+        type_id_2_info: &HashMap<TypeId, Arc<TypeInfo>>,
         spaces: &mut Vec<Space>,
-    ) -> Result<Vec<RunRule>, ProtoBuildErr> {
-        let typeless_proto_def = P::typeless_proto_def();
+        diagnostics: &mut Diagnostics,
+    ) -> Vec<RunRule> {
         use ProtoBuildErr::*;
         let mut rules = vec![];
         for (rule_id, rule_def) in typeless_proto_def.behaviour.rules.iter().enumerate() {
@@ -269,91 +1008,152 @@ impl ProtoBuilder {
             let mut actions = vec![];
             let mut assign_vals = BitSet::default();
             let mut assign_mask = BitSet::default();
+            let mut failed = false;
 
             for (action_id, action_def) in rule_def.actions.iter().enumerate() {
-                let mut mg = smallvec::smallvec![];
-                let mut pg = smallvec::smallvec![];
-
-                let p = action_def.putter;
-                let p_kind = typeless_proto_def
-                    .loc_kinds
-                    .get(&p)
-                    .ok_or(UnknownType { loc_id: p })?;
-                let p_type = id_2_type_id.get(&p).unwrap();
-                if !p_kind.can_put() {
-                    return Err(LocCannotPut { loc_id: p });
-                }
-                let mem_putter = p_kind.is_mem();
-                if guard_ready.test(p) {
-                    return Err(SynchronousFiring { loc_id: p });
-                }
-                if mem_putter {
-                    guard_full.set_to(p, true); // putter must be full!
-                    assign_vals.set_to(p, false); // putter becomes empty!
-                    assign_mask.set_to(p, true); // putter memory fullness changes!
-                }
-                let was = guard_ready.set_to(p, true); // putter is involved!
-                if was {
-                    // this putter was involved in a different action!
-                    return Err(SynchronousFiring { loc_id: p });
-                }
+                // errors end the action, but the remaining actions are still checked
+                let mut build_action = || -> Result<RunAction, ProtoBuildErr> {
+                    let mut mg = smallvec::smallvec![];
+                    let mut pg = smallvec::smallvec![];
 
-                use itertools::Itertools;
-                for &g in action_def.getters.iter().unique() {
-                    let g_kind = typeless_proto_def
+                    let p = action_def.putter;
+                    let p_kind = typeless_proto_def
                         .loc_kinds
-                        .get(&g)
-                        .ok_or(UnknownType { loc_id: g })?;
-                    let g_type = id_2_type_id.get(&g).unwrap();
-                    if p_type != g_type {
-                        return Err(TypeMismatch {
-                            rule_id,
-                            action_id,
-                            loc_id_putter: p,
-                            loc_id_getter: g,
-                        });
+                        .get(&p)
+                        .ok_or(UnknownType { loc_id: p })?;
+                    let p_type = id_2_type_id.get(&p).unwrap();
+                    if !p_kind.can_put() {
+                        return Err(LocCannotPut { loc_id: p });
                     }
-                    if !g_kind.can_get() {
-                        return Err(LocCannotGet { loc_id: g });
+                    let mem_putter = p_kind.is_mem();
+                    if guard_ready.test(p) {
+                        return Err(SynchronousFiring { loc_id: p });
                     }
-                    let mem_getter = g_kind.is_mem();
-                    match mem_getter {
-                        false => &mut pg,
-                        true => &mut mg,
+                    if mem_putter {
+                        guard_full.set_to(p, true); // putter must be full!
+                        assign_vals.set_to(p, false); // putter becomes empty!
+                        assign_mask.set_to(p, true); // putter memory fullness changes!
                     }
-                    .push(g);
-                    if mem_getter {
-                        guard_full.set_to(g, false); // getter must be empty!
-                        assign_vals.set_to(g, true); // getter becomes full!
-                        assign_mask.set_to(g, true); // getter memory fullness changes!
+                    let was = guard_ready.set_to(p, true); // putter is involved!
+                    if was {
+                        // this putter was involved in a different action!
+                        return Err(SynchronousFiring { loc_id: p });
                     }
-                    let was_set = guard_ready.set_to(g, true);
-                    if was_set {
-                        // oh no! this getter was already involved in the firing
-                        if g == p && mem_putter {
-                            // nevermind. its OK for memory to put and get to itself
-                            guard_full.set_to(g, true); // getter must be full (because its also the putter)!
-                            assign_mask.set_to(g, false); // getter memory fullness DOES NOT change (Full -> Full)!
-                            assign_vals.set_to(g, false);
-                        } else {
+                    // getters of a transforming action receive the function's result instead
+                    let (transform, datum_info) = match action_def.transform {
+                        None => (None, &*type_id_2_info[p_type]),
+                        Some(name) => {
+                            let (t, ret_info) = self.calc_transform(name, p, p_type, spaces)?;
+                            (Some(t), ret_info)
+                        }
+                    };
+
+                    use itertools::Itertools;
+                    for &g in action_def.getters.iter().unique() {
+                        let g_kind = typeless_proto_def
+                            .loc_kinds
+                            .get(&g)
+                            .ok_or(UnknownType { loc_id: g })?;
+                        let g_type = id_2_type_id.get(&g).unwrap();
+                        if datum_info.type_id != *g_type {
+                            return Err(TypeMismatch {
+                                rule_id,
+                                action_id,
+                                loc_id_putter: p,
+                                loc_id_getter: g,
+                                putter_type: datum_info.type_name,
+                                getter_type: type_id_2_info[g_type].type_name,
+                            });
+                        }
+                        if !g_kind.can_get() {
+                            return Err(LocCannotGet { loc_id: g });
+                        }
+                        let mem_getter = g_kind.is_mem();
+                        match mem_getter {
+                            false => &mut pg,
+                            true => &mut mg,
+                        }
+                        .push(g);
+                        if mem_getter {
+                            guard_full.set_to(g, false); // getter must be empty!
+                            assign_vals.set_to(g, true); // getter becomes full!
+                            assign_mask.set_to(g, true); // getter memory fullness changes!
+                        }
+                        let was_set = guard_ready.set_to(g, true);
+                        if was_set && transform.is_some() {
+                            // the putter is released only after its getters are done
                             return Err(SynchronousFiring { loc_id: g });
                         }
+                        if was_set {
+                            // oh no! this getter was already involved in the firing
+                            if g == p && mem_putter {
+                                // nevermind. its OK for memory to put and get to itself
+                                guard_full.set_to(g, true); // getter must be full (because its also the putter)!
+                                assign_mask.set_to(g, false); // getter memory fullness DOES NOT change (Full -> Full)!
+                                assign_vals.set_to(g, false);
+                            } else {
+                                return Err(SynchronousFiring { loc_id: g });
+                            }
+                        }
+                    }
+                    Ok(RunAction {
+                        putter: p,
+                        mg,
+                        pg,
+                        transform,
+                    })
+                };
+                match build_action() {
+                    Ok(action) => actions.push(action),
+                    Err(e) => {
+                        diagnostics.note(e, Some(rule_id), Some(action_id));
+                        failed = true;
                     }
                 }
-                actions.push(RunAction { putter: p, mg, pg });
             }
-            let c = Self::max_loc_id(typeless_proto_def);
+            let mut ctx = GuardCtx {
+                rule_id,
+                id_2_type_id,
+                type_id_2_info,
+                spaces,
+                temp_mems: vec![],
+            };
+            let (guard_pred, mut temp_mems) = match self.calc_guard(&mut ctx, &rule_def.guard) {
+                Ok(x) if !failed => x,
+                Ok(_) => continue,
+                Err(e) => {
+                    diagnostics.note(e, Some(rule_id), Option::None);
+                    continue;
+                }
+            };
+            let mut guard_mem = BitSet::default();
+            let guard_pred = match Self::lift_mem_conjuncts(
+                guard_pred.normalized(),
+                &guard_ready,
+                &mut guard_full,
+                &mut guard_mem,
+            ) {
+                Some(f) => f,
+                Option::None => continue, // this rule can never fire
+            };
+            Self::retain_used_temps(&guard_pred, &mut temp_mems);
+            let guard_pred = match guard_pred {
+                Formula::True => Option::None,
+                f => Some(f),
+            };
+
+            let c = typeless_proto_def.loc_kinds.len();
             guard_ready.pad_trailing_zeroes_to_capacity(c);
             guard_full.pad_trailing_zeroes_to_capacity(c);
+            guard_mem.pad_trailing_zeroes_to_capacity(c);
             assign_vals.pad_trailing_zeroes_to_capacity(c);
             assign_mask.pad_trailing_zeroes_to_capacity(c);
-
-            self.define_all_funcs_in::<P>(&rule_def.guard);
-            let (guard_pred, temp_mems) =
-                Self::calc_guard(id_2_type_id, &rule_def.guard, &actions, spaces);
             rules.push(RunRule {
+                rule_id,
+                priority: rule_def.priority,
                 guard_ready,
                 guard_full,
+                guard_mem,
                 temp_mems,
                 guard_pred,
                 assign_vals,
@@ -361,7 +1161,77 @@ impl ProtoBuilder {
                 actions,
             });
         }
-        Ok(rules)
+        rules
+    }
+
+    /// Moves the conjuncts `null(m)` and `!null(m)` of a normalized guard into the
+    /// bitsets which are checked before the guard is evaluated. Cells involved in the
+    /// actions already have their fullness fixed by `guard_full`, so their conjuncts
+    /// are redundant or contradictory. Others are marked in `guard_mem`.
+    /// Returns the remaining guard, or None if the guard can never be satisfied.
+    fn lift_mem_conjuncts(
+        guard: Formula,
+        guard_ready: &BitSet,
+        guard_full: &mut BitSet,
+        guard_mem: &mut BitSet,
+    ) -> Option<Formula> {
+        use Formula::*;
+        let conjuncts = match guard {
+            False => return Option::None,
+            True => vec![],
+            And(fs) => fs,
+            f => vec![f],
+        };
+        let mut rest = vec![];
+        for f in conjuncts {
+            let (m, full) = match &f {
+                MemIsNull(m) => (*m, false),
+                Not(g) => match **g {
+                    MemIsNull(m) => (m, true),
+                    _ => {
+                        rest.push(f);
+                        continue;
+                    }
+                },
+                _ => {
+                    rest.push(f);
+                    continue;
+                }
+            };
+            if guard_ready.test(m) || guard_mem.test(m) {
+                if guard_full.test(m) != full {
+                    return Option::None;
+                }
+            } else {
+                guard_mem.set_to(m, true);
+                guard_full.set_to(m, full);
+            }
+        }
+        Some(match rest.len() {
+            0 => True,
+            1 => rest.pop().unwrap(),
+            _ => And(rest),
+        })
+    }
+
+    /// Discards the temp runnables whose results the (simplified) guard no longer
+    /// reads, directly or as the argument of another function.
+    fn retain_used_temps(guard: &Formula, temp_mems: &mut Vec<TempMemRunnable>) {
+        let mut used: HashSet<LocId> = Default::default();
+        guard.visit_loc_ids(&mut |id| {
+            used.insert(id);
+        });
+        // arguments are computed before the function that reads them
+        for t in temp_mems.iter().rev() {
+            if used.contains(&t.temp_mem_loc_id) {
+                t.func.args().iter().for_each(|a| {
+                    a.visit_loc_ids(&mut |id| {
+                        used.insert(id);
+                    })
+                });
+            }
+        }
+        temp_mems.retain(|t| used.contains(&t.temp_mem_loc_id));
     }
 
     fn define_all_funcs_in<P: Proto>(&mut self, f: &Formula) -> Result<(), ProtoBuildErr> {
@@ -373,111 +1243,406 @@ impl ProtoBuilder {
         };
         let term = |me: &mut Self, t: &Term| match t {
             Term::Boolean(f) => me.define_all_funcs_in::<P>(&f),
-            Term::Value(_) => Ok(()),
+            Term::Value(_) | Term::Const(_) => Ok(()),
         };
         Ok(match f {
-            True | MemIsNull(_) => (),
+            True | False | MemIsNull(_) => (),
             And(fs) | Or(fs) | None(fs) => clos(self, fs)?,
-            ValueEq(a, b) => {
+            Not(f) => self.define_all_funcs_in::<P>(f)?,
+            ValueEq(a, b) | Lt(a, b) | Le(a, b) | Gt(a, b) | Ge(a, b) => {
                 term(self, a)?;
                 term(self, b)?;
             }
             TermVal(a) => term(self, a)?,
-            FuncDeclaration { name, .. } => {
-                let promise = FuncDefPromise {
-                    builder: self,
-                    name,
-                };
-                if P::def_func(name, promise).is_none() {
-                    return Err(ProtoBuildErr::FunctionUndefined { name });
+            FuncDeclaration { name, args } => {
+                for a in args.iter() {
+                    term(self, a)?;
                 }
+                self.define_func_in::<P>(name)?
             }
         })
     }
 
+    fn define_func_in<P: Proto>(&mut self, name: Name) -> Result<(), ProtoBuildErr> {
+        if self.func_defs.contains_key(name) {
+            return Ok(());
+        }
+        let promise = FuncDefPromise {
+            builder: self,
+            name,
+        };
+        match P::def_func(name, promise) {
+            Some(_) => Ok(()),
+            None => Err(ProtoBuildErr::FunctionUndefined { name }),
+        }
+    }
+
+    /// Allocates the temp cell into which the transform `name` writes its result when
+    /// applied to the datum of putter `p`. Returns the runnable and its result type.
+    fn calc_transform<'a>(
+        &'a self,
+        name: Name,
+        p: LocId,
+        p_type: &TypeId,
+        spaces: &mut Vec<Space>,
+    ) -> Result<(TempMemRunnable, &'a TypeInfo), ProtoBuildErr> {
+        use ProtoBuildErr::*;
+        let func_def = self.func_defs.get(name).ok_or(FunctionUndefined { name })?;
+        if func_def.param_info.len() != 1 {
+            return Err(FunctionUsedWithWrongArity {
+                name,
+                used_arity: 1,
+            });
+        }
+        if func_def.param_info[0].type_id != *p_type {
+            return Err(FunctionParamTypeMismatch { name, param: 0 });
+        }
+        let func = TempRuleFunc::Arity1 {
+            // safe: param type was checked against the definition
+            func: unsafe { std::mem::transmute::<fn(), fn(*mut u8, *mut u8)>(func_def.fnptr) },
+            args: [Term::Value(p)],
+        };
+        let temp_mem_loc_id = spaces.new_temp(&func_def.ret_info, Some(p));
+        let t = TempMemRunnable {
+            temp_mem_loc_id,
+            func,
+        };
+        Ok((t, &func_def.ret_info))
+    }
+
+    /// Replaces each function call in the guard with a value read from a fresh temp
+    /// memory cell, returning the runnables which fill these cells before evaluation.
+    /// Literals are likewise replaced by (always full) memory cells holding their value.
     fn calc_guard(
-        _id_2_type_id: &HashMap<LocId, TypeId>,
+        &mut self,
+        ctx: &mut GuardCtx,
         data_constraint: &Formula,
-        _actions: &[RunAction],
-        spaces: &mut Vec<Space>,
-    ) -> (Formula, Vec<TempMemRunnable>) {
-        let f = data_constraint.clone();
-        let t = vec![];
-        (f, t)
+    ) -> Result<(Formula, Vec<TempMemRunnable>), ProtoBuildErr> {
+        let f = self.runnify_formula(ctx, data_constraint)?;
+        Ok((f, std::mem::take(&mut ctx.temp_mems)))
     }
 
-    fn runnify_formulae<P: Proto>(
-        &self,
+    fn runnify_formulae(
+        &mut self,
+        ctx: &mut GuardCtx,
         f: &[Formula],
-        spaces: &mut Vec<Space>,
-        temp_mems: &mut Vec<TempRuleFunc>,
     ) -> Result<Vec<Formula>, ProtoBuildErr> {
-        f.iter()
-            .map(|e| self.runnify_formula::<P>(e, spaces, temp_mems))
-            .collect()
+        f.iter().map(|e| self.runnify_formula(ctx, e)).collect()
+    }
+
+    /// The type of the given term, if it can be determined without context.
+    fn term_type(&self, ctx: &GuardCtx, t: &Term) -> Result<Option<Arc<TypeInfo>>, ProtoBuildErr> {
+        Ok(match t {
+            Term::Boolean(_) => Some(Arc::new(*TypeInfo::BOOL_TYPE_INFO)),
+            Term::Value(loc_id) => {
+                let type_id = ctx
+                    .id_2_type_id
+                    .get(loc_id)
+                    .ok_or(ProtoBuildErr::UnknownType { loc_id: *loc_id })?;
+                Some(ctx.type_id_2_info.get(type_id).unwrap().clone())
+            }
+            Term::Const(literal) => self.consts.get(literal).map(|(_, info)| info.clone()),
+        })
+    }
+
+    /// `expected` is the type the context requires of this term, if known.
+    fn runnify_term(
+        &mut self,
+        ctx: &mut GuardCtx,
+        t: &Term,
+        expected: Option<&Arc<TypeInfo>>,
+    ) -> Result<Term, ProtoBuildErr> {
+        use ProtoBuildErr::*;
+        Ok(match t {
+            Term::Boolean(f) => Term::Boolean(Box::new(self.runnify_formula(ctx, f)?)),
+            Term::Value(loc_id) => Term::Value(*loc_id),
+            Term::Const(literal) => {
+                let rule_id = ctx.rule_id;
+                let info = match (self.term_type(ctx, t)?, expected) {
+                    (Some(info), Some(e)) if info.type_id != e.type_id => {
+                        return Err(GuardTypeMismatch { rule_id })
+                    }
+                    (Some(info), _) => info,
+                    (Option::None, Some(e)) => e.clone(),
+                    (Option::None, Option::None) => return Err(ConstTypeUnknown { rule_id }),
+                };
+                Term::Value(self.const_loc(ctx, literal, &info)?)
+            }
+        })
+    }
+
+    /// Returns a memory cell holding the value of the given literal as the given type.
+    fn const_loc(
+        &mut self,
+        ctx: &mut GuardCtx,
+        literal: &str,
+        info: &Arc<TypeInfo>,
+    ) -> Result<LocId, ProtoBuildErr> {
+        let key = (literal.to_owned(), info.type_id);
+        if let Some(&loc_id) = self.const_locs.get(&key) {
+            return Ok(loc_id);
+        }
+        let ptr = match self.consts.get(literal) {
+            Some(&(ptr, _)) => ptr,
+            Option::None => unsafe {
+                let ptr = self.mem_storage.alloc(info);
+                if !info.funcs.parse.execute(literal, ptr) {
+                    self.mem_storage.forget_inside(ptr, info);
+                    return Err(ProtoBuildErr::ConstUnparsable {
+                        rule_id: ctx.rule_id,
+                    });
+                }
+                ptr
+            },
+        };
+        let loc_id = ctx
+            .spaces
+            .new_space(Space::Memo(MemoSpace::new(ptr, info.clone())));
+        self.const_locs.insert(key, loc_id);
+        Ok(loc_id)
+    }
+
+    /// Runnifies both operands of a comparison, which must have the same type.
+    /// Literals take the type of the other operand.
+    fn runnify_comparison(
+        &mut self,
+        ctx: &mut GuardCtx,
+        a: &Term,
+        b: &Term,
+    ) -> Result<(Term, Term, Arc<TypeInfo>), ProtoBuildErr> {
+        use ProtoBuildErr::*;
+        let rule_id = ctx.rule_id;
+        let info = match (self.term_type(ctx, a)?, self.term_type(ctx, b)?) {
+            (Some(x), Some(y)) if x.type_id != y.type_id => {
+                return Err(GuardTypeMismatch { rule_id })
+            }
+            (Some(x), _) | (_, Some(x)) => x,
+            (Option::None, Option::None) => return Err(ConstTypeUnknown { rule_id }),
+        };
+        let a = self.runnify_term(ctx, a, Some(&info))?;
+        let b = self.runnify_term(ctx, b, Some(&info))?;
+        Ok((a, b, info))
+    }
+
+    fn runnify_ordering(
+        &mut self,
+        ctx: &mut GuardCtx,
+        a: &Term,
+        b: &Term,
+        op: fn(Term, Term) -> Formula,
+    ) -> Result<Formula, ProtoBuildErr> {
+        let (a, b, info) = self.runnify_comparison(ctx, a, b)?;
+        if !info.funcs.partial_ord.is_defined() {
+            return Err(ProtoBuildErr::GuardOpUndefined {
+                rule_id: ctx.rule_id,
+            });
+        }
+        Ok(op(a, b))
     }
 
-    fn runnify_formula<P: Proto>(
-        &self,
+    fn runnify_formula(
+        &mut self,
+        ctx: &mut GuardCtx,
         f: &Formula,
-        spaces: &mut Vec<Space>,
-        temp_mems: &mut Vec<TempRuleFunc>,
     ) -> Result<Formula, ProtoBuildErr> {
         use Formula::*;
         use ProtoBuildErr::*;
-        let mut r = |fs| self.runnify_formulae::<P>(fs, spaces, temp_mems);
         Ok(match f {
-            True | ValueEq(_, _) | MemIsNull(_) => f.clone(), // stop condtion
-            TermVal(t) => match t {
-                Term::Boolean(f) => TermVal(Term::Boolean(Box::new(
-                    self.runnify_formula::<P>(f, spaces, temp_mems)?,
-                ))),
-                Term::Value(_) => f.clone(),
-            },
-            And(fs) => And(r(fs)?),
-            Or(fs) => Or(r(fs)?),
-            None(fs) => None(r(fs)?),
+            True | False | MemIsNull(_) => f.clone(), // stop condtion
+            TermVal(t) => {
+                let bool_info = Arc::new(*TypeInfo::BOOL_TYPE_INFO);
+                if let Some(info) = self.term_type(ctx, t)? {
+                    if info.type_id != bool_info.type_id {
+                        return Err(GuardTypeMismatch {
+                            rule_id: ctx.rule_id,
+                        });
+                    }
+                }
+                TermVal(self.runnify_term(ctx, t, Some(&bool_info))?)
+            }
+            ValueEq(a, b) => {
+                let (a, b, info) = self.runnify_comparison(ctx, a, b)?;
+                if !info.funcs.partial_eq.is_defined() {
+                    return Err(GuardOpUndefined {
+                        rule_id: ctx.rule_id,
+                    });
+                }
+                ValueEq(a, b)
+            }
+            Lt(a, b) => self.runnify_ordering(ctx, a, b, Lt)?,
+            Le(a, b) => self.runnify_ordering(ctx, a, b, Le)?,
+            Gt(a, b) => self.runnify_ordering(ctx, a, b, Gt)?,
+            Ge(a, b) => self.runnify_ordering(ctx, a, b, Ge)?,
+            Not(f) => Not(Box::new(self.runnify_formula(ctx, f)?)),
+            And(fs) => And(self.runnify_formulae(ctx, fs)?),
+            Or(fs) => Or(self.runnify_formulae(ctx, fs)?),
+            None(fs) => None(self.runnify_formulae(ctx, fs)?),
             FuncDeclaration { name, args } => {
                 // 1 ensure the function has been defined by user
-                if let Some(func_def) = self.func_defs.get(name) {
-                    // 2 make sure the NUMBER of params corresponds with the definition
-                    if args.len() != func_def.param_info.len() {
-                        return Err(FunctionUsedWithWrongArity {
-                            name,
-                            used_arity: args.len(),
-                        });
+                let func_def = self.func_defs.get(name).ok_or(FunctionUndefined { name })?;
+                if func_def.ret_info.type_id != TypeId::of::<bool>() {
+                    return Err(FunctionReturnsNonBool { name });
+                }
+                // 2 make sure the NUMBER of params corresponds with the definition
+                if args.len() != func_def.param_info.len() {
+                    return Err(FunctionUsedWithWrongArity {
+                        name,
+                        used_arity: args.len(),
+                    });
+                }
+                let (ret_info, param_info, fnptr) = (
+                    func_def.ret_info.clone(),
+                    func_def.param_info.clone(),
+                    func_def.fnptr,
+                );
+                // 3 make sure each TYPE of param matches what is expected
+                for (param, (a, p)) in args.iter().zip(param_info.iter()).enumerate() {
+                    if let Some(info) = self.term_type(ctx, a)? {
+                        if info.type_id != p.type_id {
+                            return Err(FunctionParamTypeMismatch { name, param });
+                        }
                     }
-                    // 3 make sure each TYPE of param matches what is expected
-                    // TODO walk down them, destructure the term and check types
-
-                    // fix all downstream params
-                    let fixed_subterms: Vec<Term> = args
-                        .iter()
-                        .zip(func_def.param_info.iter())
-                        .map(|(a, p)| {
-                            Ok(match a {
-                                Term::Boolean(f) => Term::Boolean(Box::new(
-                                    self.runnify_formula::<P>(f, spaces, temp_mems)?,
-                                )),
-                                Term::Value(loc_id) => Term::Value(*loc_id),
-                            })
-                        })
-                        .collect::<Result<Vec<Term>, ProtoBuildErr>>()?;
-                    unimplemented!()
-                } else {
-                    return Err(FunctionUndefined { name });
                 }
+
+                // fix all downstream params. their temps are computed first
+                let mut fixed_subterms = args
+                    .iter()
+                    .zip(param_info.iter())
+                    .map(|(a, p)| self.runnify_term(ctx, a, Some(p)))
+                    .collect::<Result<Vec<Term>, ProtoBuildErr>>()?
+                    .into_iter();
+                let mut arg = || fixed_subterms.next().unwrap();
+                use std::mem::transmute;
+                use TempRuleFunc::*;
+                // safe: param and return types were checked against the definition
+                let func = unsafe {
+                    match param_info.len() {
+                        0 => Arity0 {
+                            func: transmute(fnptr),
+                        },
+                        1 => Arity1 {
+                            func: transmute(fnptr),
+                            args: [arg()],
+                        },
+                        2 => Arity2 {
+                            func: transmute(fnptr),
+                            args: [arg(), arg()],
+                        },
+                        3 => Arity3 {
+                            func: transmute(fnptr),
+                            args: [arg(), arg(), arg()],
+                        },
+                        _ => unreachable!("functions have at most 3 params"),
+                    }
+                };
+                let temp_mem_loc_id = ctx.spaces.new_temp(&ret_info, Option::None);
+                ctx.temp_mems.push(TempMemRunnable {
+                    temp_mem_loc_id,
+                    func,
+                });
+                TermVal(Term::Value(temp_mem_loc_id))
             }
         })
     }
 }
 
+/// Counterpart to `ProtoBuilder::finish` for protocols that are assembled at
+/// runtime rather than defined by some `Proto` type. The definition is owned,
+/// and types, initial memory values and functions are provided explicitly.
+/// ```ignore
+/// let mut b = DynProtoBuilder::new(def, loc_types);
+/// b.init_memory(3, 42u32)?;
+/// b.def_func("is_even").define_arity1(is_even);
+/// let p: Arc<ProtoAll> = b.build()?;
+/// ```
+pub struct DynProtoBuilder {
+    def: TypelessProtoDef,
+    loc_types: HashMap<LocId, TypeInfo>,
+    builder: ProtoBuilder,
+}
+impl DynProtoBuilder {
+    pub fn new(def: TypelessProtoDef, loc_types: HashMap<LocId, TypeInfo>) -> Self {
+        Self {
+            def,
+            loc_types,
+            builder: ProtoBuilder::new(),
+        }
+    }
+    pub fn def(&self) -> &TypelessProtoDef {
+        &self.def
+    }
+    /// Provides the initial value of a `MemInitialized` memory cell.
+    pub fn init_memory<T: 'static>(&mut self, loc_id: LocId, t: T) -> Result<(), ProtoBuildErr> {
+        let type_id_expected = self
+            .loc_types
+            .get(&loc_id)
+            .ok_or(ProtoBuildErr::UnknownType { loc_id })?
+            .type_id;
+        if self.def.loc_kinds.get(&loc_id) != Some(&LocKind::MemInitialized) {
+            return Err(ProtoBuildErr::MemoryFillNotInitialized { loc_id });
+        }
+        let promise = MemFillPromise {
+            type_id_expected,
+            loc_id,
+            builder: &mut self.builder,
+        };
+        match promise.fill_memory(t) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(ProtoBuildErr::MemoryFillTypeMismatch { loc_id }),
+        }
+    }
+    /// Provides the value of a literal occurring in guards. See `ProtoBuilder::define_const`.
+    pub fn def_const<T: 'static>(&mut self, literal: &str, t: T) {
+        self.builder.define_const(literal, t)
+    }
+    /// Provides the definition of a function used in the guards of rules.
+    pub fn def_func(&mut self, name: Name) -> FuncDefPromise<'_> {
+        FuncDefPromise {
+            builder: &mut self.builder,
+            name,
+        }
+    }
+    /// Chooses among the rules that can fire at once. See `ProtoBuilder::rule_selector`.
+    pub fn rule_selector(&mut self, selector: Box<dyn RuleSelector>) {
+        self.builder.rule_selector(selector)
+    }
+    /// Fires rules on a dedicated thread. See `ProtoBuilder::coordinator_thread`.
+    pub fn coordinator_thread(&mut self) {
+        self.builder.coordinator_thread()
+    }
+    pub fn build(self) -> Result<Arc<ProtoAll>, ProtoBuildErr> {
+        let Self {
+            def,
+            loc_types,
+            builder,
+        } = self;
+        let p = builder.finish_def(&def, |loc_id| loc_types.get(&loc_id).copied())?;
+        Ok(Arc::new(p))
+    }
+    /// Like `build`, but collects all errors. See `ProtoBuilder::finish_diagnosed`.
+    pub fn build_diagnosed(self) -> Result<Arc<ProtoAll>, Diagnostics> {
+        let Self {
+            def,
+            loc_types,
+            builder,
+        } = self;
+        let diagnostics = Diagnostics::new(&def);
+        let loc_type = |loc_id| loc_types.get(&loc_id).copied();
+        let p = builder.finish_def_diagnosed(&def, loc_type, diagnostics)?;
+        Ok(Arc::new(p))
+    }
+}
+
 trait TempAllocator {
-    fn new_temp(&mut self, type_info: &Arc<TypeInfo>) -> LocId;
+    fn new_temp(&mut self, type_info: &Arc<TypeInfo>, source: Option<LocId>) -> LocId;
+    fn new_space(&mut self, t: Space) -> LocId;
 }
 impl TempAllocator for Vec<Space> {
-    fn new_temp(&mut self, type_info: &Arc<TypeInfo>) -> LocId {
-        let t = Space::Temp(TempSpace::new(type_info.clone()));
+    fn new_temp(&mut self, type_info: &Arc<TypeInfo>, source: Option<LocId>) -> LocId {
+        self.new_space(Space::Temp(TempSpace::new(type_info.clone(), source)))
+    }
+    /// Places the space in the first unused slot, or appends it.
+    fn new_space(&mut self, t: Space) -> LocId {
         for (i, s) in self.iter_mut().enumerate() {
             match s {
                 Space::Unused => {
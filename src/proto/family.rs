@@ -0,0 +1,224 @@
+//! Templates for protocols with families of ports, whose sizes are only known at runtime.
+//! The ports of a family `f` of size `n` are named `f[0]` to `f[n-1]`. All of them are
+//! claimed at once with `HasUnclaimedPorts::claim_putters` or `claim_getters`.
+//! ```ignore
+//! let p = family::instantiate::<u32>(family::merger(workers))?;
+//! let ins: Vec<Putter<u32>> = p.claim_putters("in")?;
+//! let out: Getter<u32> = p.claim_by_name("out").try_into()?;
+//! ```
+
+use super::*;
+use crate::proto::definition::{ActionDef, BehaviourDef, DynProtoBuilder, RuleDef};
+
+/// The name of the port at `index` of the given family.
+pub fn indexed_name(family: &str, index: usize) -> String {
+    format!("{}[{}]", family, index)
+}
+
+/// The index of the port of the given family with the given name, if it is one.
+pub(crate) fn index_in(family: &str, name: &str) -> Option<usize> {
+    let index = name
+        .strip_prefix(family)?
+        .strip_prefix('[')?
+        .strip_suffix(']')?;
+    index.parse().ok()
+}
+
+/// Assembles a `TypelessProtoDef`, assigning LocIds to named locations
+/// and families of locations in order of declaration.
+#[derive(Debug, Clone)]
+pub struct Template {
+    def: TypelessProtoDef,
+}
+impl Default for Template {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Template {
+    pub fn new() -> Self {
+        Self {
+            def: TypelessProtoDef {
+                behaviour: BehaviourDef { rules: vec![] },
+                loc_kinds: Default::default(),
+                loc_names: Default::default(),
+                families: Default::default(),
+            },
+        }
+    }
+    pub fn loc(&mut self, name: &str, kind: LocKind) -> LocId {
+        let id = self.def.loc_kinds.len();
+        self.def.loc_kinds.insert(id, kind);
+        self.def.loc_names.insert(id, name.to_owned());
+        id
+    }
+    pub fn family(&mut self, name: &str, kind: LocKind, n: usize) -> Vec<LocId> {
+        self.def.families.insert(name.to_owned());
+        (0..n)
+            .map(|i| self.loc(&indexed_name(name, i), kind))
+            .collect()
+    }
+    pub fn rule(&mut self, guard: Formula, actions: Vec<ActionDef>) {
+        self.def.behaviour.rules.push(RuleDef {
+            guard,
+            actions,
+            priority: 0,
+        });
+    }
+    pub fn finish(self) -> TypelessProtoDef {
+        self.def
+    }
+}
+
+fn action(putter: LocId, getters: Vec<LocId>) -> ActionDef {
+    ActionDef {
+        putter,
+        getters,
+        transform: None,
+    }
+}
+
+/// Passes the data of any of the putters `in[0]`..`in[n-1]` to the getter `out`, one at a time.
+pub fn merger(n: usize) -> TypelessProtoDef {
+    let mut t = Template::new();
+    let ins = t.family("in", LocKind::PortPutter, n);
+    let out = t.loc("out", LocKind::PortGetter);
+    for i in ins {
+        t.rule(Formula::True, vec![action(i, vec![out])]);
+    }
+    t.finish()
+}
+
+/// Passes the data of the putter `in` to exactly one of the getters `out[0]`..`out[n-1]`.
+pub fn router(n: usize) -> TypelessProtoDef {
+    let mut t = Template::new();
+    let i = t.loc("in", LocKind::PortPutter);
+    for o in t.family("out", LocKind::PortGetter, n) {
+        t.rule(Formula::True, vec![action(i, vec![o])]);
+    }
+    t.finish()
+}
+
+/// Synchronously passes (clones of) the data of the putter `in` to all
+/// of the getters `out[0]`..`out[n-1]`.
+pub fn replicator(n: usize) -> TypelessProtoDef {
+    let mut t = Template::new();
+    let i = t.loc("in", LocKind::PortPutter);
+    let outs = t.family("out", LocKind::PortGetter, n);
+    t.rule(Formula::True, vec![action(i, outs)]);
+    t.finish()
+}
+
+/// Builds a protocol whose locations all hold data of type `T`,
+/// such as those generated by the templates of this module.
+pub fn instantiate<T: 'static>(def: TypelessProtoDef) -> Result<Arc<ProtoAll>, ProtoBuildErr> {
+    let loc_types = def
+        .loc_kinds
+        .keys()
+        .map(|&id| (id, TypeInfo::new::<T>()))
+        .collect();
+    DynProtoBuilder::new(def, loc_types).build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::traits::HasUnclaimedPorts;
+    use std::convert::TryInto;
+
+    #[test]
+    fn template_ids() {
+        let def = router(3);
+        assert_eq!(def.loc_names[&0], "in");
+        assert_eq!(def.loc_names[&3], "out[2]");
+        assert_eq!(def.behaviour.rules.len(), 3);
+        assert_eq!(merger(0).behaviour.rules.len(), 0);
+    }
+
+    #[test]
+    fn merger_workers() {
+        for &n in [1, 4, 9].iter() {
+            let p = instantiate::<usize>(merger(n)).unwrap();
+            let ins: Vec<Putter<usize>> = p.claim_putters("in").unwrap();
+            assert_eq!(ins.len(), n);
+            let mut out: Getter<usize> = p.claim_by_name("out").try_into().unwrap();
+            crossbeam::scope(|s| {
+                for (i, mut putter) in ins.into_iter().enumerate() {
+                    s.spawn(move |_| assert!(putter.put(i).is_none()));
+                }
+                let mut got: Vec<usize> = (0..n).map(|_| out.get()).collect();
+                got.sort();
+                assert_eq!(got, (0..n).collect::<Vec<_>>());
+            })
+            .expect("Crashed!");
+        }
+    }
+
+    #[test]
+    fn router_workers() {
+        let p = instantiate::<u32>(router(3)).unwrap();
+        let mut i: Putter<u32> = p.claim_by_name("in").try_into().unwrap();
+        let outs: Vec<Getter<u32>> = p.claim_getters("out").unwrap();
+        crossbeam::scope(|s| {
+            let handles: Vec<_> = outs
+                .into_iter()
+                .map(|mut getter| s.spawn(move |_| getter.get()))
+                .collect();
+            for x in 0..3 {
+                assert!(i.put(x).is_none());
+            }
+            let mut got: Vec<u32> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+            got.sort();
+            assert_eq!(got, vec![0, 1, 2]);
+        })
+        .expect("Crashed!");
+    }
+
+    #[test]
+    fn replicator_workers() {
+        let p = instantiate::<String>(replicator(4)).unwrap();
+        let mut i: Putter<String> = p.claim_by_name("in").try_into().unwrap();
+        let outs: Vec<Getter<String>> = p.claim_getters("out").unwrap();
+        crossbeam::scope(|s| {
+            for mut getter in outs {
+                s.spawn(move |_| assert_eq!(getter.get(), "hello"));
+            }
+            assert!(i.put("hello".to_owned()).is_none());
+        })
+        .expect("Crashed!");
+    }
+
+    #[test]
+    fn claim_empty_family() {
+        let p = instantiate::<u32>(merger(0)).unwrap();
+        assert_eq!(p.family_ids("in"), Some(vec![]));
+        assert!(p.claim_putters::<u32>("in").unwrap().is_empty());
+        assert_eq!(p.family_ids("out"), None);
+    }
+
+    #[test]
+    fn claim_family_errors() {
+        let p = instantiate::<u32>(merger(2)).unwrap();
+        assert_eq!(p.family_ids("in"), Some(vec![0, 1]));
+        match p.claim_getters::<u32>("in") {
+            Err(ClaimResult::GotPutter(_)) => {}
+            _ => panic!("claimed putters as getters"),
+        }
+        match p.claim_putters::<u8>("in") {
+            Err(ClaimResult::TypeMismatch { loc_id: 0, .. }) => {}
+            _ => panic!("claimed with the wrong type"),
+        }
+        match p.claim_putters::<u32>("nope") {
+            Err(ClaimResult::UnknownName(name)) => assert_eq!(name, "nope"),
+            _ => panic!("claimed an unknown family"),
+        }
+        // failed claims leave the family unclaimed
+        let _ins: Vec<Putter<u32>> = p.claim_putters("in").unwrap();
+        match p.claim_putters::<u32>("in") {
+            Err(ClaimResult::NotUnclaimed { loc_id: 0, name }) => {
+                assert_eq!(name.as_deref(), Some("in[0]"))
+            }
+            _ => panic!("claimed twice"),
+        }
+    }
+}
@@ -3,7 +3,8 @@ use ClaimResult as Cr;
 use GroupAddError as Gae;
 
 pub struct PortGroup {
-    maybe_proto: Option<Arc<ProtoAll>>,
+    // the instance and region that all members share
+    maybe_proto: Option<(Arc<ProtoAll>, usize)>,
     members: BitSet,
     member_info: HashMap<LocId, PortInfo>,
     members_indexed: Vec<LocId>,
@@ -37,15 +38,14 @@ impl PortGroup {
         id: LocId,
     ) -> Result<Grouped<D, Putter<T>>, GroupAddError> {
         let m = match handle.claim::<T>(id) {
-            Cr::GotGetter(_) => return Err(Gae::GotGetterExpectedPutter),
             Cr::GotPutter(p) => p,
-            Cr::NotUnclaimed => return Err(Gae::NotUnclaimed),
-            Cr::TypeMismatch => return Err(Gae::TypeMismatch),
+            Cr::GotGetter(_) => {
+                let name = handle.loc_name(id).map(String::from);
+                return Err(Gae::GotGetterExpectedPutter { loc_id: id, name });
+            }
+            failed => return Err(Gae::from_failed_claim(failed)),
         };
-        let p = self.maybe_proto.get_or_insert_with(|| handle.clone());
-        if !Arc::ptr_eq(p, &m.c.p) {
-            return Err(Gae::DifferentProtoInstance);
-        }
+        self.admit(handle, &m.c)?;
         Ok(Grouped::from_putter(m))
     }
 
@@ -56,21 +56,38 @@ impl PortGroup {
     ) -> Result<Grouped<D, Getter<T>>, GroupAddError> {
         let m = match handle.claim::<T>(id) {
             Cr::GotGetter(g) => g,
-            Cr::GotPutter(_) => return Err(Gae::GotPutterExpectedGetter),
-            Cr::NotUnclaimed => return Err(Gae::NotUnclaimed),
-            Cr::TypeMismatch => return Err(Gae::TypeMismatch),
+            Cr::GotPutter(_) => {
+                let name = handle.loc_name(id).map(String::from);
+                return Err(Gae::GotPutterExpectedGetter { loc_id: id, name });
+            }
+            failed => return Err(Gae::from_failed_claim(failed)),
         };
-        let p = self.maybe_proto.get_or_insert_with(|| handle.clone());
-        if !Arc::ptr_eq(p, &m.c.p) {
+        self.admit(handle, &m.c)?;
+        Ok(Grouped::from_getter(m))
+    }
+
+    /// Fails unless the claimed port is of the instance and region of the other members.
+    /// A group takes those of its first member. The whole group is locked with one region.
+    fn admit(&mut self, handle: &ProtoHandle, c: &PortCommon) -> Result<(), GroupAddError> {
+        let region = handle.r.loc_region[c.id];
+        let (p, group_region) = self
+            .maybe_proto
+            .get_or_insert_with(|| (handle.clone(), region));
+        if !Arc::ptr_eq(p, &c.p) {
             return Err(Gae::DifferentProtoInstance);
         }
-        Ok(Grouped::from_getter(m))
+        if *group_region != region {
+            let loc_id = handle.r.id_map.external(c.id);
+            let name = handle.loc_name(loc_id).map(String::from);
+            return Err(Gae::DifferentRegion { loc_id, name });
+        }
+        Ok(())
     }
 
     pub fn deliberate(&mut self) -> (LocId, LockedProto) {
         // step 1: prepare for callback (does not require lock)
         let mut sel = crossbeam::channel::Select::new();
-        let proto: &ProtoHandle = self.maybe_proto.as_ref().expect("NO PROTO??");
+        let (proto, region) = self.maybe_proto.as_ref().expect("NO PROTO??");
         for (expected_index, &id) in self.members_indexed.iter().enumerate() {
             let r = match proto.r.get_space(id) {
                 Some(Space::PoPu(space)) => &space.dropbox.r,
@@ -82,7 +99,7 @@ impl PortGroup {
         }
 
         // step 2: lock proto and batch-flag readiness and tentativeness
-        let mut w = proto.w.lock();
+        let mut w = proto.regions[*region].lock();
         let ProtoW {
             ready_tentative,
             active,
@@ -97,6 +114,9 @@ impl PortGroup {
             *ready |= members;
             *tenta |= members;
         }
+        for id in self.members.iter_sparse() {
+            w.mark_rules_of(&proto.r, id);
+        }
         drop(w);
 
         // step 3: await callback
@@ -107,7 +127,7 @@ impl PortGroup {
             .expect("UNEXPECTED INDEX");
 
         // step 4: protocol is committed. UNSET readiness and tentativeness again.
-        let mut w = proto.w.lock();
+        let mut w = proto.regions[*region].lock();
         let ProtoW {
             ready_tentative,
             active,
@@ -129,16 +149,17 @@ impl PortGroup {
             w,
             members: &self.members,
         };
-        (id, locked_proto)
+        (proto.r.id_map.external(id), locked_proto)
     }
 }
 impl Drop for PortGroup {
     fn drop(&mut self) {
-        if let Some(ref proto) = self.maybe_proto {
+        if let Some((ref proto, region)) = self.maybe_proto {
             // UNCLAIM the contained ports
-            let mut w = proto.w.lock();
+            let mut w = proto.regions[region].lock();
             for (&id, &info) in self.member_info.iter() {
                 w.unclaimed_ports.insert(id, info);
+                w.close_port(&proto.r, id);
             }
         } else {
             assert!(self.members_indexed.is_empty());
@@ -173,11 +194,39 @@ impl Drop for LockedProto<'_> {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+/// Failures carry the name of the port, if it has one.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum GroupAddError {
     DifferentProtoInstance,
-    GotGetterExpectedPutter,
-    GotPutterExpectedGetter,
-    NotUnclaimed,
-    TypeMismatch,
+    /// The port is in another region of the protocol than the other members.
+    /// See `ProtoAll::num_regions`.
+    DifferentRegion {
+        loc_id: LocId,
+        name: Option<String>,
+    },
+    GotGetterExpectedPutter {
+        loc_id: LocId,
+        name: Option<String>,
+    },
+    GotPutterExpectedGetter {
+        loc_id: LocId,
+        name: Option<String>,
+    },
+    NotUnclaimed {
+        loc_id: LocId,
+        name: Option<String>,
+    },
+    TypeMismatch {
+        loc_id: LocId,
+        name: Option<String>,
+    },
+}
+impl GroupAddError {
+    fn from_failed_claim<T>(failed: ClaimResult<T>) -> Self {
+        match failed {
+            Cr::NotUnclaimed { loc_id, name } => Gae::NotUnclaimed { loc_id, name },
+            Cr::TypeMismatch { loc_id, name } => Gae::TypeMismatch { loc_id, name },
+            Cr::GotGetter(_) | Cr::GotPutter(_) | Cr::UnknownName(_) => unreachable!(),
+        }
+    }
 }
@@ -0,0 +1,256 @@
+use super::*;
+use crate::{proto::definition::RuleDef, RuleId};
+use hashbrown::HashSet;
+
+/// Something suspicious about a protocol definition that is nevertheless legal.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LintWarning {
+    /// No reachable configuration of memory cells satisfies this rule's
+    /// requirements on the fullness of its memory cells.
+    DeadRule { rule_id: RuleId },
+    /// This memory cell appears in no rule.
+    UnusedLoc { loc_id: LocId },
+    /// Some rule fills this memory cell, but no rule that can fire empties it.
+    MemNeverEmptied { loc_id: LocId },
+    /// This port is involved in no rule's actions, so operations on it block forever.
+    PortInNoRule { loc_id: LocId },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LintReport {
+    pub warnings: Vec<LintWarning>,
+}
+impl LintReport {
+    pub fn is_clean(&self) -> bool {
+        self.warnings.is_empty()
+    }
+}
+
+/// Fullness of a memory cell. Indexes `Reachable`.
+const EMPTY: usize = 0;
+const FULL: usize = 1;
+type Reachable = [bool; 2];
+
+/// Requirements and effects of one rule on its memory cells.
+struct MemEffects {
+    /// (cell, required fullness). The same cell may appear with conflicting requirements.
+    requires: Vec<(LocId, usize)>,
+    /// (cell, fullness after firing)
+    becomes: Vec<(LocId, usize)>,
+    /// The guard is always false.
+    never: bool,
+}
+impl MemEffects {
+    fn of(def: &TypelessProtoDef, rule: &RuleDef) -> Self {
+        let is_mem = |id: &LocId| def.loc_kinds.get(id).map(|k| k.is_mem()).unwrap_or(false);
+        let mut requires = vec![];
+        let mut becomes = vec![];
+        for action in rule.actions.iter() {
+            let p = action.putter;
+            if is_mem(&p) {
+                requires.push((p, FULL));
+                if !action.getters.contains(&p) {
+                    becomes.push((p, EMPTY));
+                }
+            }
+            for g in action.getters.iter().filter(|&&g| g != p && is_mem(&g)) {
+                requires.push((*g, EMPTY));
+                becomes.push((*g, FULL));
+            }
+        }
+        let guard = rule.guard.clone().normalized();
+        Self::guard_requires(&guard, &mut requires);
+        // only memory cells have a reachable fullness. other ids in `null` are skipped
+        requires.retain(|(id, _)| is_mem(id));
+        let never = guard == Formula::False;
+        Self {
+            requires,
+            becomes,
+            never,
+        }
+    }
+
+    /// Collects the fullness requirements that the guard makes unconditionally.
+    fn guard_requires(f: &Formula, requires: &mut Vec<(LocId, usize)>) {
+        match f {
+            Formula::And(fs) => fs.iter().for_each(|f| Self::guard_requires(f, requires)),
+            Formula::MemIsNull(m) => requires.push((*m, EMPTY)),
+            Formula::Not(f) => {
+                if let Formula::MemIsNull(m) = &**f {
+                    requires.push((*m, FULL));
+                }
+            }
+            Formula::None(fs) => {
+                for f in fs.iter() {
+                    if let Formula::MemIsNull(m) = f {
+                        requires.push((*m, FULL));
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn satisfiable(&self, reachable: &HashMap<LocId, Reachable>) -> bool {
+        let consistent = self
+            .requires
+            .iter()
+            .all(|&(m, x)| self.requires.iter().all(|&(m2, x2)| m != m2 || x == x2));
+        !self.never
+            && consistent
+            && self
+                .requires
+                .iter()
+                .all(|(m, x)| reachable.get(m).map(|r| r[*x]).unwrap_or(false))
+    }
+}
+
+/// Analyses a protocol definition for things that are legal but likely mistakes.
+/// The analysis over-approximates the reachable states by considering each memory cell
+/// in isolation, so reported dead rules are certainly dead, but not every dead rule is reported.
+pub fn lint(def: &TypelessProtoDef) -> LintReport {
+    let rules = &def.behaviour.rules;
+    let effects: Vec<MemEffects> = rules.iter().map(|r| MemEffects::of(def, r)).collect();
+
+    // fixpoint over the reachable fullness of each memory cell
+    let mut reachable: HashMap<LocId, Reachable> = def
+        .loc_kinds
+        .iter()
+        .filter_map(|(&id, kind)| match kind {
+            LocKind::MemInitialized => Some((id, [false, true])),
+            LocKind::MemUninitialized => Some((id, [true, false])),
+            _ => None,
+        })
+        .collect();
+    let mut live = vec![false; rules.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (rule_id, e) in effects.iter().enumerate() {
+            if live[rule_id] || !e.satisfiable(&reachable) {
+                continue;
+            }
+            live[rule_id] = true;
+            changed = true;
+            for &(m, x) in e.becomes.iter() {
+                if let Some(r) = reachable.get_mut(&m) {
+                    r[x] = true;
+                }
+            }
+        }
+    }
+
+    let mut warnings: Vec<LintWarning> = live
+        .iter()
+        .enumerate()
+        .filter(|(_, &l)| !l)
+        .map(|(rule_id, _)| LintWarning::DeadRule { rule_id })
+        .collect();
+
+    let mut in_guards: HashSet<LocId> = Default::default();
+    let mut in_actions: HashSet<LocId> = Default::default();
+    for rule in rules.iter() {
+        rule.guard.visit_loc_ids(&mut |id| {
+            in_guards.insert(id);
+        });
+        for action in rule.actions.iter() {
+            in_actions.insert(action.putter);
+            in_actions.extend(action.getters.iter().copied());
+        }
+    }
+    let mut loc_ids: Vec<LocId> = def.loc_kinds.keys().copied().collect();
+    loc_ids.sort();
+    for loc_id in loc_ids {
+        if def.loc_kinds[&loc_id].is_mem() {
+            if !in_actions.contains(&loc_id) && !in_guards.contains(&loc_id) {
+                warnings.push(LintWarning::UnusedLoc { loc_id });
+                continue;
+            }
+            let live_effects = || effects.iter().zip(live.iter()).filter(|(_, &l)| l);
+            let filled = live_effects().any(|(e, _)| e.becomes.contains(&(loc_id, FULL)));
+            let emptied = live_effects().any(|(e, _)| e.becomes.contains(&(loc_id, EMPTY)));
+            if filled && !emptied {
+                warnings.push(LintWarning::MemNeverEmptied { loc_id });
+            }
+        } else if !in_actions.contains(&loc_id) {
+            warnings.push(LintWarning::PortInNoRule { loc_id });
+        }
+    }
+    LintReport { warnings }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::parse::parse_proto;
+    use LintWarning::*;
+
+    fn lint_src(src: &str) -> Vec<LintWarning> {
+        lint(&parse_proto(src).unwrap().def).warnings
+    }
+
+    #[test]
+    fn fifo_is_clean() {
+        let w = lint_src(
+            "protocol Fifo {
+                putter a: T; getter b: T; mem m: T;
+                rule true { a => m; }
+                rule true { m => b; }
+            }",
+        );
+        assert_eq!(w, vec![]);
+    }
+
+    #[test]
+    fn dead_rules() {
+        let w = lint_src(
+            "protocol P {
+                putter a: T; getter b: T; mem m, n: T;
+                rule true { a => m; }
+                rule true { m => b; }
+                rule true { n => b; }
+                rule null(m) { m => b; }
+                rule a == a & (false | none(true)) { a => b; }
+            }",
+        );
+        // n is never filled, so rule 2 is dead.
+        // rule 3 requires m to be both empty and full.
+        // rule 4 has a guard that is always false.
+        assert_eq!(
+            w,
+            vec![
+                DeadRule { rule_id: 2 },
+                DeadRule { rule_id: 3 },
+                DeadRule { rule_id: 4 }
+            ]
+        );
+    }
+
+    #[test]
+    fn null_on_port() {
+        // rejected by the parser, but definitions assembled otherwise may contain it
+        let src = "protocol P { putter a: T; getter b: T; rule true { a => b; } }";
+        let mut def = parse_proto(src).unwrap().def;
+        def.behaviour.rules[0].guard = Formula::MemIsNull(0);
+        assert_eq!(lint(&def).warnings, vec![]);
+    }
+
+    #[test]
+    fn unused_and_stuck() {
+        let w = lint_src(
+            "protocol P {
+                putter a, c: T; getter b: T; mem m, n, o: T;
+                rule true { a => b, m; }
+                rule null(n) { a => b; }
+            }",
+        );
+        assert_eq!(
+            w,
+            vec![
+                PortInNoRule { loc_id: 1 },
+                MemNeverEmptied { loc_id: 3 },
+                UnusedLoc { loc_id: 5 },
+            ]
+        );
+    }
+}
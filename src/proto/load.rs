@@ -0,0 +1,349 @@
+use super::*;
+use crate::proto::{
+    definition::DynProtoBuilder,
+    parse::ParsedProto,
+    traits::{FuncDefPromise, Parsable, PromiseFulfilled},
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt, path::Path};
+
+/// A protocol definition as stored in a RON or JSON file. Types of locations
+/// are given by name, and resolved against a `TypeRegistry` when loading.
+/// Initial values of `MemInitialized` cells are given in textual form.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtoFile {
+    pub def: TypelessProtoDef,
+    pub loc_types: HashMap<LocId, String>,
+    #[serde(default)]
+    pub init_values: HashMap<LocId, String>,
+}
+impl From<ParsedProto> for ProtoFile {
+    fn from(p: ParsedProto) -> Self {
+        Self {
+            def: p.def,
+            loc_types: p.loc_types,
+            init_values: p.init_values,
+        }
+    }
+}
+impl ProtoFile {
+    pub fn from_ron_str(s: &str) -> Result<Self, LoadError> {
+        ron::de::from_str(s).map_err(|e| LoadError::Ron(e.to_string()))
+    }
+    pub fn from_json_str(s: &str) -> Result<Self, LoadError> {
+        serde_json::from_str(s).map_err(|e| LoadError::Json(e.to_string()))
+    }
+    pub fn to_ron_string(&self) -> String {
+        ron::ser::to_string_pretty(self, Default::default()).expect("serializing failed")
+    }
+    pub fn to_json_string(&self) -> String {
+        serde_json::to_string_pretty(self).expect("serializing failed")
+    }
+    /// Reads a file, choosing the format by its extension (`.ron` or `.json`).
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, LoadError> {
+        let path = path.as_ref();
+        let s = std::fs::read_to_string(path).map_err(|e| LoadError::Io(e.to_string()))?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => Self::from_json_str(&s),
+            Some("ron") => Self::from_ron_str(&s),
+            _ => Err(LoadError::UnknownFormat),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    Io(String),
+    Ron(String),
+    Json(String),
+    UnknownFormat,
+    UnknownTypeName { loc_id: LocId, type_name: String },
+    MissingInitValue { loc_id: LocId },
+    UnparsableInitValue { loc_id: LocId, value: String },
+    Build(ProtoBuildErr),
+}
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use LoadError::*;
+        match self {
+            Io(e) => write!(f, "io error: {}", e),
+            Ron(e) => write!(f, "bad RON: {}", e),
+            Json(e) => write!(f, "bad JSON: {}", e),
+            UnknownFormat => write!(f, "unknown file extension. expected .ron or .json"),
+            UnknownTypeName { loc_id, type_name } => write!(
+                f,
+                "type `{}` of location {} is not registered",
+                type_name, loc_id
+            ),
+            MissingInitValue { loc_id } => {
+                write!(f, "memory cell {} has no initial value", loc_id)
+            }
+            UnparsableInitValue { loc_id, value } => write!(
+                f,
+                "initial value {:?} of memory cell {} cannot be parsed",
+                value, loc_id
+            ),
+            Build(e) => write!(f, "protocol build failed: {}", e),
+        }
+    }
+}
+impl std::error::Error for LoadError {}
+
+type InitFn = fn(&str, &mut DynProtoBuilder, LocId) -> bool;
+
+fn parse_init<T: Parsable>(s: &str, builder: &mut DynProtoBuilder, loc_id: LocId) -> bool {
+    match T::try_parse(s) {
+        Some(t) => builder.init_memory(loc_id, t).is_ok(),
+        None => false,
+    }
+}
+
+struct RegisteredType {
+    info: TypeInfo,
+    init: Option<InitFn>,
+}
+
+type FuncFn = for<'a> fn(FuncDefPromise<'a>) -> PromiseFulfilled;
+type ConstFn = Box<dyn Fn(&mut DynProtoBuilder) + Send + Sync>;
+
+/// Maps the type names used in protocol files to the types they stand for.
+/// Only types registered with `register_parsable` may be used for initialized memory.
+/// Functions and constants used in guards are provided to every protocol instantiated.
+#[derive(Default)]
+pub struct TypeRegistry {
+    types: HashMap<String, RegisteredType>,
+    funcs: HashMap<Name, FuncFn>,
+    consts: HashMap<String, ConstFn>,
+}
+impl TypeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn register<T: 'static>(&mut self, type_name: &str) -> &mut Self {
+        let t = RegisteredType {
+            info: TypeInfo::new::<T>(),
+            init: None,
+        };
+        self.types.insert(type_name.to_owned(), t);
+        self
+    }
+    pub fn register_parsable<T: Parsable>(&mut self, type_name: &str) -> &mut Self {
+        let t = RegisteredType {
+            info: TypeInfo::new::<T>(),
+            init: Some(parse_init::<T>),
+        };
+        self.types.insert(type_name.to_owned(), t);
+        self
+    }
+    /// Defines the guard function `name`. See `DynProtoBuilder::def_func`.
+    /// ```ignore
+    /// registry.register_func("is_even", |f| f.define_arity1(is_even));
+    /// ```
+    pub fn register_func(&mut self, name: Name, define: FuncFn) -> &mut Self {
+        self.funcs.insert(name, define);
+        self
+    }
+    /// Defines the value of a literal in guards. See `DynProtoBuilder::def_const`.
+    pub fn register_const<T: Clone + Send + Sync + 'static>(
+        &mut self,
+        literal: &str,
+        t: T,
+    ) -> &mut Self {
+        let literal_owned = literal.to_owned();
+        let define = move |b: &mut DynProtoBuilder| b.def_const(&literal_owned, t.clone());
+        self.consts.insert(literal.to_owned(), Box::new(define));
+        self
+    }
+    pub fn type_info(&self, type_name: &str) -> Option<TypeInfo> {
+        self.types.get(type_name).map(|t| t.info)
+    }
+
+    /// Builds a protocol instance from the given file, resolving its type names.
+    pub fn instantiate(&self, file: &ProtoFile) -> Result<Arc<ProtoAll>, LoadError> {
+        use LoadError::*;
+        let mut registered: HashMap<LocId, &RegisteredType> = Default::default();
+        for &loc_id in file.def.loc_kinds.keys() {
+            let type_name = file
+                .loc_types
+                .get(&loc_id)
+                .ok_or(Build(ProtoBuildErr::UnknownType { loc_id }))?;
+            let t = self.types.get(type_name).ok_or_else(|| UnknownTypeName {
+                loc_id,
+                type_name: type_name.clone(),
+            })?;
+            registered.insert(loc_id, t);
+        }
+        let loc_types = registered.iter().map(|(&id, t)| (id, t.info)).collect();
+        let mut builder = DynProtoBuilder::new(file.def.clone(), loc_types);
+        for (&name, define) in self.funcs.iter() {
+            define(builder.def_func(name));
+        }
+        for define in self.consts.values() {
+            define(&mut builder);
+        }
+        for (&loc_id, &kind) in file.def.loc_kinds.iter() {
+            if kind != LocKind::MemInitialized {
+                continue;
+            }
+            let value = file
+                .init_values
+                .get(&loc_id)
+                .ok_or(MissingInitValue { loc_id })?;
+            let parsed = match registered[&loc_id].init {
+                Some(init) => init(value, &mut builder, loc_id),
+                None => false,
+            };
+            if !parsed {
+                return Err(UnparsableInitValue {
+                    loc_id,
+                    value: value.clone(),
+                });
+            }
+        }
+        builder.build().map_err(Build)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::parse::parse_proto;
+
+    const FIFO: &str = "
+        protocol Fifo {
+            putter a: u32;
+            getter b: u32;
+            mem m: u32 = 7;
+            rule true { a => m; }
+            rule true { m => b; }
+        }";
+
+    fn registry() -> TypeRegistry {
+        let mut r = TypeRegistry::new();
+        r.register_parsable::<u32>("u32")
+            .register::<std::sync::mpsc::Sender<()>>("Sender");
+        r
+    }
+
+    #[test]
+    fn ron_and_json_round_trip() {
+        let file: ProtoFile = parse_proto(FIFO).unwrap().into();
+        let from_ron = ProtoFile::from_ron_str(&file.to_ron_string()).unwrap();
+        let from_json = ProtoFile::from_json_str(&file.to_json_string()).unwrap();
+        for f in [from_ron, from_json].iter() {
+            assert_eq!(
+                format!("{:?}", f.def.behaviour),
+                format!("{:?}", file.def.behaviour)
+            );
+            assert_eq!(f.def.loc_kinds, file.def.loc_kinds);
+            assert_eq!(f.loc_types, file.loc_types);
+            assert_eq!(f.init_values, file.init_values);
+        }
+    }
+
+    #[test]
+    fn func_names_round_trip() {
+        let f = Formula::FuncDeclaration {
+            name: "is_even",
+            args: vec![Term::Value(0)],
+        };
+        let s = serde_json::to_string(&f).unwrap();
+        assert_eq!(serde_json::from_str::<Formula>(&s).unwrap(), f);
+        // loading the name again does not leak another copy of it
+        let name = |f| match f {
+            Formula::FuncDeclaration { name, .. } => name,
+            _ => unreachable!(),
+        };
+        let loaded = name(serde_json::from_str(&s).unwrap());
+        assert!(std::ptr::eq(
+            loaded,
+            name(serde_json::from_str(&s).unwrap())
+        ));
+    }
+
+    #[test]
+    fn load_and_run() {
+        let file: ProtoFile = parse_proto(FIFO).unwrap().into();
+        let p = registry().instantiate(&file).unwrap();
+        use std::convert::TryInto;
+        let mut a: Putter<u32> = p.claim(0).try_into().unwrap();
+        let mut b: Getter<u32> = p.claim(1).try_into().unwrap();
+        assert_eq!(b.get(), 7);
+        a.put(3);
+        assert_eq!(b.get(), 3);
+    }
+
+    #[test]
+    fn init_mem_refcount() {
+        // the initial value is referenced once by its cell, whatever the cell's LocId
+        let src = "
+            protocol Fifo {
+                mem m: u32 = 7;
+                putter a: u32;
+                getter b: u32;
+                rule true { a => m; }
+                rule true { m => b; }
+            }";
+        let file: ProtoFile = parse_proto(src).unwrap().into();
+        let p = registry().instantiate(&file).unwrap();
+        use std::convert::TryInto;
+        let mut a: Putter<u32> = p.claim(1).try_into().unwrap();
+        let mut b: Getter<u32> = p.claim(2).try_into().unwrap();
+        assert_eq!(b.get(), 7);
+        a.put(3);
+        assert_eq!(b.get(), 3);
+    }
+
+    #[test]
+    fn load_and_run_guard_funcs() {
+        use std::mem::MaybeUninit;
+        fn is_even(r: &mut MaybeUninit<bool>, x: *const u32) {
+            *r = MaybeUninit::new(unsafe { *x } % 2 == 0);
+        }
+        let src = "
+            protocol EvenFilter {
+                putter a: u32;
+                getter b: u32;
+                rule is_even(a) & a < \"max\" { a => b; }
+                rule !is_even(a) | a >= \"max\" { a => ; }
+            }";
+        let file: ProtoFile = parse_proto(src).unwrap().into();
+        let mut registry = registry();
+        registry
+            .register_func("is_even", |f| f.define_arity1(is_even))
+            .register_const("max", 6u32);
+        let p = registry.instantiate(&file).unwrap();
+        use std::convert::TryInto;
+        let mut a: Putter<u32> = p.claim(0).try_into().unwrap();
+        let mut b: Getter<u32> = p.claim(1).try_into().unwrap();
+        crossbeam::scope(|s| {
+            s.spawn(move |_| {
+                for i in 1..=8 {
+                    a.put(i);
+                }
+            });
+            s.spawn(move |_| assert_eq!((b.get(), b.get()), (2, 4)));
+        })
+        .expect("Crashed!");
+    }
+
+    #[test]
+    fn load_errors() {
+        let mut file: ProtoFile = parse_proto(FIFO).unwrap().into();
+        file.init_values.insert(2, "seven".into());
+        match registry().instantiate(&file) {
+            Err(LoadError::UnparsableInitValue { loc_id: 2, .. }) => (),
+            _ => panic!("expected unparsable value"),
+        }
+        file.loc_types.insert(2, "Sender".into());
+        match registry().instantiate(&file) {
+            Err(LoadError::UnparsableInitValue { loc_id: 2, .. }) => (),
+            _ => panic!("expected unparsable value"),
+        }
+        file.loc_types.insert(0, "u64".into());
+        match registry().instantiate(&file) {
+            Err(LoadError::UnknownTypeName { loc_id: 0, .. }) => (),
+            _ => panic!("expected unknown type"),
+        }
+    }
+}
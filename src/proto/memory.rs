@@ -56,6 +56,14 @@ impl Storage {
         info.funcs.drop.execute(ptr);
         self.inner_free(ptr, &LayoutHashable(info.layout));
     }
+    /// Hands the allocation at `ptr`, with the value it holds, over to `dest`.
+    pub fn transfer(&mut self, ptr: StorePtr, dest: &mut Self) {
+        let tid = self.owned.remove(&ptr).expect("not owned?");
+        let info = self.type_info.get(&tid).unwrap();
+        dest.type_info.entry(tid).or_insert_with(|| info.clone());
+        dest.free.entry(LayoutHashable(info.layout)).or_default();
+        dest.owned.insert(ptr, tid);
+    }
     /// Deallocates emptied allocations
     pub fn shrink_to_fit(&mut self) {
         for (layout_hashable, vec) in self.free.drain() {
@@ -2,16 +2,34 @@ use crate::proto::memory::Storage;
 use itertools::izip;
 use smallvec::SmallVec;
 
+pub mod codegen;
+pub mod compose;
+mod coordinator;
+use coordinator::Coordinator;
 pub mod definition;
+pub mod family;
+pub mod lint;
+pub mod load;
 mod memory;
+pub mod observe;
+use observe::{ProtoEvent, ProtoObserver};
+pub mod parse;
 use definition::{Formula, LocKind, ProtoBuildErr, ProtoBuilder, Term, TypelessProtoDef};
 
 pub mod reflection;
+
+pub mod select;
+use select::{FirstMatch, RuleSelector};
+
 use reflection::TypeInfo;
 
+pub mod trace;
+use trace::TraceRecorder;
+
 pub mod traits;
 use traits::{
-    DataSource, HasMsgDropBox, HasUnclaimedPorts, MaybeClone, MaybeCopy, MaybePartialEq, Proto,
+    DataSource, HasMsgDropBox, HasUnclaimedPorts, MaybeClone, MaybeCopy, MaybeDeserialize,
+    MaybeParsable, MaybePartialEq, MaybePartialOrd, MaybeSerialize, Proto,
 };
 
 #[cfg(test)]
@@ -22,9 +40,9 @@ pub mod groups;
 use crate::{
     bitset::BitSet,
     tokens::{decimal::Decimal, Grouped},
-    LocId, ProtoHandle,
+    LocId, Name, ProtoHandle,
 };
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use parking_lot::{Mutex, MutexGuard};
 use std::{
     alloc::{self, Layout},
@@ -53,7 +71,7 @@ impl MoveFlags {
     #[inline]
     fn type_is_copy_i_moved(&self) {
         self.move_flags
-            .store(Self::MOVE_FLAG_MOVED, Ordering::SeqCst);
+            .fetch_or(Self::MOVE_FLAG_MOVED, Ordering::SeqCst);
     }
 
     #[inline]
@@ -62,6 +80,7 @@ impl MoveFlags {
         x & Self::MOVE_FLAG_MOVED != 0 && x & Self::MOVE_FLAG_DISABLED == 0
     }
 
+    /// Returns true for the first caller, if moving is enabled.
     #[inline]
     fn ask_for_move_permission(&self) -> bool {
         0 == self
@@ -72,9 +91,9 @@ impl MoveFlags {
     #[inline]
     fn reset(&self, move_enabled: bool) {
         let val = if move_enabled {
-            Self::MOVE_FLAG_DISABLED
-        } else {
             0
+        } else {
+            Self::MOVE_FLAG_DISABLED
         };
         self.move_flags.store(val, Ordering::SeqCst);
     }
@@ -131,7 +150,22 @@ impl MemoSpace {
     }
 
     /// invoked from both protocol or last getter.
-    pub(crate) fn make_empty(&self, w: &mut ProtoActive, drop_if_last_ref: bool, my_id: LocId) {
+    pub(crate) fn make_empty(
+        &self,
+        r: &ProtoR,
+        w: &mut ProtoActive,
+        drop_if_last_ref: bool,
+        my_id: LocId,
+    ) {
+        self.release(w, drop_if_last_ref);
+        let was = w.ready.set_to(my_id, true); // I am ready
+        assert!(!was);
+        w.observe(r, ProtoEvent::MemEmptied { loc_id: my_id });
+    }
+
+    /// Removes the ptr, dropping or forgetting the value if no other cell refers to it.
+    /// Does not affect readiness.
+    fn release(&self, w: &mut ProtoActive, drop_if_last_ref: bool) {
         let src = self.p.remove_ptr();
         let refs: &mut usize = w.mem_refs.get_mut(&src).expect("no memrefs?");
         assert!(*refs >= 1);
@@ -140,16 +174,12 @@ impl MemoSpace {
             w.mem_refs.remove(&src);
             unsafe {
                 if drop_if_last_ref {
-                    println!("MEM CELL DROPPING");
                     w.storage.drop_inside(src, &self.p.type_info)
                 } else {
-                    println!("MEM CELL FORGETTING");
                     w.storage.forget_inside(src, &self.p.type_info)
                 }
             }
         }
-        let was = w.ready.set_to(my_id, true); // I am ready
-        assert!(!was);
     }
 }
 
@@ -178,11 +208,38 @@ impl PoPuSpace {
 ///     either the coordinator itself OR 1+ getters involved in the ONE RULE must still be unready before it can be fired again
 ///   - invariant between firings of its one rule: this memory cell is EMPTY
 /// 2. filled explicitly by the coordinator. emptied by coordinator or last getter as usual
+/// 3. never ready or unready. readiness bits are not touched when it is emptied
+/// 4. if it holds the result of a transforming action, the `source` putter the datum was computed
+///    from is released only once the temp is emptied. this keeps the ONE RULE from firing again early
 #[derive(Debug)]
-struct TempSpace(MemoSpace);
+struct TempSpace {
+    mem: MemoSpace,
+    source: Option<LocId>,
+}
 impl TempSpace {
-    fn new(type_info: Arc<TypeInfo>) -> Self {
-        Self(MemoSpace::new(std::ptr::null_mut(), type_info))
+    fn new(type_info: Arc<TypeInfo>, source: Option<LocId>) -> Self {
+        Self {
+            mem: MemoSpace::new(std::ptr::null_mut(), type_info),
+            source,
+        }
+    }
+    /// Empties this cell and releases its source putter, if any. Returns the source
+    /// if it is a memory cell that has become ready as a result.
+    fn make_empty(&self, r: &ProtoR, w: &mut ProtoActive, drop_if_last_ref: bool) -> Option<LocId> {
+        self.mem.release(w, drop_if_last_ref);
+        let source = self.source?;
+        match r.get_space(source) {
+            Some(Space::PoPu(space)) => {
+                // the putter's datum was observed, not moved
+                space.dropbox.send(0);
+                None
+            }
+            Some(Space::Memo(space)) => {
+                space.make_empty(r, w, true, source);
+                Some(source)
+            }
+            _ => panic!("Bad source ID!!"),
+        }
     }
 }
 
@@ -205,6 +262,9 @@ impl PoGeSpace {
                 space.acquire_data([out_ptr].iter().copied(), (a, putter_id))
             }
             Some(Space::PoPu(space)) => space.acquire_data([out_ptr].iter().copied(), ()),
+            Some(Space::Temp(space)) => {
+                space.acquire_data([out_ptr].iter().copied(), (a, putter_id))
+            }
             _ => panic!("Bad putter ID!!"),
         }
     }
@@ -212,6 +272,7 @@ impl PoGeSpace {
         match a.r.get_space(putter_id) {
             Some(Space::Memo(space)) => space.acquire_data(std::iter::empty(), (a, putter_id)),
             Some(Space::PoPu(space)) => space.acquire_data(std::iter::empty(), ()),
+            Some(Space::Temp(space)) => space.acquire_data(std::iter::empty(), (a, putter_id)),
             _ => panic!("Bad putter ID!!"),
         }
     }
@@ -229,6 +290,7 @@ struct ProtoActive {
     ready: BitSet,
     storage: Storage,
     mem_refs: HashMap<*mut u8, usize>,
+    observer: Option<Arc<dyn ProtoObserver>>,
 }
 
 /// Part of protocol Meta-state. Remembers:
@@ -246,17 +308,6 @@ struct StateWaiter {
     whom: LocId,
 }
 
-trait DebugPrint {
-    fn debug_print(&self);
-}
-impl<'a, 'b> DebugPrint for (&'a ProtoR, &'b ProtoW) {
-    fn debug_print(&self) {
-        println!(":: MEMOR: {:?}", &self.1.memory_bits);
-        println!(":: READY: {:?}", &self.1.active.ready);
-        println!(":: TENTA: {:?}", &self.1.ready_tentative);
-    }
-}
-
 enum EvalTerm {
     True,
     False,
@@ -264,13 +315,26 @@ enum EvalTerm {
 }
 
 /// The portion of the protcol that is proected by the lock.
+/// Each region of the protocol has its own.
 struct ProtoW {
+    region: usize,
     memory_bits: BitSet,
     active: ProtoActive,
     commitment: Option<Commitment>,
     ready_tentative: BitSet,
     awaiting_states: Vec<StateWaiter>,
     unclaimed_ports: HashMap<LocId, PortInfo>,
+    // shared by all regions
+    recorder: Option<Arc<Mutex<TraceRecorder>>>,
+    // rules of this region that may have become enabled since they were last checked
+    rules_to_check: BitSet,
+    // the coordinator thread is yet to lock this region since it was last woken for it
+    coordinator_woken: bool,
+    selector: Box<dyn RuleSelector>,
+    // ports that were claimed, then dropped, and not claimed since
+    closed: BitSet,
+    // ports whose current operation fails once it cannot complete. See `close_port`
+    fallible: BitSet,
 }
 impl ProtoW {
     fn notify_state_waiters(ready: &BitSet, awaiting_states: &mut Vec<StateWaiter>, r: &ProtoR) {
@@ -291,95 +355,204 @@ impl ProtoW {
     /// "Act as protocol" procedure. Mutable reference ensures 0/1 threads
     /// call this per proto at a time.
     fn ready_set_coordinate(&mut self, r: &ProtoR, my_id: LocId) {
-        println!("ENTER WITH ID {}", my_id);
+        self.ready_set(r, my_id);
+        self.coordinate_unless_awaiting(r);
+    }
+    /// Makes the location ready without coordinating. A tentative port is no longer awaited.
+    fn ready_set(&mut self, r: &ProtoR, my_id: LocId) {
         self.active.ready.set_to(my_id, true);
-        (r, self as &ProtoW).debug_print();
-        match &mut self.commitment {
-            Some(commitment) => {
-                let i_was_tentative = !self.ready_tentative.set_to(my_id, false);
-                if i_was_tentative {
-                    commitment.awaiting -= 1;
-                    if commitment.awaiting == 0 {
-                        // I was the last!
-                        let rule = &r.rules[commitment.rule_id];
-                        subtract_readiness(&mut self.active.ready, rule);
-                        rule.fire(Firer {
-                            r,
-                            w: &mut self.active,
-                        });
-                        Self::notify_state_waiters(
-                            &self.active.ready,
-                            &mut self.awaiting_states,
-                            r,
-                        );
-                        self.commitment = None;
-                        self.exhaust_rules(r);
-                    }
-                }
+        self.mark_rules_of(r, my_id);
+        if !r.loc_is_mem(my_id) {
+            self.active
+                .observe(r, ProtoEvent::PortReady { loc_id: my_id });
+        }
+        if let Some(commitment) = &mut self.commitment {
+            if self.ready_tentative.set_to(my_id, false) {
+                commitment.awaiting -= 1;
+            }
+        }
+    }
+    fn coordinate_unless_awaiting(&mut self, r: &ProtoR) {
+        match &self.commitment {
+            // nothing fires until the tentative ports resolve
+            Some(commitment) if commitment.awaiting > 0 => {}
+            // if the last was just resolved, the committed rule fires first
+            _ => self.coordinate(r),
+        }
+    }
+    /// Makes the ports ready for an infallible operation each, as `port_ready`, but at once.
+    /// No rule can fire with only some of them.
+    fn ports_ready(&mut self, r: &ProtoR, ids: &[LocId]) {
+        for &id in ids {
+            self.fallible.set_to(id, false);
+            self.ready_set(r, id);
+        }
+        self.coordinate_unless_awaiting(r);
+    }
+
+    /// Makes the port ready for an operation, as `ready_set_coordinate`. A fallible operation
+    /// instead returns false if the port is doomed, or else is failed by `close_port` once it is.
+    fn port_ready(&mut self, r: &ProtoR, id: LocId, fallible: bool) -> bool {
+        if fallible && self.port_doomed(r, id) {
+            return false;
+        }
+        self.fallible.set_to(id, fallible);
+        self.ready_set_coordinate(r, id);
+        true
+    }
+    /// Whether every rule that could complete an operation of the port involves a closed port.
+    /// Holds vacuously for a port involved in no rule. Memory cells are not followed to the
+    /// closed ports that would fill or empty them.
+    fn port_doomed(&self, r: &ProtoR, id: LocId) -> bool {
+        r.loc_rules[id]
+            .iter()
+            .map(|&i| &r.rules[i])
+            .filter(|rule| rule.guard_ready.test(id))
+            .all(|rule| rule.guard_ready.intersects_with(&self.closed))
+    }
+    /// Records that the port was dropped. Waiting fallible operations it dooms are failed.
+    fn close_port(&mut self, r: &ProtoR, id: LocId) {
+        self.closed.set_to(id, true);
+        let doomed: SmallVec<[LocId; 4]> = self
+            .fallible
+            .iter_and(&self.active.ready)
+            .filter(|&waiter| self.port_doomed(r, waiter))
+            .collect();
+        for waiter in doomed {
+            // no longer ready, so no rule can fire with it
+            self.active.ready.set_to(waiter, false);
+            self.fallible.set_to(waiter, false);
+            match r.get_space(waiter) {
+                Some(Space::PoPu(space)) => space.dropbox.send_closed(),
+                Some(Space::PoGe(space)) => space.dropbox.send_closed(),
+                _ => panic!("bad fallible LocId!"),
             }
-            None => self.exhaust_rules(r),
         }
     }
 
+    /// Marks the rules depending on the given location to be checked by `exhaust_rules`.
+    fn mark_rules_of(&mut self, r: &ProtoR, id: LocId) {
+        for &rule_id in r.loc_rules[id].iter() {
+            self.rules_to_check.set_to(rule_id, true);
+        }
+    }
+
+    /// Checks the marked rules, unmarking those that cannot fire. Of those that can, the
+    /// ones of the highest priority are offered to the selector. Returns the rule it chose.
+    fn select_rule(&mut self, r: &ProtoR) -> Option<usize> {
+        let marked: SmallVec<[usize; 8]> = self.rules_to_check.iter_sparse().collect();
+        let mut enabled: SmallVec<[usize; 8]> = SmallVec::new();
+        for rule_id in marked {
+            let rule = &r.rules[rule_id];
+            let can_fire = is_ready(&self.memory_bits, &self.active.ready, rule)
+                && match &rule.guard_pred {
+                    None => true,
+                    Some(guard_pred) => unsafe {
+                        self.build_temps(r, rule);
+                        let pass = r.eval_formula(guard_pred, self);
+                        self.unbuild_temps(r, rule);
+                        pass
+                    },
+                };
+            if !can_fire {
+                self.rules_to_check.set_to(rule_id, false);
+                continue;
+            }
+            // rules passed over remain marked
+            if let Some(&first) = enabled.first() {
+                match r.rules[first].priority.cmp(&rule.priority) {
+                    std::cmp::Ordering::Greater => continue,
+                    std::cmp::Ordering::Less => enabled.clear(),
+                    std::cmp::Ordering::Equal => {}
+                }
+            }
+            enabled.push(rule_id);
+        }
+        let index = match enabled.len() {
+            0 => return None,
+            1 => 0,
+            _ => {
+                let ids: SmallVec<[usize; 8]> =
+                    enabled.iter().map(|&i| r.rules[i].rule_id).collect();
+                self.selector.select(&ids)
+            }
+        };
+        Some(enabled[index])
+    }
+
+    /// Fires the rule of the commitment, which no tentative port is awaited for any longer.
+    fn fire_commitment(&mut self, r: &ProtoR) {
+        let rule_id = self.commitment.take().expect("no commitment").rule_id;
+        self.active
+            .observe(r, ProtoEvent::CommitmentResolved { rule_id });
+        let rule = &r.rules[rule_id];
+        subtract_readiness(&mut self.active.ready, rule);
+        rule.fire(Firer {
+            r,
+            w: &mut self.active,
+        });
+        self.active.observe(r, ProtoEvent::RuleFired { rule_id });
+        Self::notify_state_waiters(&self.active.ready, &mut self.awaiting_states, r);
+    }
+    /// Fires rules until none can fire. Only marked rules are checked, as all others
+    /// were found to be disabled, and none of their locations changed since.
+    /// The `RuleSelector` chooses which rule fires, starting over after each firing.
+    /// A resolved commitment fires before any of them.
     fn exhaust_rules(&mut self, r: &ProtoR) {
-        'repeat: loop {
-            // keep looping until 0 rules can fire
-            for (rule_id, rule) in r.rules.iter().enumerate() {
-                let bits_ready = is_ready(&self.memory_bits, &self.active.ready, rule);
-                if bits_ready {
-                    // TODO
-                    unsafe { self.build_temps(r, rule) };
-                    let guard_pass = unsafe { r.eval_formula(&rule.guard_pred, self) };
-                    if !guard_pass {
-                        unsafe { self.unbuild_temps(r, rule) };
-                        continue;
-                    }
+        match &self.commitment {
+            // nothing else fires until the tentative ports resolve
+            Some(commitment) if commitment.awaiting > 0 => return,
+            Some(_) => self.fire_commitment(r),
+            None => (),
+        }
+        while let Some(rule_id) = self.select_rule(r) {
+            self.rules_to_check.set_to(rule_id, false);
+            let rule = &r.rules[rule_id];
+            // firing changes the readiness and memory of all locations involved
+            for id in rule.guard_ready.iter_sparse() {
+                self.mark_rules_of(r, id);
+            }
 
-                    // safe if Equal functions are sound
-                    println!("FIRING {}: {:?}", rule_id, rule);
-                    println!("FIRING BEFORE:");
-                    (r, self as &ProtoW).debug_print();
-                    println!("refs currently: {:?}", &self.active.mem_refs);
-
-                    let mut num_tenatives = 0;
-                    for id in self.active.ready.iter_and(&self.ready_tentative) {
-                        num_tenatives += 1;
-                        match r.get_space(id) {
-                            Some(Space::PoPu(po_pu)) => po_pu.dropbox.send(rule_id),
-                            Some(Space::PoGe(po_ge)) => po_ge.dropbox.send(rule_id),
-                            _ => panic!("bad tentative!"),
-                        }
-                    }
-                    // assign bits BEFORE the action happens. necessary for the tentative ports
-                    assign_memory_bits(&mut self.memory_bits, rule);
-
-                    // tenative ports! must wait for them to resolve
-                    if num_tenatives > 0 {
-                        self.commitment = Some(Commitment {
-                            rule_id,
-                            awaiting: num_tenatives,
-                        });
-                        return;
-                    }
-                    subtract_readiness(&mut self.active.ready, rule);
-                    rule.fire(Firer {
-                        r,
-                        w: &mut self.active,
-                    });
-
-                    println!("FIRING AFTER:");
-                    (r, self as &ProtoW).debug_print();
-                    println!("refs currently: {:?}", &self.active.mem_refs);
-                    println!("----------");
-
-                    Self::notify_state_waiters(&self.active.ready, &mut self.awaiting_states, r);
-                    continue 'repeat;
+            // safe if Equal functions are sound
+            let mut num_tenatives = 0;
+            for id in self.active.ready.iter_and(&self.ready_tentative) {
+                num_tenatives += 1;
+                match r.get_space(id) {
+                    Some(Space::PoPu(po_pu)) => po_pu.dropbox.send(rule_id),
+                    Some(Space::PoGe(po_ge)) => po_ge.dropbox.send(rule_id),
+                    _ => panic!("bad tentative!"),
                 }
             }
-            // only get here if NO rule fired
-            println!("EXITING");
-            return;
+            if let Some(recorder) = &self.recorder {
+                recorder.lock().record(r, &self.memory_bits, rule);
+            }
+            // assign bits BEFORE the action happens. necessary for the tentative ports
+            assign_memory_bits(&mut self.memory_bits, rule);
+
+            // tenative ports! must wait for them to resolve
+            if num_tenatives > 0 {
+                self.commitment = Some(Commitment {
+                    rule_id,
+                    awaiting: num_tenatives,
+                });
+                let event = ProtoEvent::CommitmentMade {
+                    rule_id,
+                    awaiting: num_tenatives,
+                };
+                self.active.observe(r, event);
+                return;
+            }
+            subtract_readiness(&mut self.active.ready, rule);
+            rule.fire(Firer {
+                r,
+                w: &mut self.active,
+            });
+            self.active.observe(r, ProtoEvent::RuleFired { rule_id });
+
+            Self::notify_state_waiters(&self.active.ready, &mut self.awaiting_states, r);
         }
+        // only get here if NO rule fired
+        self.check_deadlock(r);
     }
     #[inline]
     unsafe fn build_temps(&mut self, r: &ProtoR, rule: &RunRule) {
@@ -408,28 +581,20 @@ impl ProtoW {
             }
             let was = self.active.mem_refs.insert(dest, 1);
             assert!(was.is_none());
-            println!(
-                "COMPUTED TEMP VAL. INSERTED INTO TEMP SLOT {}",
-                t.temp_mem_loc_id
-            );
         }
     }
     #[inline]
     unsafe fn unbuild_temps(&mut self, r: &ProtoR, rule: &RunRule) {
         for t in rule.temp_mems.iter() {
             let dest = r.get_temp(t.temp_mem_loc_id).expect("NOT TEMP??");
-            dest.0.make_empty(&mut self.active, true, t.temp_mem_loc_id);
-            println!("DROPPING TEMP VAL IN SLOT {}", t.temp_mem_loc_id);
+            // temps are never ready or unready. see TempSpace
+            dest.mem.release(&mut self.active, true);
         }
     }
 }
 
 fn subtract_readiness(ready: &mut BitSet, rule: &RunRule) {
     // ready.pad_trailing_zeroes(rule.guard_ready.data.len());
-    println!(
-        "subtracking readiness {:?}",
-        (ready.data.len(), rule.guard_ready.data.len())
-    );
     for (mr, &gr) in izip!(ready.data.iter_mut(), rule.guard_ready.data.iter()) {
         *mr &= !gr;
     }
@@ -439,11 +604,12 @@ fn subtract_readiness(ready: &mut BitSet, rule: &RunRule) {
 /// of the provided rule. The guard is able to specify which bits should be
 /// ready & true, and which should be ready & false.
 fn is_ready(memory: &BitSet, ready: &BitSet, rule: &RunRule) -> bool {
-    for (&mr, &mv, &gr, &gv) in izip!(
+    for (&mr, &mv, &gr, &gv, &gm) in izip!(
         ready.data.iter(),
         memory.data.iter(),
         rule.guard_ready.data.iter(),
         rule.guard_full.data.iter(),
+        rule.guard_mem.data.iter(),
     ) {
         let should_be_pos = gr & gv;
         let should_be_neg = gr & !gv;
@@ -451,19 +617,12 @@ fn is_ready(memory: &BitSet, ready: &BitSet, rule: &RunRule) -> bool {
         let are_neg = mr & !mv;
         let false_neg = should_be_pos & !(are_pos);
         let false_pos = should_be_neg & !(are_neg);
-        if (false_neg | false_pos) != 0 {
+        // guard_mem cells need not be ready. only their fullness matters
+        let mem_mismatch = gm & (gv ^ mv);
+        if (false_neg | false_pos | mem_mismatch) != 0 {
             return false;
         }
     }
-    println!(
-        "is_ready returning TRUE with lens {:?}",
-        (
-            ready.data.len(),
-            memory.data.len(),
-            rule.guard_ready.data.len(),
-            rule.guard_full.data.len()
-        )
-    );
     true
 }
 
@@ -502,6 +661,15 @@ enum Space {
 pub struct ProtoR {
     rules: Vec<RunRule>,
     spaces: Vec<Space>,
+    loc_names: HashMap<LocId, String>,
+    families: HashSet<String>,
+    ports: Vec<PortDesc>,
+    id_map: LocIdMap,
+    // for each location, the rules whose readiness or guard depends on it
+    loc_rules: Vec<Vec<usize>>,
+    // for each location, the region whose lock protects it
+    loc_region: Vec<usize>,
+    coordinator: Option<Coordinator>,
 }
 impl ProtoR {
     unsafe fn eval_formula(&self, formula: &Formula, w: &ProtoW) -> bool {
@@ -509,25 +677,48 @@ impl ProtoR {
         let f = |q: &Formula| self.eval_formula(q, w);
         match formula {
             True => true,
+            False => false,
             None(x) => !x.iter().any(f),
-            And(x) => !x.iter().all(f),
+            And(x) => x.iter().all(f),
             Or(x) => x.iter().any(f),
             TermVal(loc_id) => {
                 let (ptr, i) = self.eval_term_with_info(loc_id, w);
                 assert_eq!(i.type_id, TypeInfo::BOOL_TYPE_INFO.type_id);
                 let p: *mut bool = transmute(ptr);
                 *p
-            },
+            }
             ValueEq(a, b) => {
                 let (aptr, i1) = self.eval_term_with_info(a, w);
                 let (bptr, i2) = self.eval_term_with_info(b, w);
                 assert_eq!(i1.type_id, i2.type_id);
                 i1.funcs.partial_eq.execute(aptr, bptr)
             }
+            Lt(a, b) => self.eval_partial_cmp(a, b, w) == Some(std::cmp::Ordering::Less),
+            Le(a, b) => match self.eval_partial_cmp(a, b, w) {
+                Some(o) => o != std::cmp::Ordering::Greater,
+                Option::None => false,
+            },
+            Gt(a, b) => self.eval_partial_cmp(a, b, w) == Some(std::cmp::Ordering::Greater),
+            Ge(a, b) => match self.eval_partial_cmp(a, b, w) {
+                Some(o) => o != std::cmp::Ordering::Less,
+                Option::None => false,
+            },
+            Not(x) => !f(x),
             MemIsNull(a) => !w.memory_bits.test(*a),
-            FuncDeclaration { .. } => panic!("TEMP. NOT ALLOWED AT RUNTIME"),
+            FuncDeclaration { .. } => unreachable!("replaced by temp cells when built"),
         }
     }
+    unsafe fn eval_partial_cmp(
+        &self,
+        a: &Term,
+        b: &Term,
+        w: &ProtoW,
+    ) -> Option<std::cmp::Ordering> {
+        let (aptr, i1) = self.eval_term_with_info(a, w);
+        let (bptr, i2) = self.eval_term_with_info(b, w);
+        assert_eq!(i1.type_id, i2.type_id);
+        i1.funcs.partial_ord.execute(aptr, bptr)
+    }
     unsafe fn eval_term(&self, term: &Term, w: &ProtoW) -> *mut u8 {
         match term {
             Term::Boolean(f) => {
@@ -541,6 +732,7 @@ impl ProtoR {
                 .get_space_putter(*loc_id)
                 .expect("NOT PUTTER")
                 .get_ptr(),
+            Term::Const(_) => unreachable!("replaced by memory cells when built"),
         }
     }
     unsafe fn eval_term_with_info(&self, term: &Term, w: &ProtoW) -> (*mut u8, &TypeInfo) {
@@ -557,6 +749,7 @@ impl ProtoR {
                 let s = self.get_space_putter(*loc_id).expect("NOT PUTTER");
                 (s.get_ptr(), &s.type_info)
             }
+            Term::Const(_) => unreachable!("replaced by memory cells when built"),
         }
     }
     fn send_to_getter(&self, id: LocId, msg: usize) {
@@ -612,6 +805,14 @@ impl ProtoR {
             _ => false,
         }
     }
+    /// External LocIds of the memory cells that are full according to `memory_bits`, ascending.
+    fn full_mems(&self, memory_bits: &BitSet) -> Vec<LocId> {
+        memory_bits
+            .iter_sparse()
+            .filter(|&id| self.loc_is_mem(id))
+            .map(|id| self.id_map.external(id))
+            .collect()
+    }
 }
 
 /// A single-cell message channel. The port-thread associated with this
@@ -624,6 +825,8 @@ pub(crate) struct MsgDropbox {
 impl MsgDropbox {
     // Value chosen only for visibility during debug
     const NOTHING_MSG: usize = !0; // 0xffff...
+    /// Tells a fallible operation that it cannot complete.
+    const CLOSED_MSG: usize = !0 - 1;
 
     fn new() -> Self {
         let (s, r) = crossbeam::channel::bounded(1);
@@ -646,12 +849,141 @@ impl MsgDropbox {
     fn send_nothing(&self) {
         self.send(Self::NOTHING_MSG)
     }
+    fn send_closed(&self) {
+        self.send(Self::CLOSED_MSG)
+    }
 }
 
 /// The entire state of a single protocol instance. Usually only accessed via Arc.
 pub struct ProtoAll {
     r: ProtoR,
-    w: Mutex<ProtoW>,
+    // rules that share no locations fire independently, each under the lock of its region
+    regions: Vec<Mutex<ProtoW>>,
+}
+impl ProtoAll {
+    /// Locks the region that the location (internal LocId) belongs to.
+    fn lock_region_of(&self, loc_id: LocId) -> MutexGuard<'_, ProtoW> {
+        self.regions[self.r.loc_region[loc_id]].lock()
+    }
+    /// The number of independently locked regions the protocol's rules are partitioned into.
+    pub fn num_regions(&self) -> usize {
+        self.regions.len()
+    }
+    /// Lists all ports of the protocol, claimed or not, in order of LocId.
+    pub fn ports(&self) -> &[PortDesc] {
+        &self.r.ports
+    }
+    pub fn loc_name(&self, loc_id: LocId) -> Option<&str> {
+        self.r.loc_names.get(&loc_id).map(String::as_str)
+    }
+    pub fn loc_id_of(&self, name: &str) -> Option<LocId> {
+        self.r
+            .loc_names
+            .iter()
+            .find(|(_, n)| n.as_str() == name)
+            .map(|(&id, _)| id)
+    }
+    /// LocIds of the locations named `family[0]`, `family[1]`, and so on. See `family`.
+    /// None if the protocol has no family of that name. Families may be empty.
+    pub fn family_ids(&self, family: &str) -> Option<Vec<LocId>> {
+        let mut indexed: HashMap<usize, LocId> = Default::default();
+        for (&id, name) in self.r.loc_names.iter() {
+            if let Some(index) = family::index_in(family, name) {
+                indexed.insert(index, id);
+            }
+        }
+        if indexed.is_empty() && !self.r.families.contains(family) {
+            return None;
+        }
+        Some((0..).map_while(|i| indexed.remove(&i)).collect())
+    }
+}
+
+/// Translates between the LocIds of a definition, which may be sparse, and the
+/// dense range `0..n` the protocol uses internally to index its spaces and bitsets.
+/// Only claiming and reporting to the user require translation.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LocIdMap {
+    to_internal: HashMap<LocId, LocId>,
+    to_external: Vec<LocId>,
+    num_locs: usize,
+}
+impl LocIdMap {
+    /// Assigns internal LocIds to the locations of `def` in ascending order. LocIds that
+    /// are referenced but not defined follow, such that they can still be reported.
+    pub(crate) fn compacting(def: &TypelessProtoDef) -> Self {
+        let mut known: Vec<LocId> = def.loc_kinds.keys().copied().collect();
+        known.sort();
+        let mut referenced = hashbrown::HashSet::new();
+        for rule in def.behaviour.rules.iter() {
+            rule.guard.visit_loc_ids(&mut |id| {
+                referenced.insert(id);
+            });
+            for a in rule.actions.iter() {
+                referenced.insert(a.putter);
+                referenced.extend(a.getters.iter().copied());
+            }
+        }
+        referenced.extend(def.loc_names.keys().copied());
+        let mut unknown: Vec<LocId> = referenced
+            .into_iter()
+            .filter(|id| !def.loc_kinds.contains_key(id))
+            .collect();
+        unknown.sort();
+        let num_locs = known.len();
+        known.extend(unknown);
+        Self {
+            to_internal: known.iter().enumerate().map(|(i, &id)| (id, i)).collect(),
+            to_external: known,
+            num_locs,
+        }
+    }
+    /// The number of defined locations, whose internal LocIds are `0..num_locs`.
+    pub(crate) fn num_locs(&self) -> usize {
+        self.num_locs
+    }
+    pub(crate) fn internal(&self, external: LocId) -> Option<LocId> {
+        self.to_internal.get(&external).copied()
+    }
+    pub(crate) fn external(&self, internal: LocId) -> LocId {
+        self.to_external[internal]
+    }
+    pub(crate) fn externalize_keys<V: Clone>(&self, m: &HashMap<LocId, V>) -> HashMap<LocId, V> {
+        m.iter()
+            .map(|(&id, v)| (self.external(id), v.clone()))
+            .collect()
+    }
+    /// A copy of `def` that refers to locations by their internal LocIds.
+    pub(crate) fn compact(&self, def: &TypelessProtoDef) -> TypelessProtoDef {
+        let f = |id| self.to_internal[&id];
+        TypelessProtoDef {
+            behaviour: definition::BehaviourDef {
+                rules: def
+                    .behaviour
+                    .rules
+                    .iter()
+                    .map(|rule| rule.map_loc_ids(&f))
+                    .collect(),
+            },
+            loc_kinds: def.loc_kinds.iter().map(|(&id, &k)| (f(id), k)).collect(),
+            loc_names: def
+                .loc_names
+                .iter()
+                .map(|(&id, name)| (f(id), name.clone()))
+                .collect(),
+            families: def.families.clone(),
+        }
+    }
+}
+
+/// Describes a port of a protocol, as listed by `ProtoAll::ports`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortDesc {
+    pub loc_id: LocId,
+    pub name: Option<String>,
+    pub role: PortRole,
+    pub type_id: TypeId,
+    pub type_name: &'static str,
 }
 
 /// Part of protocol Meta-state. Remembers that a Putter / Getter with this
@@ -670,18 +1002,28 @@ pub enum PortRole {
 
 /// Result of attempting to claim a given port Id from the protocol.
 /// Fails if another putter/getter exists that has already claimed it.
+/// Failures carry the name of the port, if it has one.
+#[derive(Debug)]
 pub enum ClaimResult<T: 'static> {
     GotGetter(Getter<T>),
     GotPutter(Putter<T>),
-    NotUnclaimed,
-    TypeMismatch,
+    NotUnclaimed {
+        loc_id: LocId,
+        name: Option<String>,
+    },
+    TypeMismatch {
+        loc_id: LocId,
+        name: Option<String>,
+    },
+    /// No location has this name.
+    UnknownName(String),
 }
 impl<T: 'static> ClaimResult<T> {
     pub fn claimed_nothing(&self) -> bool {
         use ClaimResult::*;
         match self {
             GotGetter(_) | GotPutter(_) => false,
-            NotUnclaimed | TypeMismatch => true,
+            NotUnclaimed { .. } | TypeMismatch { .. } | UnknownName(_) => true,
         }
     }
 }
@@ -692,7 +1034,7 @@ impl<T: 'static> TryInto<Putter<T>> for ClaimResult<T> {
         match self {
             GotPutter(p) => Ok(p),
             GotGetter(_) => Err(true),
-            NotUnclaimed | TypeMismatch => Err(true),
+            NotUnclaimed { .. } | TypeMismatch { .. } | UnknownName(_) => Err(true),
         }
     }
 }
@@ -703,7 +1045,7 @@ impl<T: 'static> TryInto<Getter<T>> for ClaimResult<T> {
         match self {
             GotPutter(_) => Err(true),
             GotGetter(g) => Ok(g),
-            NotUnclaimed | TypeMismatch => Err(true),
+            NotUnclaimed { .. } | TypeMismatch { .. } | UnknownName(_) => Err(true),
         }
     }
 }
@@ -727,6 +1069,17 @@ enum TempRuleFunc {
         args: [Term; 3],
     },
 }
+impl TempRuleFunc {
+    fn args(&self) -> &[Term] {
+        use TempRuleFunc::*;
+        match self {
+            Arity0 { .. } => &[],
+            Arity1 { args, .. } => args,
+            Arity2 { args, .. } => args,
+            Arity3 { args, .. } => args,
+        }
+    }
+}
 #[derive(Debug)]
 struct TempMemRunnable {
     temp_mem_loc_id: LocId,
@@ -739,11 +1092,17 @@ struct TempMemRunnable {
 /// 2. t.func is well-formed: its function populates the correct type, reading the correct types
 #[derive(Debug)]
 struct RunRule {
+    // index of the rule in the definition. rules that can never fire are not built
+    rule_id: usize,
+    priority: i32,
     guard_ready: BitSet,
     guard_full: BitSet,
+    // memory cells whose fullness (in guard_full) is required without being involved
+    guard_mem: BitSet,
 
     temp_mems: Vec<TempMemRunnable>,
-    guard_pred: Formula,
+    // None if the guard is always true
+    guard_pred: Option<Formula>,
 
     assign_vals: BitSet,
     assign_mask: BitSet,
@@ -753,7 +1112,10 @@ impl RunRule {
     #[inline]
     fn fire(&self, mut f: Firer) {
         for a in self.actions.iter() {
-            f.perform_action(a.putter, &a.mg, &a.pg)
+            match &a.transform {
+                None => f.perform_action(a.putter, &a.mg, &a.pg),
+                Some(t) => f.perform_transform(a.putter, t, &a.mg, &a.pg),
+            }
         }
     }
 }
@@ -764,6 +1126,8 @@ struct RunAction {
     putter: LocId,
     mg: SmallVec<[LocId; 4]>,
     pg: SmallVec<[LocId; 4]>,
+    /// computes the datum the getters receive into a temp cell, which acts as their putter
+    transform: Option<TempMemRunnable>,
 }
 
 pub(crate) struct PortCommon {
@@ -776,6 +1140,12 @@ pub struct Getter<T: 'static> {
     c: PortCommon,
     phantom: PhantomData<T>,
 }
+impl<T: 'static> Debug for Getter<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let id = self.c.p.r.id_map.external(self.c.id);
+        f.debug_struct("Getter").field("id", &id).finish()
+    }
+}
 impl<T: 'static> Getter<T> {
     const BAD_ID: &'static str = "My ID isn't associated with a valid getter!";
 
@@ -793,9 +1163,8 @@ impl<T: 'static> Getter<T> {
         let po_ge = self.c.p.r.get_po_ge(self.c.id).expect(Self::BAD_ID);
         self.c
             .p
-            .w
-            .lock()
-            .ready_set_coordinate(&self.c.p.r, self.c.id);
+            .lock_region_of(self.c.id)
+            .port_ready(&self.c.p.r, self.c.id, false);
         unsafe { po_ge.get_signal(&self.c.p, po_ge.dropbox.recv()) }
     }
     /// like `get` but attempts to return with `None` if the provided duration
@@ -820,9 +1189,8 @@ impl<T: 'static> Getter<T> {
         // po_ge.set_want_data(true);
         self.c
             .p
-            .w
-            .lock()
-            .ready_set_coordinate(&self.c.p.r, self.c.id);
+            .lock_region_of(self.c.id)
+            .port_ready(&self.c.p.r, self.c.id, false);
         po_ge.get_data(&self.c.p, po_ge.dropbox.recv(), transmute(dest));
     }
 
@@ -833,9 +1201,8 @@ impl<T: 'static> Getter<T> {
         // po_ge.set_want_data(true);
         self.c
             .p
-            .w
-            .lock()
-            .ready_set_coordinate(&self.c.p.r, self.c.id);
+            .lock_region_of(self.c.id)
+            .port_ready(&self.c.p.r, self.c.id, false);
         match po_ge.await_msg_timeout(&self.c.p, timeout, self.c.id) {
             Some(msg) => {
                 po_ge.get_data(&self.c.p, msg, transmute(dest));
@@ -850,9 +1217,8 @@ impl<T: 'static> Getter<T> {
         // po_ge.set_want_data(true);
         self.c
             .p
-            .w
-            .lock()
-            .ready_set_coordinate(&self.c.p.r, self.c.id);
+            .lock_region_of(self.c.id)
+            .port_ready(&self.c.p.r, self.c.id, false);
         match po_ge.await_msg_timeout(&self.c.p, timeout, self.c.id) {
             Some(msg) => {
                 po_ge.get_signal(&self.c.p, msg);
@@ -871,16 +1237,53 @@ impl<T: 'static> Getter<T> {
             datum.assume_init()
         }
     }
+
+    /// Like `get`, but fails rather than waiting forever once every rule that could
+    /// supply this getter involves a port that was dropped (and not claimed again).
+    /// Only ports involved in the rules themselves count. A getter supplied by a memory cell
+    /// still waits forever once the cell is empty and only a dropped putter could fill it.
+    pub fn try_get(&mut self) -> Result<T, PortClosed> {
+        let mut datum: MaybeUninit<T> = MaybeUninit::uninit();
+        unsafe {
+            match self.try_get_in_place(datum.as_mut_ptr()) {
+                true => Ok(datum.assume_init()),
+                false => Err(PortClosed(())),
+            }
+        }
+    }
+
+    /// Safety: `dest` is uninitialized at first.
+    /// on return: `dest` is initialized iff `true` was returned.
+    unsafe fn try_get_in_place(&mut self, dest: *mut T) -> bool {
+        let po_ge = self.c.p.r.get_po_ge(self.c.id).expect(Self::BAD_ID);
+        let ready = self
+            .c
+            .p
+            .lock_region_of(self.c.id)
+            .port_ready(&self.c.p.r, self.c.id, true);
+        if !ready {
+            return false;
+        }
+        match po_ge.dropbox.recv() {
+            MsgDropbox::CLOSED_MSG => false,
+            msg => {
+                po_ge.get_data(&self.c.p, msg, dest as *mut u8);
+                true
+            }
+        }
+    }
 }
 impl<T: 'static> Drop for Getter<T> {
     fn drop(&mut self) {
-        self.c.p.w.lock().unclaimed_ports.insert(
+        let mut w = self.c.p.lock_region_of(self.c.id);
+        w.unclaimed_ports.insert(
             self.c.id,
             PortInfo {
                 type_id: TypeId::of::<T>(),
                 role: PortRole::Getter,
             },
         );
+        w.close_port(&self.c.p.r, self.c.id);
     }
 }
 
@@ -911,11 +1314,31 @@ impl<T> PutTimeoutResult<T> {
     }
 }
 
+/// Error of `Putter::try_put` and `Getter::try_get`: every rule that could complete the
+/// operation involves a port that was dropped. A putter gets its datum back.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PortClosed<T = ()>(pub T);
+impl<T> std::fmt::Display for PortClosed<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "the operation cannot complete, as a peer port was dropped"
+        )
+    }
+}
+impl<T: Debug> std::error::Error for PortClosed<T> {}
+
 /// User-facing port-object with the role of "Putter" of type T.
 pub struct Putter<T: 'static> {
     c: PortCommon,
     phantom: PhantomData<T>,
 }
+impl<T: 'static> Debug for Putter<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let id = self.c.p.r.id_map.external(self.c.id);
+        f.debug_struct("Putter").field("id", &id).finish()
+    }
+}
 impl<T: 'static> Putter<T> {
     const BAD_MSG: &'static str = "putter got a bad `num_movers_msg`";
     const BAD_ID: &'static str = "protocol doesn't recognize my role as putter!";
@@ -960,9 +1383,8 @@ impl<T: 'static> Putter<T> {
         po_pu.p.set_ptr(transmute(src));
         self.c
             .p
-            .w
-            .lock()
-            .ready_set_coordinate(&self.c.p.r, self.c.id);
+            .lock_region_of(self.c.id)
+            .port_ready(&self.c.p.r, self.c.id, false);
         let num_movers_msg = po_pu.dropbox.recv();
         match num_movers_msg {
             0 => false,
@@ -983,13 +1405,19 @@ impl<T: 'static> Putter<T> {
         po_pu.p.set_ptr(transmute(src));
         self.c
             .p
-            .w
-            .lock()
-            .ready_set_coordinate(&self.c.p.r, self.c.id);
+            .lock_region_of(self.c.id)
+            .port_ready(&self.c.p.r, self.c.id, false);
         let num_movers_msg = match po_pu.dropbox.recv_timeout(timeout) {
             Some(msg) => msg,
             None => {
-                if self.c.p.w.lock().active.ready.set_to(self.c.id, false) {
+                if self
+                    .c
+                    .p
+                    .lock_region_of(self.c.id)
+                    .active
+                    .ready
+                    .set_to(self.c.id, false)
+                {
                     return Timeout(());
                 } else {
                     po_pu.dropbox.recv()
@@ -1017,6 +1445,30 @@ impl<T: 'static> Putter<T> {
             }
         }
     }
+    /// Like `put`, but fails rather than waiting forever once every rule that could
+    /// involve this putter involves a port that was dropped (and not claimed again).
+    /// Only ports involved in the rules themselves count, as for `Getter::try_get`.
+    pub fn try_put(&mut self, mut datum: T) -> Result<Option<T>, PortClosed<T>> {
+        let po_pu = self.c.p.r.get_po_pu(self.c.id).expect(Self::BAD_ID);
+        po_pu.p.set_ptr(&mut datum as *mut T as *mut u8);
+        let ready = self
+            .c
+            .p
+            .lock_region_of(self.c.id)
+            .port_ready(&self.c.p.r, self.c.id, true);
+        if !ready {
+            return Err(PortClosed(datum));
+        }
+        match po_pu.dropbox.recv() {
+            MsgDropbox::CLOSED_MSG => Err(PortClosed(datum)),
+            0 => Ok(Some(datum)),
+            1 => {
+                std::mem::forget(datum);
+                Ok(None)
+            }
+            _ => panic!("{}", Self::BAD_MSG),
+        }
+    }
     /// This function mirrors the API of that of `put`, returning `Some` if the
     /// value was not consumed, but instead drops the datum in place.
     pub fn put_lossy(&mut self, mut datum: T) -> Option<()> {
@@ -1033,13 +1485,15 @@ impl<T: 'static> Putter<T> {
 }
 impl<T: 'static> Drop for Putter<T> {
     fn drop(&mut self) {
-        self.c.p.w.lock().unclaimed_ports.insert(
+        let mut w = self.c.p.lock_region_of(self.c.id);
+        w.unclaimed_ports.insert(
             self.c.id,
             PortInfo {
                 type_id: TypeId::of::<T>(),
                 role: PortRole::Putter,
             },
         );
+        w.close_port(&self.c.p.r, self.c.id);
     }
 }
 
@@ -1049,11 +1503,39 @@ pub struct Firer<'a> {
     w: &'a mut ProtoActive,
 }
 impl<'a> Firer<'a> {
+    /// Like `perform_action`, but the getters receive the result of the transform instead.
+    /// The putter is released once the temp cell holding the result is emptied.
+    fn perform_transform(
+        &mut self,
+        putter: LocId,
+        t: &TempMemRunnable,
+        me_ge: &[LocId],
+        po_ge: &[LocId],
+    ) {
+        let src = self
+            .r
+            .get_space_putter(putter)
+            .expect("Not a putter!")
+            .get_ptr();
+        let temp = self.r.get_temp(t.temp_mem_loc_id).expect("NOT TEMP??");
+        let dest = unsafe { self.w.storage.alloc(&temp.mem.p.type_info) };
+        match &t.func {
+            TempRuleFunc::Arity1 { func, .. } => func(dest, src),
+            _ => unreachable!("transforms have arity 1"),
+        }
+        temp.mem.p.overwrite_null_ptr(dest);
+        let was = self.w.mem_refs.insert(dest, 1);
+        assert!(was.is_none());
+        self.perform_action(t.temp_mem_loc_id, me_ge, po_ge);
+    }
+
     pub fn perform_action(&mut self, putter: LocId, me_ge: &[LocId], po_ge: &[LocId]) {
         let space = self.r.get_space(putter);
         let (putter_space, mem_putter): (&PutterSpace, bool) = match space {
             Some(Space::PoPu(space)) => (&space.p, false),
-            Some(Space::Memo(space)) | Some(Space::Temp(TempSpace(space))) => (&space.p, true),
+            Some(Space::Memo(space)) | Some(Space::Temp(TempSpace { mem: space, .. })) => {
+                (&space.p, true)
+            }
             _ => panic!("Not a putter!"),
         };
         let src = putter_space.get_ptr();
@@ -1070,6 +1552,7 @@ impl<'a> Firer<'a> {
                     assert_eq!(tid, me_ge_space.p.type_info.type_id);
                     me_ge_space.p.overwrite_null_ptr(src);
                     self.w.ready.set_to(g, true); // PUTTER is ready
+                    self.w.observe(self.r, ProtoEvent::MemFilled { loc_id: g });
                 }
             }
             // 3. update refcounts
@@ -1099,6 +1582,10 @@ impl<'a> Firer<'a> {
                 let mut refcounts = 1;
                 first_me_ge_space.p.overwrite_null_ptr(dest);
                 self.w.ready.set_to(first_me_ge, true); // mem is ready for GET
+                let event = ProtoEvent::MemFilled {
+                    loc_id: first_me_ge,
+                };
+                self.w.observe(self.r, event);
 
                 // 4. copy pointers to other memory cells (if any)
                 for g in me_ge_iter {
@@ -1107,6 +1594,7 @@ impl<'a> Firer<'a> {
 
                     me_ge_space.p.overwrite_null_ptr(dest);
                     self.w.ready.set_to(g, true); // mem is ready for GET
+                    self.w.observe(self.r, ProtoEvent::MemFilled { loc_id: g });
                     refcounts += 1;
                 }
                 let was = self.w.mem_refs.insert(dest, refcounts);
@@ -1117,17 +1605,19 @@ impl<'a> Firer<'a> {
 
         // 4. perform port moves
         if po_ge.len() == 0 {
-            println!("PROTO MEM CLEANUP");
             match space.unwrap() {
                 Space::PoPu(space) => {
                     let mem_movers = if me_ge.is_empty() { 0 } else { 1 };
                     space.dropbox.send(mem_movers);
                 }
-                Space::Memo(space) | Space::Temp(TempSpace(space)) => {
+                Space::Memo(space) => {
                     if !move_into_self {
-                        space.make_empty(self.w, true, putter);
+                        space.make_empty(self.r, self.w, true, putter);
                     }
                 }
+                Space::Temp(space) => {
+                    space.make_empty(self.r, self.w, true);
+                }
                 _ => unreachable!(),
             };
         } else {
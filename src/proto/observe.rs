@@ -0,0 +1,246 @@
+//! Observing what a protocol instance does, e.g. for logging or debugging,
+//! and capturing the state it is in with `ProtoAll::snapshot`.
+//! ```ignore
+//! p.set_observer(Arc::new(|event: ProtoEvent| log::trace!("{:?}", event)));
+//! ```
+//! Without an observer installed, a protocol does no more than check that none is.
+
+use super::*;
+use std::fmt;
+
+/// Something that happened inside a protocol instance.
+/// LocIds and rule ids are those of the protocol's definition.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ProtoEvent {
+    /// The port is ready to participate in an interaction.
+    PortReady { loc_id: LocId },
+    /// The rule fired, performing all of its actions.
+    RuleFired { rule_id: usize },
+    /// The rule will fire once `awaiting` ports that were only tentatively ready
+    /// have confirmed their participation.
+    CommitmentMade { rule_id: usize, awaiting: usize },
+    /// The last tentative port involved in the rule confirmed its participation.
+    CommitmentResolved { rule_id: usize },
+    /// The memory cell now holds a datum.
+    MemFilled { loc_id: LocId },
+    /// The memory cell no longer holds a datum.
+    MemEmptied { loc_id: LocId },
+}
+impl ProtoEvent {
+    /// Translates internal LocIds and rule indices to those of the definition.
+    fn externalized(self, r: &ProtoR) -> Self {
+        use ProtoEvent::*;
+        let loc = |id| r.id_map.external(id);
+        let rule = |index: usize| r.rules[index].rule_id;
+        match self {
+            PortReady { loc_id } => PortReady {
+                loc_id: loc(loc_id),
+            },
+            RuleFired { rule_id } => RuleFired {
+                rule_id: rule(rule_id),
+            },
+            CommitmentMade { rule_id, awaiting } => CommitmentMade {
+                rule_id: rule(rule_id),
+                awaiting,
+            },
+            CommitmentResolved { rule_id } => CommitmentResolved {
+                rule_id: rule(rule_id),
+            },
+            MemFilled { loc_id } => MemFilled {
+                loc_id: loc(loc_id),
+            },
+            MemEmptied { loc_id } => MemEmptied {
+                loc_id: loc(loc_id),
+            },
+        }
+    }
+}
+
+/// Every claimed port of a region is blocked in an operation, yet none of the region's
+/// rules can fire, and no commitment is pending. Unless ports time out or more ports are claimed, none
+/// of these operations will ever complete.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deadlock {
+    /// The blocked ports, ascending.
+    pub ports: Vec<LocId>,
+    /// The rules involving any of the blocked ports, none of which is enabled.
+    pub rules: Vec<usize>,
+}
+impl fmt::Display for Deadlock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "deadlock: ports {:?} are blocked, while their rules {:?} are disabled",
+            self.ports, self.rules
+        )
+    }
+}
+
+/// Receives the events of the protocol instance it is installed in with `ProtoAll::set_observer`.
+/// Events are observed while the protocol is locked, in the order they occur. As such, `observe`
+/// must not interact with the protocol's ports, and should return quickly.
+pub trait ProtoObserver: Send + Sync {
+    fn observe(&self, event: ProtoEvent);
+    /// Invoked when the protocol detects a deadlock, which is only checked
+    /// for while an observer is installed. Does nothing by default.
+    fn deadlock(&self, _deadlock: &Deadlock) {}
+}
+impl<F: Fn(ProtoEvent) + Send + Sync> ProtoObserver for F {
+    fn observe(&self, event: ProtoEvent) {
+        self(event)
+    }
+}
+
+impl ProtoActive {
+    /// Passes the event to the observer, if there is one. `event` uses internal LocIds
+    /// and indices into `ProtoR::rules`.
+    #[inline]
+    pub(crate) fn observe(&self, r: &ProtoR, event: ProtoEvent) {
+        if let Some(observer) = &self.observer {
+            observer.observe(event.externalized(r));
+        }
+    }
+}
+
+impl ProtoW {
+    /// Called once none of this region's rules can fire. LocIds and rule ids of the result
+    /// are external.
+    fn detect_deadlock(&self, r: &ProtoR) -> Option<Deadlock> {
+        if self.commitment.is_some() {
+            return None;
+        }
+        let mut blocked = BitSet::default();
+        for port in r.ports.iter() {
+            let id = r.id_map.internal(port.loc_id).unwrap();
+            if r.loc_region[id] != self.region || self.unclaimed_ports.contains_key(&id) {
+                continue;
+            }
+            if !self.active.ready.test(id) {
+                // a claimed port that may yet act
+                return None;
+            }
+            blocked.set_to(id, true);
+        }
+        if blocked.is_empty() {
+            return None;
+        }
+        let rules = r
+            .rules
+            .iter()
+            .filter(|rule| rule.guard_ready.intersects_with(&blocked))
+            .map(|rule| rule.rule_id)
+            .collect();
+        let ports = blocked.iter_sparse().map(|id| r.id_map.external(id));
+        Some(Deadlock {
+            ports: ports.collect(),
+            rules,
+        })
+    }
+    /// Reports a deadlock to the observer, if there is one.
+    pub(crate) fn check_deadlock(&self, r: &ProtoR) {
+        if let Some(observer) = &self.active.observer {
+            if let Some(deadlock) = self.detect_deadlock(r) {
+                observer.deadlock(&deadlock);
+            }
+        }
+    }
+}
+
+/// The state of a protocol instance at some point in time. See `ProtoAll::snapshot`.
+/// LocIds and rule ids are those of the protocol's definition. Lists are ascending.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtoSnapshot {
+    /// Ports that have been claimed, and not yet dropped.
+    pub claimed: Vec<LocId>,
+    /// Ports that are blocked in an operation.
+    pub waiting: Vec<LocId>,
+    /// Waiting ports that only tentatively participate, as members of a port group.
+    pub tentative: Vec<LocId>,
+    /// Ports waiting for the protocol to reach some state.
+    pub awaiting_state: Vec<LocId>,
+    pub full_mems: Vec<LocId>,
+    /// At most one per region, in order of region.
+    pub commitments: Vec<CommitmentSnapshot>,
+    /// Rules that the readiness of their locations and the fullness of memory cells allow to
+    /// fire. Their guards may yet depend on data.
+    pub enabled_rules: Vec<usize>,
+}
+
+/// The rule the protocol committed to fire, once `awaiting` tentative ports have confirmed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CommitmentSnapshot {
+    pub rule_id: usize,
+    pub awaiting: usize,
+}
+
+impl ProtoAll {
+    /// Captures the current state of this protocol instance, e.g. for health checks.
+    /// Locks all regions at once, so the state is consistent across them.
+    pub fn snapshot(&self) -> ProtoSnapshot {
+        let r = &self.r;
+        // always locked in order of region, so this cannot deadlock with another snapshot
+        let regions: Vec<MutexGuard<ProtoW>> = self.regions.iter().map(|w| w.lock()).collect();
+        let merged = |bits: &dyn Fn(&ProtoW) -> &BitSet| {
+            let mut merged = BitSet::default();
+            for w in regions.iter() {
+                merged.or_with(bits(w));
+            }
+            merged
+        };
+        let memory_bits = merged(&|w| &w.memory_bits);
+        let ready = merged(&|w| &w.active.ready);
+        let ready_tentative = merged(&|w| &w.ready_tentative);
+        let is_unclaimed = |id: &LocId| regions.iter().any(|w| w.unclaimed_ports.contains_key(id));
+        let external = |ids: &mut dyn Iterator<Item = LocId>| -> Vec<LocId> {
+            let mut ids: Vec<LocId> = ids.map(|id| r.id_map.external(id)).collect();
+            ids.sort_unstable();
+            ids
+        };
+        let claimed = r
+            .ports
+            .iter()
+            .map(|port| r.id_map.internal(port.loc_id).unwrap())
+            .filter(|id| !is_unclaimed(id));
+        let is_port = |&id: &LocId| !r.loc_is_mem(id) && id < r.id_map.num_locs();
+        ProtoSnapshot {
+            claimed: external(&mut claimed.clone()),
+            waiting: external(&mut claimed.clone().filter(|&id| ready.test(id))),
+            tentative: external(&mut ready_tentative.iter_and(&ready).filter(is_port)),
+            awaiting_state: external(
+                &mut regions
+                    .iter()
+                    .flat_map(|w| w.awaiting_states.iter().map(|s| s.whom)),
+            ),
+            full_mems: r.full_mems(&memory_bits),
+            commitments: regions
+                .iter()
+                .filter_map(|w| w.commitment.as_ref())
+                .map(|c| CommitmentSnapshot {
+                    rule_id: r.rules[c.rule_id].rule_id,
+                    awaiting: c.awaiting,
+                })
+                .collect(),
+            enabled_rules: r
+                .rules
+                .iter()
+                .filter(|rule| is_ready(&memory_bits, &ready, rule))
+                .map(|rule| rule.rule_id)
+                .collect(),
+        }
+    }
+    /// Installs the observer of this protocol instance, replacing any previous one.
+    /// Events of different regions may be observed concurrently.
+    pub fn set_observer(&self, observer: Arc<dyn ProtoObserver>) {
+        for w in self.regions.iter() {
+            w.lock().active.observer = Some(observer.clone());
+        }
+    }
+    /// Uninstalls and returns the observer of this protocol instance, if it has one.
+    pub fn take_observer(&self) -> Option<Arc<dyn ProtoObserver>> {
+        let mut taken = None;
+        for w in self.regions.iter() {
+            taken = w.lock().active.observer.take().or(taken);
+        }
+        taken
+    }
+}
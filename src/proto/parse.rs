@@ -0,0 +1,794 @@
+use super::*;
+use crate::proto::definition::{intern_name, ActionDef, BehaviourDef, RuleDef};
+use std::fmt;
+
+/// Result of parsing a textual protocol definition. Alongside the typeless
+/// definition, the names of locations and their (textual) types are retained.
+/// Types are left unresolved: they are either one of `type_params` or
+/// some concrete type name such as `u32`.
+#[derive(Debug, Clone)]
+pub struct ParsedProto {
+    pub name: String,
+    pub type_params: Vec<String>,
+    pub def: TypelessProtoDef,
+    pub loc_ids: HashMap<String, LocId>,
+    pub loc_types: HashMap<LocId, String>,
+    pub init_values: HashMap<LocId, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: ParseErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    UnexpectedChar(char),
+    UnterminatedString,
+    UnexpectedToken {
+        expected: &'static str,
+        found: String,
+    },
+    UnexpectedEnd {
+        expected: &'static str,
+    },
+    DuplicateName(String),
+    UnknownName(String),
+    CannotPut(String),
+    CannotGet(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ParseErrorKind::*;
+        write!(f, "{}:{}: ", self.line, self.column)?;
+        match &self.kind {
+            UnexpectedChar(c) => write!(f, "unexpected character {:?}", c),
+            UnterminatedString => write!(f, "unterminated string literal"),
+            UnexpectedToken { expected, found } => {
+                write!(f, "expected {}, found `{}`", expected, found)
+            }
+            UnexpectedEnd { expected } => write!(f, "expected {}, found end of input", expected),
+            DuplicateName(n) => write!(f, "location `{}` is declared twice", n),
+            UnknownName(n) => write!(f, "location `{}` is not declared", n),
+            CannotPut(n) => write!(f, "location `{}` cannot act as a putter", n),
+            CannotGet(n) => write!(f, "location `{}` cannot act as a getter", n),
+        }
+    }
+}
+impl std::error::Error for ParseError {}
+
+/// Parses a protocol definition written in the textual format:
+/// ```text
+/// # comments run to the end of the line
+/// protocol Alternator<T> {
+///     putter a, b: T;
+///     getter c: T;
+///     mem m: T;           # starts empty
+///     mem n: u32 = 5;     # starts full
+///     rule true { a => c; b => m; }
+///     rule true { m => c; }
+///     rule null(n) & a == b { a => c; b => ; }
+///     rule !null(n) & n < 10 { n => c; }
+///     rule true { f(n) => c; }   # c gets the result of applying f to n's datum
+///     rule priority 1 true { m => c; }   # preferred over rules of lower priority
+/// }
+/// ```
+/// LocIds are assigned in order of declaration, starting at 0.
+/// Guards are built from `true`, `false`, `null(m)`, `none(..)`, comparisons `x == y`,
+/// `x != y`, `x < y`, `x <= y`, `x > y` and `x >= y`, function calls `f(x, ..)`,
+/// boolean locations, `!`, `&`, `|` and parentheses. Literals such as `10` or `"hi"`
+/// may be used as terms, taking the type of whatever they are compared with.
+/// Function names are interned to obtain the `&'static str` that
+/// `Formula::FuncDeclaration` requires.
+pub fn parse_proto(src: &str) -> Result<ParsedProto, ParseError> {
+    let tokens = Lexer::new(src).tokenize()?;
+    Parser {
+        tokens,
+        next: 0,
+        end: Lexer::end_pos(src),
+        loc_ids: Default::default(),
+        loc_kinds: Default::default(),
+    }
+    .parse_file()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Ident(String),
+    Literal(String),
+    Sym(&'static str),
+}
+impl fmt::Display for Tok {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Tok::Ident(s) | Tok::Literal(s) => write!(f, "{}", s),
+            Tok::Sym(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Pos {
+    line: usize,
+    column: usize,
+}
+
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    pos: Pos,
+}
+impl<'a> Lexer<'a> {
+    const SYMBOLS: &'static [&'static str] = &[
+        "==", "=>", "<=", ">=", "!=", "::", "{", "}", "(", ")", "<", ">", ",", ";", ":", "=", "&",
+        "|", "!",
+    ];
+    /// Binary operators comparing two terms in guards.
+    const COMPARISONS: &'static [&'static str] = &["==", "!=", "<", "<=", ">", ">="];
+
+    fn new(src: &'a str) -> Self {
+        Self {
+            chars: src.chars().peekable(),
+            pos: Pos { line: 1, column: 1 },
+        }
+    }
+    fn end_pos(src: &str) -> Pos {
+        let mut lexer = Lexer::new(src);
+        while lexer.bump().is_some() {}
+        lexer.pos
+    }
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.pos.line += 1;
+            self.pos.column = 1;
+        } else {
+            self.pos.column += 1;
+        }
+        Some(c)
+    }
+    fn err(&self, pos: Pos, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            line: pos.line,
+            column: pos.column,
+            kind,
+        }
+    }
+    fn take_while(&mut self, s: &mut String, pred: impl Fn(char) -> bool) {
+        while let Some(&c) = self.chars.peek() {
+            if !pred(c) {
+                break;
+            }
+            s.push(c);
+            self.bump();
+        }
+    }
+    fn tokenize(mut self) -> Result<Vec<(Tok, Pos)>, ParseError> {
+        let mut tokens = vec![];
+        while let Some(&c) = self.chars.peek() {
+            let pos = self.pos;
+            if c.is_whitespace() {
+                self.bump();
+            } else if c == '#' {
+                while self.chars.peek().map(|&c| c != '\n').unwrap_or(false) {
+                    self.bump();
+                }
+            } else if c.is_alphabetic() || c == '_' {
+                let mut s = String::new();
+                self.take_while(&mut s, |c| c.is_alphanumeric() || c == '_');
+                tokens.push((Tok::Ident(s), pos));
+            } else if c.is_ascii_digit() || c == '-' {
+                let mut s = String::new();
+                s.push(c);
+                self.bump();
+                self.take_while(&mut s, |c| c.is_alphanumeric() || c == '.' || c == '_');
+                tokens.push((Tok::Literal(s), pos));
+            } else if c == '"' {
+                self.bump();
+                let mut s = String::new();
+                loop {
+                    match self.bump() {
+                        None => return Err(self.err(pos, ParseErrorKind::UnterminatedString)),
+                        Some('"') => break,
+                        Some('\\') => match self.bump() {
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some(c) => s.push(c),
+                            None => return Err(self.err(pos, ParseErrorKind::UnterminatedString)),
+                        },
+                        Some(c) => s.push(c),
+                    }
+                }
+                tokens.push((Tok::Literal(s), pos));
+            } else {
+                let mut rest = String::new();
+                let mut lookahead = self.chars.clone();
+                for _ in 0..2 {
+                    if let Some(c) = lookahead.next() {
+                        rest.push(c);
+                    }
+                }
+                let sym = Self::SYMBOLS
+                    .iter()
+                    .find(|sym| rest.starts_with(*sym))
+                    .ok_or_else(|| self.err(pos, ParseErrorKind::UnexpectedChar(c)))?;
+                for _ in 0..sym.len() {
+                    self.bump();
+                }
+                tokens.push((Tok::Sym(sym), pos));
+            }
+        }
+        Ok(tokens)
+    }
+}
+
+struct Parser {
+    tokens: Vec<(Tok, Pos)>,
+    next: usize,
+    end: Pos,
+    loc_ids: HashMap<String, LocId>,
+    loc_kinds: HashMap<LocId, LocKind>,
+}
+impl Parser {
+    fn peek(&self) -> Option<&Tok> {
+        self.tokens.get(self.next).map(|(t, _)| t)
+    }
+    fn peek_at(&self, offset: usize) -> Option<&Tok> {
+        self.tokens.get(self.next + offset).map(|(t, _)| t)
+    }
+    fn pos(&self) -> Pos {
+        self.tokens
+            .get(self.next)
+            .map(|&(_, p)| p)
+            .unwrap_or(self.end)
+    }
+    fn err_at(&self, pos: Pos, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            line: pos.line,
+            column: pos.column,
+            kind,
+        }
+    }
+    fn unexpected(&self, expected: &'static str) -> ParseError {
+        let kind = match self.peek() {
+            Some(t) => ParseErrorKind::UnexpectedToken {
+                expected,
+                found: t.to_string(),
+            },
+            None => ParseErrorKind::UnexpectedEnd { expected },
+        };
+        self.err_at(self.pos(), kind)
+    }
+    fn is_sym(&self, sym: &str) -> bool {
+        match self.peek() {
+            Some(Tok::Sym(s)) => *s == sym,
+            _ => false,
+        }
+    }
+    fn is_keyword(&self, kw: &str) -> bool {
+        match self.peek() {
+            Some(Tok::Ident(s)) => s == kw,
+            _ => false,
+        }
+    }
+    fn eat_sym(&mut self, sym: &'static str) -> bool {
+        if self.is_sym(sym) {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn expect_sym(&mut self, sym: &'static str) -> Result<(), ParseError> {
+        if self.eat_sym(sym) {
+            Ok(())
+        } else {
+            Err(self.unexpected(sym))
+        }
+    }
+    fn expect_keyword(&mut self, kw: &'static str) -> Result<(), ParseError> {
+        if self.is_keyword(kw) {
+            self.next += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected(kw))
+        }
+    }
+    fn expect_ident(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(Tok::Ident(s)) => {
+                let s = s.clone();
+                self.next += 1;
+                Ok(s)
+            }
+            _ => Err(self.unexpected("identifier")),
+        }
+    }
+    fn expect_literal(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(Tok::Literal(s)) | Some(Tok::Ident(s)) => {
+                let s = s.clone();
+                self.next += 1;
+                Ok(s)
+            }
+            _ => Err(self.unexpected("literal value")),
+        }
+    }
+
+    fn parse_file(mut self) -> Result<ParsedProto, ParseError> {
+        self.expect_keyword("protocol")?;
+        let name = self.expect_ident()?;
+        let mut type_params = vec![];
+        if self.eat_sym("<") {
+            loop {
+                type_params.push(self.expect_ident()?);
+                if !self.eat_sym(",") {
+                    break;
+                }
+            }
+            self.expect_sym(">")?;
+        }
+        self.expect_sym("{")?;
+        let mut loc_types = HashMap::default();
+        let mut init_values = HashMap::default();
+        let mut rules = vec![];
+        while !self.eat_sym("}") {
+            if self.is_keyword("rule") {
+                self.next += 1;
+                rules.push(self.parse_rule()?);
+                continue;
+            }
+            let kind = match self.peek() {
+                Some(Tok::Ident(s)) if s == "putter" => LocKind::PortPutter,
+                Some(Tok::Ident(s)) if s == "getter" => LocKind::PortGetter,
+                Some(Tok::Ident(s)) if s == "mem" => LocKind::MemUninitialized,
+                _ => return Err(self.unexpected("`putter`, `getter`, `mem`, `rule` or `}`")),
+            };
+            self.next += 1;
+            let mut ids = vec![];
+            loop {
+                let pos = self.pos();
+                let name = self.expect_ident()?;
+                if self.loc_ids.contains_key(&name) {
+                    return Err(self.err_at(pos, ParseErrorKind::DuplicateName(name)));
+                }
+                let id = self.loc_ids.len();
+                self.loc_ids.insert(name, id);
+                self.loc_kinds.insert(id, kind);
+                ids.push(id);
+                if !self.eat_sym(",") {
+                    break;
+                }
+            }
+            self.expect_sym(":")?;
+            let type_name = self.parse_type()?;
+            if kind == LocKind::MemUninitialized && self.eat_sym("=") {
+                let value = self.expect_literal()?;
+                for &id in ids.iter() {
+                    self.loc_kinds.insert(id, LocKind::MemInitialized);
+                    init_values.insert(id, value.clone());
+                }
+            }
+            self.expect_sym(";")?;
+            for id in ids {
+                loc_types.insert(id, type_name.clone());
+            }
+        }
+        if self.peek().is_some() {
+            return Err(self.unexpected("end of input"));
+        }
+        Ok(ParsedProto {
+            name,
+            type_params,
+            def: TypelessProtoDef {
+                behaviour: BehaviourDef { rules },
+                loc_kinds: self.loc_kinds,
+                loc_names: self
+                    .loc_ids
+                    .iter()
+                    .map(|(n, &id)| (id, n.clone()))
+                    .collect(),
+                families: Default::default(),
+            },
+            loc_ids: self.loc_ids,
+            loc_types,
+            init_values,
+        })
+    }
+
+    fn parse_type(&mut self) -> Result<String, ParseError> {
+        let mut s = self.expect_ident()?;
+        while self.eat_sym("::") {
+            s.push_str("::");
+            s.push_str(&self.expect_ident()?);
+        }
+        if self.eat_sym("<") {
+            s.push('<');
+            loop {
+                s.push_str(&self.parse_type()?);
+                if !self.eat_sym(",") {
+                    break;
+                }
+                s.push_str(", ");
+            }
+            self.expect_sym(">")?;
+            s.push('>');
+        }
+        Ok(s)
+    }
+
+    fn parse_loc(&mut self) -> Result<(LocId, LocKind, String, Pos), ParseError> {
+        let pos = self.pos();
+        let name = self.expect_ident()?;
+        match self.loc_ids.get(&name) {
+            Some(&id) => Ok((id, self.loc_kinds[&id], name, pos)),
+            None => Err(self.err_at(pos, ParseErrorKind::UnknownName(name))),
+        }
+    }
+
+    fn parse_rule(&mut self) -> Result<RuleDef, ParseError> {
+        // a guard cannot start with a location followed by a literal
+        let priority = match self.peek_at(1) {
+            Some(Tok::Literal(s)) if self.is_keyword("priority") => {
+                let (s, pos) = (s.clone(), self.tokens[self.next + 1].1);
+                self.next += 2;
+                s.parse().map_err(|_| {
+                    let expected = "an integer priority";
+                    let kind = ParseErrorKind::UnexpectedToken { expected, found: s };
+                    self.err_at(pos, kind)
+                })?
+            }
+            _ => 0,
+        };
+        let guard = self.parse_guard()?;
+        self.expect_sym("{")?;
+        let mut actions = vec![];
+        while !self.eat_sym("}") {
+            let transform = match self.peek_at(1) {
+                Some(Tok::Sym("(")) => {
+                    let name = self.expect_ident()?;
+                    self.expect_sym("(")?;
+                    Some(intern_name(&name))
+                }
+                _ => None,
+            };
+            let (putter, kind, name, pos) = self.parse_loc()?;
+            if !kind.can_put() {
+                return Err(self.err_at(pos, ParseErrorKind::CannotPut(name)));
+            }
+            if transform.is_some() {
+                self.expect_sym(")")?;
+            }
+            self.expect_sym("=>")?;
+            let mut getters = vec![];
+            if !self.is_sym(";") {
+                loop {
+                    let (getter, kind, name, pos) = self.parse_loc()?;
+                    if !kind.can_get() {
+                        return Err(self.err_at(pos, ParseErrorKind::CannotGet(name)));
+                    }
+                    getters.push(getter);
+                    if !self.eat_sym(",") {
+                        break;
+                    }
+                }
+            }
+            self.expect_sym(";")?;
+            actions.push(ActionDef {
+                putter,
+                getters,
+                transform,
+            });
+        }
+        Ok(RuleDef {
+            guard,
+            actions,
+            priority,
+        })
+    }
+
+    fn parse_guard(&mut self) -> Result<Formula, ParseError> {
+        let mut fs = vec![self.parse_conjunction()?];
+        while self.eat_sym("|") {
+            fs.push(self.parse_conjunction()?);
+        }
+        Ok(if fs.len() == 1 {
+            fs.pop().unwrap()
+        } else {
+            Formula::Or(fs)
+        })
+    }
+
+    fn parse_conjunction(&mut self) -> Result<Formula, ParseError> {
+        let mut fs = vec![self.parse_atom()?];
+        while self.eat_sym("&") {
+            fs.push(self.parse_atom()?);
+        }
+        Ok(if fs.len() == 1 {
+            fs.pop().unwrap()
+        } else {
+            Formula::And(fs)
+        })
+    }
+
+    fn is_comparison(&self) -> bool {
+        Lexer::COMPARISONS.iter().any(|sym| self.is_sym(sym))
+    }
+
+    fn parse_atom(&mut self) -> Result<Formula, ParseError> {
+        let lhs = if self.eat_sym("!") {
+            return Ok(Formula::Not(Box::new(self.parse_atom()?)));
+        } else if self.eat_sym("(") {
+            let f = self.parse_guard()?;
+            self.expect_sym(")")?;
+            if !self.is_comparison() {
+                return Ok(f);
+            }
+            Term::Boolean(Box::new(f))
+        } else if let Some(Tok::Literal(s)) = self.peek() {
+            let s = s.clone();
+            self.next += 1;
+            Term::Const(s)
+        } else if self.is_keyword("true") {
+            self.next += 1;
+            return Ok(Formula::True);
+        } else if self.is_keyword("false") {
+            self.next += 1;
+            return Ok(Formula::False);
+        } else if self.is_keyword("null") && self.peek_at(1) == Some(&Tok::Sym("(")) {
+            self.next += 2;
+            let (id, kind, name, pos) = self.parse_loc()?;
+            if !kind.is_mem() {
+                return Err(self.err_at(
+                    pos,
+                    ParseErrorKind::UnexpectedToken {
+                        expected: "memory cell",
+                        found: name,
+                    },
+                ));
+            }
+            self.expect_sym(")")?;
+            return Ok(Formula::MemIsNull(id));
+        } else if self.is_keyword("none") && self.peek_at(1) == Some(&Tok::Sym("(")) {
+            self.next += 2;
+            let mut fs = vec![];
+            if !self.eat_sym(")") {
+                loop {
+                    fs.push(self.parse_guard()?);
+                    if !self.eat_sym(",") {
+                        break;
+                    }
+                }
+                self.expect_sym(")")?;
+            }
+            return Ok(Formula::None(fs));
+        } else if self.peek_at(1) == Some(&Tok::Sym("(")) {
+            let name = self.expect_ident()?;
+            self.next += 1;
+            let mut args = vec![];
+            if !self.eat_sym(")") {
+                loop {
+                    args.push(self.parse_arg()?);
+                    if !self.eat_sym(",") {
+                        break;
+                    }
+                }
+                self.expect_sym(")")?;
+            }
+            let name = intern_name(&name);
+            return Ok(Formula::FuncDeclaration { name, args });
+        } else {
+            let (id, ..) = self.parse_loc()?;
+            Term::Value(id)
+        };
+        let op: fn(Term, Term) -> Formula = match self.peek() {
+            Some(Tok::Sym("==")) => Formula::ValueEq,
+            Some(Tok::Sym("!=")) => |a, b| Formula::Not(Box::new(Formula::ValueEq(a, b))),
+            Some(Tok::Sym("<")) => Formula::Lt,
+            Some(Tok::Sym("<=")) => Formula::Le,
+            Some(Tok::Sym(">")) => Formula::Gt,
+            Some(Tok::Sym(">=")) => Formula::Ge,
+            _ => return Ok(Formula::TermVal(lhs)),
+        };
+        self.next += 1;
+        let rhs = self.parse_term()?;
+        Ok(op(lhs, rhs))
+    }
+
+    fn parse_term(&mut self) -> Result<Term, ParseError> {
+        if self.eat_sym("(") {
+            let f = self.parse_guard()?;
+            self.expect_sym(")")?;
+            Ok(Term::Boolean(Box::new(f)))
+        } else if let Some(Tok::Literal(s)) = self.peek() {
+            let s = s.clone();
+            self.next += 1;
+            Ok(Term::Const(s))
+        } else {
+            let (id, ..) = self.parse_loc()?;
+            Ok(Term::Value(id))
+        }
+    }
+
+    fn parse_arg(&mut self) -> Result<Term, ParseError> {
+        let lone_ident = matches!(
+            (self.peek(), self.peek_at(1)),
+            (Some(Tok::Ident(_)), Some(Tok::Sym(",")))
+                | (Some(Tok::Ident(_)), Some(Tok::Sym(")")))
+                | (Some(Tok::Literal(_)), Some(Tok::Sym(",")))
+                | (Some(Tok::Literal(_)), Some(Tok::Sym(")")))
+        );
+        if lone_ident && !self.is_keyword("true") && !self.is_keyword("false") {
+            self.parse_term()
+        } else {
+            Ok(Term::Boolean(Box::new(self.parse_guard()?)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALTERNATOR: &str = "
+        # the alternator from the tests, with names
+        protocol Alternator<T> {
+            putter a, b: T;
+            getter c: T;
+            mem m: T;
+            rule true { a => c; b => m; }
+            rule true { m => c; }
+        }";
+
+    #[test]
+    fn parse_alternator() {
+        let p = parse_proto(ALTERNATOR).unwrap();
+        assert_eq!(&p.name, "Alternator");
+        assert_eq!(p.type_params, vec!["T".to_string()]);
+        assert_eq!(p.loc_ids["a"], 0);
+        assert_eq!(p.loc_ids["m"], 3);
+        assert_eq!(p.def.loc_names[&3], "m");
+        assert_eq!(p.def.loc_kinds[&2], LocKind::PortGetter);
+        assert_eq!(p.def.loc_kinds[&3], LocKind::MemUninitialized);
+        assert!(p.loc_types.values().all(|t| t == "T"));
+        let rules = &p.def.behaviour.rules;
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].guard, Formula::True);
+        assert_eq!(rules[0].actions[0].putter, 0);
+        assert_eq!(rules[0].actions[0].getters, vec![2]);
+        assert_eq!(rules[0].actions[1].putter, 1);
+        assert_eq!(rules[0].actions[1].getters, vec![3]);
+        assert_eq!(rules[1].actions[0].putter, 3);
+    }
+
+    #[test]
+    fn parse_guards_and_init() {
+        let p = parse_proto(
+            "protocol G {
+                putter a, b: u32;
+                putter x: bool;
+                getter c: std::vec::Vec<u32>;
+                mem n: String = \"hello \\\"world\\\"\";
+                mem k: i64 = -3;
+                rule null(n) & (a == b | x) & even(a, (x)) { a => c; b => ; }
+            }",
+        )
+        .unwrap();
+        assert_eq!(p.def.loc_kinds[&4], LocKind::MemInitialized);
+        assert_eq!(&p.init_values[&4], "hello \"world\"");
+        assert_eq!(&p.init_values[&5], "-3");
+        assert_eq!(&p.loc_types[&3], "std::vec::Vec<u32>");
+        use Formula::*;
+        let expected = And(vec![
+            MemIsNull(4),
+            Or(vec![
+                ValueEq(Term::Value(0), Term::Value(1)),
+                TermVal(Term::Value(2)),
+            ]),
+            FuncDeclaration {
+                name: "even",
+                args: vec![
+                    Term::Value(0),
+                    Term::Boolean(Box::new(TermVal(Term::Value(2)))),
+                ],
+            },
+        ]);
+        let rule = &p.def.behaviour.rules[0];
+        assert_eq!(rule.guard, expected);
+        assert!(rule.actions[1].getters.is_empty());
+    }
+
+    #[test]
+    fn parse_transforms() {
+        let p = parse_proto(
+            "protocol T {
+                putter a: u32;
+                getter b: String;
+                rule true { to_string(a) => b; }
+            }",
+        )
+        .unwrap();
+        let action = &p.def.behaviour.rules[0].actions[0];
+        assert_eq!(action.transform, Some("to_string"));
+        assert_eq!((action.putter, &action.getters), (0, &vec![1]));
+        assert!(parse_proto("protocol T { putter a: u32; rule true { f(a => ; } }").is_err());
+    }
+
+    #[test]
+    fn parse_priority() {
+        let p = parse_proto(
+            "protocol P {
+                putter a: u32;
+                mem priority: bool;
+                rule priority -2 true { a => ; }
+                rule priority { a => ; }
+            }",
+        )
+        .unwrap();
+        let rules = &p.def.behaviour.rules;
+        assert_eq!((rules[0].priority, rules[1].priority), (-2, 0));
+        assert_eq!(rules[1].guard, Formula::TermVal(Term::Value(1)));
+        let e = parse_proto("protocol P { putter a: u8; rule priority 1.5 true { a => ; } }");
+        assert!(matches!(
+            e.unwrap_err().kind,
+            ParseErrorKind::UnexpectedToken {
+                expected: "an integer priority",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn parse_comparisons() {
+        let p = parse_proto(
+            "protocol C {
+                putter a, b: u32;
+                mem m: u32;
+                rule a < 10 & !(a >= b) & a != b & !null(m) & f(3, a) { a => ; b => ; }
+                rule -1 <= a | \"x\" > b { a => ; }
+            }",
+        )
+        .unwrap();
+        use Formula::*;
+        let c = |s: &str| Term::Const(s.to_string());
+        let v = Term::Value;
+        let expected = And(vec![
+            Lt(v(0), c("10")),
+            Not(Box::new(Ge(v(0), v(1)))),
+            Not(Box::new(ValueEq(v(0), v(1)))),
+            Not(Box::new(MemIsNull(2))),
+            FuncDeclaration {
+                name: "f",
+                args: vec![c("3"), v(0)],
+            },
+        ]);
+        assert_eq!(p.def.behaviour.rules[0].guard, expected);
+        let expected = Or(vec![Le(c("-1"), v(0)), Gt(c("x"), v(1))]);
+        assert_eq!(p.def.behaviour.rules[1].guard, expected);
+    }
+
+    #[test]
+    fn parse_error_positions() {
+        let e =
+            parse_proto("protocol P {\n  putter a: u8;\n  rule true { a => b; }\n}").unwrap_err();
+        assert_eq!((e.line, e.column), (3, 20));
+        assert_eq!(e.kind, ParseErrorKind::UnknownName("b".into()));
+
+        let e =
+            parse_proto("protocol P {\n  getter a: u8;\n  rule true { a => a; }\n}").unwrap_err();
+        assert_eq!((e.line, e.column), (3, 15));
+        assert_eq!(e.kind, ParseErrorKind::CannotPut("a".into()));
+
+        let e = parse_proto("protocol P {\n  putter a u8;\n}").unwrap_err();
+        assert_eq!((e.line, e.column), (2, 12));
+
+        let e = parse_proto("protocol P {\n  putter a: u8;\n").unwrap_err();
+        assert_eq!((e.line, e.column), (3, 1));
+        assert!(e.to_string().starts_with("3:1: expected"));
+
+        let e = parse_proto("protocol P { putter a: u8; mem a: u8; }").unwrap_err();
+        assert_eq!(e.kind, ParseErrorKind::DuplicateName("a".into()));
+    }
+}
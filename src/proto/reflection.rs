@@ -1,4 +1,5 @@
 use super::*;
+use std::cmp::Ordering;
 
 // an untyped CloneFn pointer. Null variant represents an undefined function
 // which will cause explicit panic if execute() is invoked.
@@ -47,6 +48,9 @@ impl PartialEqFn {
             None
         })
     }
+    pub fn is_defined(self) -> bool {
+        self.0.is_some()
+    }
     #[inline]
     pub unsafe fn execute(self, a: *mut u8, b: *mut u8) -> bool {
         if let Some(x) = self.0 {
@@ -57,6 +61,113 @@ impl PartialEqFn {
     }
 }
 
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct PartialOrdFn(Option<fn(*mut u8, *mut u8) -> Option<Ordering>>);
+impl PartialOrdFn {
+    fn new<T>() -> Self {
+        PartialOrdFn(if <T as MaybePartialOrd>::IS_DEFINED {
+            let clos: fn(*mut u8, *mut u8) -> Option<Ordering> =
+                |a, b| unsafe { T::maybe_partial_cmp(&*(a as *const T), &*(b as *const T)) };
+            Some(clos)
+        } else {
+            None
+        })
+    }
+    pub fn is_defined(self) -> bool {
+        self.0.is_some()
+    }
+    #[inline]
+    pub unsafe fn execute(self, a: *mut u8, b: *mut u8) -> Option<Ordering> {
+        if let Some(x) = self.0 {
+            (x)(a, b)
+        } else {
+            panic!("proto attempted to partial_cmp a type for which its not defined!");
+        }
+    }
+}
+
+// an untyped ParseFn pointer. Null variant represents a type that cannot be parsed.
+// On success, the parsed value is written to the (uninitialized) destination.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct ParseFn(Option<fn(&str, *mut u8) -> bool>);
+impl ParseFn {
+    fn new<T>() -> Self {
+        ParseFn(if <T as MaybeParsable>::IS_DEFINED {
+            let clos: fn(&str, *mut u8) -> bool = |s, dest| match T::maybe_parse(s) {
+                Some(datum) => unsafe {
+                    (dest as *mut T).write(datum);
+                    true
+                },
+                None => false,
+            };
+            Some(clos)
+        } else {
+            None
+        })
+    }
+    /// safe ONLY IF dest is &mut T to uninitialized memory.
+    /// Returns false if the type cannot be parsed, or `s` is no valid value.
+    #[inline]
+    pub unsafe fn execute(self, s: &str, dest: *mut u8) -> bool {
+        match self.0 {
+            Some(x) => (x)(s, dest),
+            None => false,
+        }
+    }
+}
+
+// an untyped SerializeFn pointer. Null variant represents a type that cannot be serialized.
+// Serializes the datum as JSON.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct SerializeFn(Option<fn(*mut u8) -> Option<String>>);
+impl SerializeFn {
+    fn new<T>() -> Self {
+        SerializeFn(if <T as MaybeSerialize>::IS_DEFINED {
+            let clos: fn(*mut u8) -> Option<String> =
+                |src| unsafe { T::maybe_serialize(&*(src as *const T)) };
+            Some(clos)
+        } else {
+            None
+        })
+    }
+    /// safe ONLY IF src is &T to initialized memory.
+    /// Returns None if the type cannot be serialized.
+    #[inline]
+    pub unsafe fn execute(self, src: *mut u8) -> Option<String> {
+        (self.0?)(src)
+    }
+}
+
+// an untyped DeserializeFn pointer. Null variant represents a type that cannot be deserialized.
+// Deserializes the datum from JSON, as written by a SerializeFn.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct DeserializeFn(Option<fn(&str, *mut u8) -> bool>);
+impl DeserializeFn {
+    fn new<T>() -> Self {
+        DeserializeFn(if <T as MaybeDeserialize>::IS_DEFINED {
+            let clos: fn(&str, *mut u8) -> bool = |s, dest| match T::maybe_deserialize(s) {
+                Some(datum) => unsafe {
+                    (dest as *mut T).write(datum);
+                    true
+                },
+                None => false,
+            };
+            Some(clos)
+        } else {
+            None
+        })
+    }
+    /// safe ONLY IF dest is &mut T to uninitialized memory.
+    /// Returns false if the type cannot be deserialized, or `s` is no valid JSON of it.
+    #[inline]
+    pub unsafe fn execute(self, s: &str, dest: *mut u8) -> bool {
+        match self.0 {
+            Some(x) => (x)(s, dest),
+            None => false,
+        }
+    }
+}
+
 // an untyped DropFn pointer. Null variant represents a trivial drop Fn (no behavior).
 // new() automatically handles types with trivial drop functions
 // UNSAFE if the type pointed to does not match the type used to instantiate the ptr.
@@ -88,6 +199,7 @@ impl DropFn {
 #[derive(Debug, Clone, Copy)]
 pub struct TypeInfo {
     pub(crate) type_id: TypeId,
+    pub(crate) type_name: &'static str,
     pub(crate) is_copy: bool,
     pub(crate) layout: Layout,
     pub(crate) funcs: TypeInfoFuncs,
@@ -98,10 +210,15 @@ pub struct TypeInfoFuncs {
     pub(crate) drop: DropFn,
     pub(crate) clone: CloneFn,
     pub(crate) partial_eq: PartialEqFn,
+    pub(crate) partial_ord: PartialOrdFn,
+    pub(crate) parse: ParseFn,
+    pub(crate) serialize: SerializeFn,
+    pub(crate) deserialize: DeserializeFn,
 }
 impl TypeInfo {
     pub const BOOL_TYPE_INFO: &'static TypeInfo = &TypeInfo {
         type_id: TypeId::of::<bool>(),
+        type_name: "bool",
         is_copy: true,
         layout: unsafe { Layout::from_size_align_unchecked(1, 1) },
         funcs: TypeInfoFuncs {
@@ -114,7 +231,32 @@ impl TypeInfo {
             partial_eq: PartialEqFn(Some(|a, b| unsafe {
                 let a: *const bool = std::mem::transmute(a);
                 let b: *const bool = std::mem::transmute(b);
-                a == b
+                *a == *b
+            })),
+            partial_ord: PartialOrdFn(Some(|a, b| unsafe {
+                let a: *const bool = std::mem::transmute(a);
+                let b: *const bool = std::mem::transmute(b);
+                (*a).partial_cmp(&*b)
+            })),
+            parse: ParseFn(Some(|s, dest| unsafe {
+                let dest: *mut bool = std::mem::transmute(dest);
+                match s {
+                    "true" => dest.write(true),
+                    "false" => dest.write(false),
+                    _ => return false,
+                }
+                true
+            })),
+            serialize: SerializeFn(Some(|src| unsafe {
+                Some((*(src as *const bool)).to_string())
+            })),
+            deserialize: DeserializeFn(Some(|s, dest| unsafe {
+                let dest: *mut bool = std::mem::transmute(dest);
+                match serde_json::from_str(s) {
+                    Ok(b) => dest.write(b),
+                    Err(_) => return false,
+                }
+                true
             })),
         },
     };
@@ -129,15 +271,24 @@ impl TypeInfo {
     pub fn get_tid(&self) -> TypeId {
         self.type_id
     }
+    /// The name of the type, as given by `std::any::type_name`.
+    pub fn get_type_name(&self) -> &'static str {
+        self.type_name
+    }
     pub fn new<T: 'static>() -> Self {
         Self {
             type_id: TypeId::of::<T>(),
+            type_name: std::any::type_name::<T>(),
             layout: Layout::new::<T>(),
             is_copy: <T as MaybeCopy>::IS_COPY,
             funcs: TypeInfoFuncs {
                 drop: DropFn::new::<T>(),
                 clone: CloneFn::new::<T>(),
                 partial_eq: PartialEqFn::new::<T>(),
+                partial_ord: PartialOrdFn::new::<T>(),
+                parse: ParseFn::new::<T>(),
+                serialize: SerializeFn::new::<T>(),
+                deserialize: DeserializeFn::new::<T>(),
             },
         }
     }
@@ -218,6 +369,58 @@ mod tests {
         assert!(partial_eq_fn.0.is_none());
     }
 
+    #[test]
+    fn partial_ord_and_parse() {
+        let (mut a, mut b) = (3u32, 5u32);
+        let partial_ord_fn = PartialOrdFn::new::<u32>();
+        unsafe {
+            let ord = partial_ord_fn.execute(&mut a as *mut u32 as _, &mut b as *mut u32 as _);
+            assert_eq!(ord, Some(Ordering::Less));
+        }
+        assert!(!PartialOrdFn::new::<Undefined>().is_defined());
+
+        let parse_fn = ParseFn::new::<u32>();
+        let mut dest = MaybeUninit::<u32>::uninit();
+        let d = dest.as_mut_ptr() as *mut u8;
+        unsafe {
+            assert!(parse_fn.execute("42", d));
+            assert_eq!(dest.assume_init(), 42);
+            assert!(!parse_fn.execute("forty-two", d));
+            assert!(!ParseFn::new::<Undefined>().execute("", d));
+        }
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let info = TypeInfo::new::<Vec<u32>>();
+        let mut src = vec![3u32, 5];
+        let mut dest = MaybeUninit::<Vec<u32>>::uninit();
+        unsafe {
+            let json = info
+                .funcs
+                .serialize
+                .execute(&mut src as *mut _ as _)
+                .unwrap();
+            assert_eq!(json, "[3,5]");
+            let d = dest.as_mut_ptr() as *mut u8;
+            assert!(!info.funcs.deserialize.execute("[3,", d));
+            assert!(info.funcs.deserialize.execute(&json, d));
+            assert_eq!(dest.assume_init(), src);
+            assert!(!DeserializeFn::new::<Undefined>().execute("[]", d));
+        }
+    }
+
+    #[test]
+    fn bool_type_info() {
+        let info = TypeInfo::BOOL_TYPE_INFO;
+        let [mut t, mut f, mut t2] = [true, false, true];
+        let [t, f, t2] = [&mut t, &mut f, &mut t2].map(|x| x as *mut bool as *mut u8);
+        unsafe {
+            assert!(!info.funcs.partial_eq.execute(t, f));
+            assert!(info.funcs.partial_eq.execute(t, t2));
+        }
+    }
+
     #[test]
     fn clone_undefined() {
         let clone_fn = CloneFn::new::<Undefined>();
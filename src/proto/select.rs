@@ -0,0 +1,107 @@
+//! Policies choosing which rule fires when several are enabled at once.
+//! Only rules of the highest `RuleDef::priority` among those enabled are offered to the
+//! `RuleSelector`, which the protocol is given at instantiation.
+//! ```ignore
+//! let mut b = DynProtoBuilder::new(def, loc_types);
+//! b.rule_selector(Box::new(SeededRandom::new(42)));
+//! ```
+
+/// Chooses the next rule to fire. Each region of a protocol instance has its own selector,
+/// which is only invoked while the region is locked.
+pub trait RuleSelector: Send {
+    /// Returns the index into `enabled` of the rule to fire. `enabled` holds the ids of the
+    /// rules that may fire, ascending, and is never empty.
+    fn select(&mut self, enabled: &[usize]) -> usize;
+    /// Returns a selector with the same policy for another region of the protocol.
+    fn for_region(&self, region: usize) -> Box<dyn RuleSelector>;
+}
+
+/// Fires the enabled rule with the lowest id. Rules with higher ids can be starved.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct FirstMatch;
+impl RuleSelector for FirstMatch {
+    fn select(&mut self, _enabled: &[usize]) -> usize {
+        0
+    }
+    fn for_region(&self, _region: usize) -> Box<dyn RuleSelector> {
+        Box::new(FirstMatch)
+    }
+}
+
+/// Fires the enabled rule with the lowest id above that of the last rule fired, if there is one.
+/// No rule that stays enabled is passed over more than once per round.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct RoundRobin {
+    last: Option<usize>,
+}
+impl RuleSelector for RoundRobin {
+    fn select(&mut self, enabled: &[usize]) -> usize {
+        let i = match self.last {
+            Some(last) => enabled.iter().position(|&id| id > last).unwrap_or(0),
+            None => 0,
+        };
+        self.last = Some(enabled[i]);
+        i
+    }
+    fn for_region(&self, _region: usize) -> Box<dyn RuleSelector> {
+        Box::new(RoundRobin::default())
+    }
+}
+
+/// Fires an enabled rule chosen uniformly at random. Given the same seed and the same
+/// sequence of choices, the same rules are chosen, which makes tests reproducible.
+#[derive(Debug, Copy, Clone)]
+pub struct SeededRandom {
+    state: u64,
+}
+impl SeededRandom {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+    /// splitmix64
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+impl RuleSelector for SeededRandom {
+    fn select(&mut self, enabled: &[usize]) -> usize {
+        (self.next() % enabled.len() as u64) as usize
+    }
+    fn for_region(&self, region: usize) -> Box<dyn RuleSelector> {
+        // regions choose independently, yet deterministically
+        let mut seeder = *self;
+        for _ in 0..=region {
+            seeder.next();
+        }
+        Box::new(SeededRandom::new(seeder.next()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_robin_wraps() {
+        let mut s = RoundRobin::default();
+        assert_eq!(s.select(&[1, 4, 6]), 0);
+        assert_eq!(s.select(&[1, 4, 6]), 1);
+        assert_eq!(s.select(&[1, 6]), 1);
+        assert_eq!(s.select(&[1, 4, 6]), 0);
+    }
+
+    #[test]
+    fn seeded_random_reproducible() {
+        let picks = |seed| {
+            let mut s = SeededRandom::new(seed);
+            (0..20).map(|_| s.select(&[0, 1, 2])).collect::<Vec<_>>()
+        };
+        assert_eq!(picks(7), picks(7));
+        assert_ne!(picks(7), picks(8));
+        assert!(picks(7).iter().all(|&i| i < 3));
+    }
+}
@@ -1,6 +1,9 @@
 use self::reo_rs::{
     proto::{
-        definition::{ActionDef, BehaviourDef, Formula, LocKind, RuleDef, TypelessProtoDef},
+        definition::{
+            ActionDef, BehaviourDef, DynProtoBuilder, Formula, LocKind, RuleDef, Term,
+            TypelessProtoDef,
+        },
         reflection::TypeInfo,
         traits::{FuncDefPromise, HasUnclaimedPorts, MemFillPromise, PromiseFulfilled, Proto},
         Getter, Putter,
@@ -11,7 +14,7 @@ use crate as reo_rs;
 use crossbeam;
 use parking_lot::Mutex;
 use rand::{thread_rng, Rng};
-use std::{sync::Arc, thread, time::Duration};
+use std::{mem::MaybeUninit, sync::Arc, thread, time::Duration};
 
 fn dur(x: u64) -> Duration {
     Duration::from_millis(x)
@@ -43,6 +46,8 @@ impl<T0: 'static> Proto for AlternatorProto<T0> {
                     2 => LocKind::PortGetter,
                     3 => LocKind::MemUninitialized,
                 },
+                loc_names: Default::default(),
+                families: Default::default(),
             };
         }
         &DEF
@@ -231,6 +236,8 @@ impl<T0: 'static> Proto for SyncProto<T0> {
                     0 => LocKind::PortPutter,
                     1 => LocKind::PortGetter,
                 },
+                loc_names: Default::default(),
+                families: Default::default(),
             };
         }
         &DEF
@@ -395,3 +402,1469 @@ fn proto_sync_f64_chain() {
     })
     .expect("Crashed!");
 }
+
+////////////////////////////////////////////////////////////////////////
+
+fn dyn_fifo_def() -> TypelessProtoDef {
+    TypelessProtoDef {
+        behaviour: BehaviourDef {
+            rules: vec![rule![Formula::True; 0=>2], rule![Formula::True; 2=>1]],
+        },
+        loc_kinds: map! {
+            0 => LocKind::PortPutter,
+            1 => LocKind::PortGetter,
+            2 => LocKind::MemInitialized,
+        },
+        loc_names: Default::default(),
+        families: Default::default(),
+    }
+}
+
+#[test]
+fn dyn_fifo_string() {
+    let loc_types = (0..=2).map(|id| (id, TypeInfo::new::<String>())).collect();
+    let mut b = DynProtoBuilder::new(dyn_fifo_def(), loc_types);
+    b.init_memory(2, "first".to_string()).unwrap();
+    let p = b.build().unwrap();
+    let (mut p0, mut p1): (Putter<String>, Getter<String>) = putters_getters![p => 0,1];
+    crossbeam::scope(|s| {
+        s.spawn(move |_| {
+            for i in 0..5 {
+                assert!(p0.put(format!("#{}", i)).is_none());
+            }
+        });
+        s.spawn(move |_| {
+            assert_eq!(&p1.get(), "first");
+            for i in 0..5 {
+                assert_eq!(p1.get(), format!("#{}", i));
+            }
+        });
+    })
+    .expect("Crashed!");
+}
+
+/// Counts how often it is cloned, telling the getter that moved it apart from those that cloned it.
+#[derive(Debug)]
+struct CloneCounter(Arc<Mutex<u32>>);
+impl Clone for CloneCounter {
+    fn clone(&self) -> Self {
+        *self.0.lock() += 1;
+        Self(self.0.clone())
+    }
+}
+
+#[test]
+fn dyn_replicate_moves_once() {
+    let def = TypelessProtoDef {
+        behaviour: BehaviourDef {
+            rules: vec![rule![Formula::True; 0=>1], rule![Formula::True; 1=>2,3,4]],
+        },
+        loc_kinds: map! {
+            0 => LocKind::PortPutter,
+            1 => LocKind::MemUninitialized,
+            2 => LocKind::PortGetter,
+            3 => LocKind::PortGetter,
+            4 => LocKind::PortGetter,
+        },
+        loc_names: Default::default(),
+        families: Default::default(),
+    };
+    let loc_types = (0..=4)
+        .map(|id| (id, TypeInfo::new::<CloneCounter>()))
+        .collect();
+    let p = DynProtoBuilder::new(def, loc_types).build().unwrap();
+    let (mut p0, p2, p3, p4): (
+        Putter<CloneCounter>,
+        Getter<CloneCounter>,
+        Getter<CloneCounter>,
+        Getter<CloneCounter>,
+    ) = putters_getters![p => 0,2,3,4];
+    const ROUNDS: u32 = 10;
+    let clones = Arc::new(Mutex::new(0));
+    let c = clones.clone();
+    thread::spawn(move || {
+        for _ in 0..ROUNDS {
+            assert!(p0.put(CloneCounter(c.clone())).is_none());
+        }
+    });
+    // getters that all try to move the datum out wait for each other forever
+    let (done, finished) = crossbeam::channel::unbounded();
+    for mut g in [p2, p3, p4] {
+        let done = done.clone();
+        thread::spawn(move || {
+            for _ in 0..ROUNDS {
+                g.get();
+            }
+            done.send(()).unwrap();
+        });
+    }
+    for _ in 0..3 {
+        assert!(finished.recv_timeout(dur(2000)).is_ok(), "getter is stuck");
+    }
+    // of the three getters, exactly one moved the datum out each round
+    assert_eq!(*clones.lock(), 2 * ROUNDS);
+}
+
+#[test]
+fn dyn_build_errors() {
+    use crate::proto::definition::ProtoBuildErr;
+    let loc_types = (0..=2).map(|id| (id, TypeInfo::new::<u8>())).collect();
+    let b = DynProtoBuilder::new(dyn_fifo_def(), loc_types);
+    assert_eq!(
+        b.build().err(),
+        Some(ProtoBuildErr::MemoryFillPromiseBroken { loc_id: 2 })
+    );
+
+    let loc_types = (0..=2).map(|id| (id, TypeInfo::new::<u8>())).collect();
+    let mut b = DynProtoBuilder::new(dyn_fifo_def(), loc_types);
+    assert_eq!(
+        b.init_memory(2, 3u16),
+        Err(ProtoBuildErr::MemoryFillTypeMismatch { loc_id: 2 })
+    );
+    // ports take no initial value
+    assert_eq!(
+        b.init_memory(0, 3u8),
+        Err(ProtoBuildErr::MemoryFillNotInitialized { loc_id: 0 })
+    );
+    assert_eq!(
+        b.init_memory(7, 3u8),
+        Err(ProtoBuildErr::UnknownType { loc_id: 7 })
+    );
+
+    let loc_types = map! {
+        0 => TypeInfo::new::<u8>(),
+        1 => TypeInfo::new::<u16>(),
+        2 => TypeInfo::new::<u8>(),
+    };
+    let mut b = DynProtoBuilder::new(dyn_fifo_def(), loc_types);
+    b.init_memory(2, 3u8).unwrap();
+    assert!(matches!(
+        b.build(),
+        Err(ProtoBuildErr::TypeMismatch { rule_id: 1, .. })
+    ));
+}
+
+////////////////////////////////////////////////////////////////////////
+
+struct EvenFilterProto;
+impl Proto for EvenFilterProto {
+    fn typeless_proto_def() -> &'static TypelessProtoDef {
+        lazy_static::lazy_static! {
+            static ref DEF: TypelessProtoDef = TypelessProtoDef {
+                behaviour: BehaviourDef {
+                    rules: vec![
+                        rule![Formula::FuncDeclaration { name: "is_even", args: vec![Term::Value(0)] }; 0=>1],
+                        rule![Formula::None(vec![
+                            Formula::FuncDeclaration { name: "is_even", args: vec![Term::Value(0)] },
+                        ]); 0=>],
+                    ]
+                },
+                loc_kinds: map! {
+                    0 => LocKind::PortPutter,
+                    1 => LocKind::PortGetter,
+                },
+                loc_names: Default::default(),
+                families: Default::default(),
+            };
+        }
+        &DEF
+    }
+    fn fill_memory(_loc_id: LocId, _p: MemFillPromise) -> Option<PromiseFulfilled> {
+        None
+    }
+    fn def_func(name: &'static str, p: FuncDefPromise) -> Option<PromiseFulfilled> {
+        fn is_even(r: &mut MaybeUninit<bool>, x: *const u32) {
+            *r = MaybeUninit::new(unsafe { *x } % 2 == 0);
+        }
+        match name {
+            "is_even" => Some(p.define_arity1(is_even)),
+            _ => None,
+        }
+    }
+    fn loc_type(loc_id: LocId) -> Option<TypeInfo> {
+        Some(match loc_id {
+            0..=1 => TypeInfo::new::<u32>(),
+            _ => return None,
+        })
+    }
+    type Interface = (Putter<u32>, Getter<u32>);
+    fn instantiate_and_claim() -> Self::Interface {
+        let p = Self::instantiate();
+        putters_getters![p => 0,1]
+    }
+}
+
+#[test]
+fn proto_filter_func_guard() {
+    let (mut p0, mut p1) = EvenFilterProto::instantiate_and_claim();
+    crossbeam::scope(|s| {
+        s.spawn(move |_| {
+            for i in 0..10 {
+                p0.put(i);
+            }
+        });
+        s.spawn(move |_| {
+            for i in 0..5 {
+                assert_eq!(p1.get(), i * 2);
+            }
+        });
+    })
+    .expect("Crashed!");
+}
+
+fn between(r: &mut MaybeUninit<bool>, lo: *const u32, x: *const u32, hi: *const u32) {
+    *r = MaybeUninit::new(unsafe { *lo <= *x && *x <= *hi });
+}
+fn differ(r: &mut MaybeUninit<bool>, a: *const u32, b: *const bool) {
+    *r = MaybeUninit::new(unsafe { (*a != 0) != *b });
+}
+
+/// putter 0 passes values to getter 1 if they lie between memory cells 2 and 3,
+/// and to getter 4 otherwise. Memory cell 5 holds a bool.
+fn dyn_func_def(guard: Formula) -> TypelessProtoDef {
+    TypelessProtoDef {
+        behaviour: BehaviourDef {
+            rules: vec![
+                rule![guard.clone(); 0=>1],
+                rule![Formula::None(vec![guard]); 0=>4],
+            ],
+        },
+        loc_kinds: map! {
+            0 => LocKind::PortPutter,
+            1 => LocKind::PortGetter,
+            2 => LocKind::MemInitialized,
+            3 => LocKind::MemInitialized,
+            4 => LocKind::PortGetter,
+            5 => LocKind::MemInitialized,
+        },
+        loc_names: Default::default(),
+        families: Default::default(),
+    }
+}
+
+fn dyn_func_builder(guard: Formula) -> DynProtoBuilder {
+    let mut loc_types: hashbrown::HashMap<_, _> =
+        (0..=4).map(|id| (id, TypeInfo::new::<u32>())).collect();
+    loc_types.insert(5, TypeInfo::new::<bool>());
+    let mut b = DynProtoBuilder::new(dyn_func_def(guard), loc_types);
+    b.init_memory(2, 3u32).unwrap();
+    b.init_memory(3, 6u32).unwrap();
+    b.init_memory(5, true).unwrap();
+    b.def_func("between").define_arity3(between);
+    b.def_func("differ").define_arity2(differ);
+    b
+}
+
+#[test]
+fn dyn_func_arity3() {
+    let guard = Formula::And(vec![
+        Formula::FuncDeclaration {
+            name: "between",
+            args: vec![Term::Value(2), Term::Value(0), Term::Value(3)],
+        },
+        Formula::FuncDeclaration {
+            name: "differ",
+            args: vec![
+                Term::Value(0),
+                Term::Boolean(Box::new(Formula::None(vec![Formula::True]))),
+            ],
+        },
+    ]);
+    let p = dyn_func_builder(guard).build().unwrap();
+    let (mut p0, mut inside, mut outside): (Putter<u32>, Getter<u32>, Getter<u32>) =
+        putters_getters![p => 0,1,4];
+    crossbeam::scope(|s| {
+        s.spawn(move |_| {
+            for i in 0..10 {
+                p0.put(i);
+            }
+        });
+        s.spawn(move |_| {
+            for &i in [0, 1, 2, 7, 8, 9].iter() {
+                assert_eq!(outside.get(), i);
+            }
+        });
+        s.spawn(move |_| {
+            for i in 3..=6 {
+                assert_eq!(inside.get(), i);
+            }
+        });
+    })
+    .expect("Crashed!");
+}
+
+#[test]
+fn dyn_func_errors() {
+    use crate::proto::definition::ProtoBuildErr;
+    let call = |name, args| Formula::FuncDeclaration { name, args };
+    let build = |guard| dyn_func_builder(guard).build().err();
+
+    assert_eq!(
+        build(call("nope", vec![])),
+        Some(ProtoBuildErr::FunctionUndefined { name: "nope" })
+    );
+    assert_eq!(
+        build(call("differ", vec![Term::Value(0)])),
+        Some(ProtoBuildErr::FunctionUsedWithWrongArity {
+            name: "differ",
+            used_arity: 1
+        })
+    );
+    assert_eq!(
+        build(call("differ", vec![Term::Value(0), Term::Value(2)])),
+        Some(ProtoBuildErr::FunctionParamTypeMismatch {
+            name: "differ",
+            param: 1
+        })
+    );
+    assert!(build(call("differ", vec![Term::Value(0), Term::Value(5)])).is_none());
+
+    fn succ(r: &mut MaybeUninit<u32>, x: *const u32) {
+        *r = MaybeUninit::new(unsafe { *x } + 1);
+    }
+    let mut b = dyn_func_builder(call("succ", vec![Term::Value(0)]));
+    b.def_func("succ").define_arity1(succ);
+    assert_eq!(
+        b.build().err(),
+        Some(ProtoBuildErr::FunctionReturnsNonBool { name: "succ" })
+    );
+}
+
+////////////////////////////////////////////////////////////////////////
+
+fn to_string(r: &mut MaybeUninit<String>, x: *const u32) {
+    *r = MaybeUninit::new(unsafe { *x }.to_string());
+}
+fn double(r: &mut MaybeUninit<u32>, x: *const u32) {
+    *r = MaybeUninit::new(unsafe { *x } * 2);
+}
+
+fn transform_builder(src: &str) -> DynProtoBuilder {
+    let parsed = crate::proto::parse::parse_proto(src).unwrap();
+    let loc_types = parsed
+        .loc_types
+        .iter()
+        .map(|(&id, t)| {
+            let info = match t.as_str() {
+                "u32" => TypeInfo::new::<u32>(),
+                "Set" => TypeInfo::new::<std::collections::HashSet<u32>>(),
+                _ => TypeInfo::new::<String>(),
+            };
+            (id, info)
+        })
+        .collect();
+    let mut b = DynProtoBuilder::new(parsed.def, loc_types);
+    b.def_func("to_string").define_arity1(to_string);
+    b.def_func("double").define_arity1(double);
+    b
+}
+
+#[test]
+fn dyn_transform_port_to_getters() {
+    let p = transform_builder(
+        "protocol T {
+            putter a: u32;
+            getter b, c: String;
+            mem m: String;
+            rule true { to_string(a) => b, m; }
+            rule true { m => c; }
+        }",
+    )
+    .build()
+    .unwrap();
+    let (mut a, mut b, mut c): (Putter<u32>, Getter<String>, Getter<String>) =
+        putters_getters![p => 0,1,2];
+    crossbeam::scope(|s| {
+        s.spawn(move |_| {
+            for i in 0..5 {
+                // the putter's own datum is only observed
+                assert_eq!(a.put(i), Some(i));
+            }
+        });
+        s.spawn(move |_| {
+            for i in 0..5 {
+                assert_eq!(b.get(), i.to_string());
+            }
+        });
+        s.spawn(move |_| {
+            for i in 0..5 {
+                assert_eq!(c.get(), i.to_string());
+            }
+        });
+    })
+    .expect("Crashed!");
+}
+
+#[test]
+fn dyn_transform_from_memory() {
+    let p = transform_builder(
+        "protocol T {
+            putter a: u32;
+            getter b: u32;
+            mem m: u32;
+            rule true { a => m; }
+            rule true { double(m) => b; }
+        }",
+    )
+    .build()
+    .unwrap();
+    let (mut a, mut b): (Putter<u32>, Getter<u32>) = putters_getters![p => 0,1];
+    crossbeam::scope(|s| {
+        s.spawn(move |_| {
+            for i in 0..5 {
+                assert_eq!(a.put(i), None);
+            }
+        });
+        s.spawn(move |_| {
+            for i in 0..5 {
+                assert_eq!(b.get(), i * 2);
+            }
+        });
+    })
+    .expect("Crashed!");
+}
+
+#[test]
+fn dyn_transform_errors() {
+    use crate::proto::definition::ProtoBuildErr;
+    let build = |src| transform_builder(src).build().err();
+    assert_eq!(
+        build("protocol T { putter a: String; getter b: String; rule true { double(a) => b; } }"),
+        Some(ProtoBuildErr::FunctionParamTypeMismatch {
+            name: "double",
+            param: 0
+        })
+    );
+    assert!(matches!(
+        build("protocol T { putter a: u32; getter b: u32; rule true { to_string(a) => b; } }"),
+        Some(ProtoBuildErr::TypeMismatch { .. })
+    ));
+    assert_eq!(
+        build("protocol T { putter a: u32; getter b: u32; rule true { nope(a) => b; } }"),
+        Some(ProtoBuildErr::FunctionUndefined { name: "nope" })
+    );
+    assert_eq!(
+        build("protocol T { mem m: u32; rule true { double(m) => m; } }"),
+        Some(ProtoBuildErr::SynchronousFiring { loc_id: 0 })
+    );
+}
+
+#[test]
+fn dyn_guard_thresholds() {
+    let p = transform_builder(
+        "protocol Threshold {
+            putter a: u32;
+            getter lo, hi: u32;
+            rule a < 10 { a => lo; }
+            rule a >= 10 { a => hi; }
+        }",
+    )
+    .build()
+    .unwrap();
+    let (mut a, mut lo, mut hi): (Putter<u32>, Getter<u32>, Getter<u32>) =
+        putters_getters![p => 0,1,2];
+    crossbeam::scope(|s| {
+        s.spawn(move |_| {
+            for &i in [3, 15, 7, 10].iter() {
+                a.put(i);
+            }
+        });
+        s.spawn(move |_| assert_eq!((lo.get(), lo.get()), (3, 7)));
+        s.spawn(move |_| assert_eq!((hi.get(), hi.get()), (15, 10)));
+    })
+    .expect("Crashed!");
+}
+
+#[test]
+fn dyn_guard_typed_const() {
+    let mut b = transform_builder(
+        "protocol Cap {
+            putter a: u32;
+            getter b: u32;
+            rule !(a > \"max\") { a => b; }
+            rule a > \"max\" { a => ; }
+        }",
+    );
+    b.def_const("max", 5u32);
+    let p = b.build().unwrap();
+    let (mut a, mut b): (Putter<u32>, Getter<u32>) = putters_getters![p => 0,1];
+    crossbeam::scope(|s| {
+        s.spawn(move |_| {
+            for &i in [3, 8, 5].iter() {
+                a.put(i);
+            }
+        });
+        s.spawn(move |_| assert_eq!((b.get(), b.get()), (3, 5)));
+    })
+    .expect("Crashed!");
+}
+
+#[test]
+fn dyn_guard_errors() {
+    use crate::proto::definition::ProtoBuildErr::*;
+    let build = |src| transform_builder(src).build().err();
+    assert_eq!(
+        build("protocol T { putter a: u32; rule a < \"ten\" { a => ; } }"),
+        Some(ConstUnparsable { rule_id: 0 })
+    );
+    assert_eq!(
+        build("protocol T { putter a: u32; rule true { a => ; } rule 1 < 2 { a => ; } }"),
+        Some(ConstTypeUnknown { rule_id: 1 })
+    );
+    assert_eq!(
+        build("protocol T { putter a: u32; putter b: String; rule a <= b { a => ; b => ; } }"),
+        Some(GuardTypeMismatch { rule_id: 0 })
+    );
+    assert_eq!(
+        build("protocol T { putter a: Set; rule a > a { a => ; } }"),
+        Some(GuardOpUndefined { rule_id: 0 })
+    );
+    let mut b = transform_builder("protocol T { putter a: u32; rule a == \"x\" { a => ; } }");
+    b.def_const("x", String::from("x"));
+    assert_eq!(b.build().err(), Some(GuardTypeMismatch { rule_id: 0 }));
+}
+
+#[test]
+fn guard_normalization() {
+    use Formula::*;
+    let v = |id| TermVal(Term::Value(id));
+    let f = And(vec![
+        True,
+        And(vec![v(0), Or(vec![False, v(1)])]),
+        TermVal(Term::Boolean(Box::new(Not(Box::new(Not(Box::new(v(2)))))))),
+    ]);
+    assert_eq!(f.normalized(), And(vec![v(0), v(1), v(2)]));
+    assert_eq!(Or(vec![v(0), None(vec![])]).normalized(), True);
+    assert_eq!(And(vec![v(0), None(vec![True])]).normalized(), False);
+    assert_eq!(
+        None(vec![MemIsNull(3)]).normalized(),
+        Not(Box::new(MemIsNull(3)))
+    );
+}
+
+#[test]
+fn dyn_guard_lifting() {
+    let mut p = transform_builder(
+        "protocol Lift {
+            putter a: u32;
+            getter b, c: u32;
+            mem m: u32 = 1;
+            rule null(m) & true { a => b; }
+            rule !null(m) & (true | a == a) { a => c; m => ; }
+            rule false | null(m) & !null(m) { a => b; }
+        }",
+    );
+    p.init_memory(3, 1u32).unwrap();
+    let p = p.build().unwrap();
+    // the last rule can never fire, and the others need not evaluate their guards
+    assert_eq!(p.r.rules.len(), 2);
+    assert!(p.r.rules.iter().all(|r| r.guard_pred.is_none()));
+    assert!(p.r.rules[0].guard_mem.test(3) && !p.r.rules[0].guard_full.test(3));
+    assert!(!p.r.rules[1].guard_mem.test(3) && p.r.rules[1].guard_full.test(3));
+
+    let (mut a, mut b, mut c): (Putter<u32>, Getter<u32>, Getter<u32>) =
+        putters_getters![p => 0,1,2];
+    crossbeam::scope(|s| {
+        s.spawn(move |_| {
+            a.put(1);
+            a.put(2);
+        });
+        s.spawn(move |_| assert_eq!(c.get(), 1));
+        s.spawn(move |_| assert_eq!(b.get(), 2));
+    })
+    .expect("Crashed!");
+}
+
+proto! {
+    InlineAlternator<T> {
+        putter a, b: T;
+        getter c: T;
+        mem m: T;
+        rule { a => c; b => m; }
+        rule { m => c; }
+    }
+}
+
+#[test]
+fn proto_macro_alternator() {
+    let (mut a, mut b, mut c) = InlineAlternator::<u32>::instantiate_and_claim();
+    crossbeam::scope(|s| {
+        s.spawn(move |_| {
+            for i in 0..3 {
+                a.put(i);
+            }
+        });
+        s.spawn(move |_| {
+            for i in 0..3 {
+                b.put(i + 10);
+            }
+        });
+        for i in 0..3 {
+            assert_eq!(c.get(), i);
+            assert_eq!(c.get(), i + 10);
+        }
+    })
+    .expect("Crashed!");
+}
+
+proto! {
+    InlinePriorities {
+        putter a: u32;
+        getter b: u32;
+        rule priority -1 (Formula::True) { a => b; }
+        rule priority 2 { a => ; }
+        rule { a => b; }
+    }
+}
+
+#[test]
+fn proto_macro_priorities() {
+    let rules = &InlinePriorities::typeless_proto_def().behaviour.rules;
+    let priorities: Vec<i32> = rules.iter().map(|r| r.priority).collect();
+    assert_eq!(priorities, vec![-1, 2, 0]);
+}
+
+fn is_odd(r: &mut MaybeUninit<bool>, x: *const u32) {
+    *r = MaybeUninit::new(unsafe { *x } % 2 == 1);
+}
+
+proto! {
+    InlineCounter {
+        putter a: u32;
+        getter odd, small: u32;
+        getter total: String;
+        mem limit: u32 = 5;
+        fn is_odd/1 = is_odd;
+        fn to_string/1 = to_string;
+        rule (Formula::FuncDeclaration { name: "is_odd", args: vec![Term::Value(a)] }) {
+            a => odd;
+        }
+        rule (Formula::And(vec![
+            Formula::Lt(Term::Value(a), Term::Value(limit)),
+            Formula::None(vec![Formula::FuncDeclaration { name: "is_odd", args: vec![Term::Value(a)] }]),
+        ])) {
+            a => small;
+        }
+        rule (Formula::Ge(Term::Value(a), Term::Value(limit))) { to_string(a) => total; }
+    }
+}
+
+#[test]
+fn proto_macro_funcs_and_memory() {
+    let (mut a, mut odd, mut small, mut total) = InlineCounter::instantiate_and_claim();
+    crossbeam::scope(|s| {
+        s.spawn(move |_| {
+            for i in 1..=4 {
+                a.put(i);
+            }
+            a.put(8);
+        });
+        s.spawn(move |_| assert_eq!((odd.get(), odd.get()), (1, 3)));
+        s.spawn(move |_| assert_eq!((small.get(), small.get()), (2, 4)));
+        s.spawn(move |_| assert_eq!(total.get(), "8"));
+    })
+    .expect("Crashed!");
+}
+
+#[test]
+fn claim_by_name() {
+    use crate::proto::{ClaimResult, PortDesc, PortRole};
+    use std::{any::TypeId, convert::TryInto};
+    let p = InlineAlternator::<u32>::instantiate();
+    let listed: Vec<_> = p
+        .ports()
+        .iter()
+        .map(
+            |PortDesc {
+                 loc_id, name, role, ..
+             }| (*loc_id, name.as_deref().unwrap(), *role),
+        )
+        .collect();
+    assert_eq!(
+        listed,
+        vec![
+            (0, "a", PortRole::Putter),
+            (1, "b", PortRole::Putter),
+            (2, "c", PortRole::Getter)
+        ]
+    );
+    assert_eq!(p.ports()[2].type_id, TypeId::of::<u32>());
+    assert_eq!(p.ports()[2].type_name, "u32");
+    assert_eq!(p.loc_name(3), Some("m"));
+    assert_eq!(p.loc_id_of("c"), Some(2));
+
+    let _b: Putter<u32> = p.claim_by_name("b").try_into().unwrap();
+    match p.claim_by_name::<u32>("b") {
+        ClaimResult::NotUnclaimed { loc_id, name } => {
+            assert_eq!((loc_id, name.as_deref()), (1, Some("b")))
+        }
+        _ => panic!("claimed twice"),
+    }
+    match p.claim_by_name::<u8>("c") {
+        ClaimResult::TypeMismatch { loc_id, name } => {
+            assert_eq!((loc_id, name.as_deref()), (2, Some("c")))
+        }
+        _ => panic!("claimed with the wrong type"),
+    }
+    match p.claim_by_name::<u32>("x") {
+        ClaimResult::UnknownName(name) => assert_eq!(name, "x"),
+        _ => panic!("claimed an unknown name"),
+    }
+    let _c: Getter<u32> = p.claim_by_name("c").try_into().unwrap();
+}
+
+#[test]
+fn group_add_error_names() {
+    use crate::{
+        proto::groups::{GroupAddError, PortGroup},
+        tokens::decimal::E0,
+    };
+    let p = InlineAlternator::<u32>::instantiate();
+    let mut group = PortGroup::new();
+    assert_eq!(
+        group.add_putter::<E0, u32>(&p, 2).err(),
+        Some(GroupAddError::GotGetterExpectedPutter {
+            loc_id: 2,
+            name: Some("c".to_owned())
+        })
+    );
+    assert_eq!(
+        group.add_getter::<E0, u8>(&p, 2).err(),
+        Some(GroupAddError::TypeMismatch {
+            loc_id: 2,
+            name: Some("c".to_owned())
+        })
+    );
+}
+
+#[test]
+fn group_within_region() {
+    use crate::{
+        proto::groups::{GroupAddError, PortGroup},
+        tokens::decimal::E0,
+    };
+    use std::convert::TryInto;
+    // two independent syncs, each in a region of its own
+    let def = TypelessProtoDef {
+        behaviour: BehaviourDef {
+            rules: vec![rule![Formula::True; 0=>1], rule![Formula::True; 2=>3]],
+        },
+        loc_kinds: map! {
+            0 => LocKind::PortPutter,
+            1 => LocKind::PortGetter,
+            2 => LocKind::PortPutter,
+            3 => LocKind::PortGetter,
+        },
+        loc_names: map! { 2 => "b".to_owned() },
+        families: Default::default(),
+    };
+    let loc_types = (0..=3).map(|id| (id, TypeInfo::new::<u32>())).collect();
+    let p = DynProtoBuilder::new(def, loc_types).build().unwrap();
+    assert_eq!(p.num_regions(), 2);
+    let mut group = PortGroup::new();
+    let _a = group.add_putter::<E0, u32>(&p, 0).unwrap();
+    assert_eq!(
+        group.add_putter::<E0, u32>(&p, 2).err(),
+        Some(GroupAddError::DifferentRegion {
+            loc_id: 2,
+            name: Some("b".to_owned())
+        })
+    );
+    let _c = group.add_getter::<E0, u32>(&p, 1).unwrap();
+    // the rejected port was unclaimed again
+    let _b: Putter<u32> = p.claim(2).try_into().unwrap();
+}
+
+#[test]
+fn dyn_duplicate_names() {
+    use crate::proto::definition::ProtoBuildErr;
+    let mut def = dyn_fifo_def();
+    def.loc_names = map! {
+        0 => "x".to_owned(),
+        1 => "y".to_owned(),
+        2 => "x".to_owned(),
+    };
+    let loc_types = (0..=2).map(|id| (id, TypeInfo::new::<u8>())).collect();
+    let mut b = DynProtoBuilder::new(def, loc_types);
+    b.init_memory(2, 3u8).unwrap();
+    assert_eq!(
+        b.build().err(),
+        Some(ProtoBuildErr::DuplicateLocName { loc_id: 2 })
+    );
+}
+
+#[test]
+fn dyn_diagnostics() {
+    use crate::proto::definition::{Diagnostic, ProtoBuildErr::*};
+    let def = TypelessProtoDef {
+        behaviour: BehaviourDef {
+            rules: vec![
+                rule![Formula::True; 0=>2],
+                rule![Formula::True; 2=>0; 1=>2],
+                rule![Formula::FuncDeclaration { name: "nope", args: vec![] }; 0=>3],
+            ],
+        },
+        loc_kinds: map! {
+            0 => LocKind::PortPutter,
+            1 => LocKind::PortPutter,
+            2 => LocKind::PortGetter,
+            3 => LocKind::MemInitialized,
+        },
+        loc_names: map! {
+            0 => "a".to_owned(),
+            2 => "c".to_owned(),
+            3 => "m".to_owned(),
+        },
+        families: Default::default(),
+    };
+    let loc_types = map! {
+        0 => TypeInfo::new::<u32>(),
+        1 => TypeInfo::new::<u32>(),
+        2 => TypeInfo::new::<String>(),
+        3 => TypeInfo::new::<u32>(),
+    };
+    let d = DynProtoBuilder::new(def.clone(), loc_types.clone())
+        .build_diagnosed()
+        .err()
+        .unwrap();
+    let errs: Vec<_> = d
+        .list
+        .iter()
+        .map(
+            |&Diagnostic {
+                 err,
+                 rule_id,
+                 action_id,
+             }| (err, rule_id, action_id),
+        )
+        .collect();
+    let mismatch = |rule_id, action_id, loc_id_putter| TypeMismatch {
+        rule_id,
+        action_id,
+        loc_id_putter,
+        loc_id_getter: 2,
+        putter_type: "u32",
+        getter_type: "alloc::string::String",
+    };
+    assert_eq!(
+        errs,
+        vec![
+            (MemoryFillPromiseBroken { loc_id: 3 }, None, None),
+            (mismatch(0, 0, 0), Some(0), Some(0)),
+            (LocCannotPut { loc_id: 2 }, Some(1), Some(0)),
+            (mismatch(1, 1, 1), Some(1), Some(1)),
+            (FunctionUndefined { name: "nope" }, Some(2), None),
+        ]
+    );
+    let lines: Vec<String> = d.to_string().lines().map(String::from).collect();
+    assert_eq!(
+        lines[0],
+        "error: location 3 (`m`: `u32`) was given no initial value"
+    );
+    assert_eq!(
+        lines[2],
+        "error: action 0 of rule 1: location 2 (`c`: `alloc::string::String`) \
+         cannot act as a putter"
+    );
+    assert_eq!(
+        lines[3],
+        "error: action 1 of rule 1 passes `u32` from location 1 (`u32`) to location 2 \
+         (`c`: `alloc::string::String`), which holds `alloc::string::String`"
+    );
+    assert_eq!(lines[4], "error: rule 2: function `nope` is not defined");
+
+    // without diagnostics, the first error is returned
+    let b = DynProtoBuilder::new(def, loc_types);
+    let e = b.build().err().unwrap();
+    assert_eq!(e, MemoryFillPromiseBroken { loc_id: 3 });
+    assert_eq!(e.to_string(), "location 3 was given no initial value");
+}
+
+proto! {
+    Misdefined {
+        putter a: u32;
+        getter b: String;
+        mem m: u32;
+        rule { a => b; }
+        rule (Formula::FuncDeclaration { name: "nope", args: vec![] }) { a => m; }
+    }
+}
+
+#[test]
+fn proto_diagnostics() {
+    use crate::proto::definition::ProtoBuildErr::*;
+    let d = Misdefined::try_instantiate_diagnosed().err().unwrap();
+    // rules are checked only once all functions are defined
+    assert_eq!(d.list.len(), 1);
+    assert_eq!(d.first(), FunctionUndefined { name: "nope" });
+    assert_eq!(
+        Misdefined::try_instantiate().err(),
+        Some(FunctionUndefined { name: "nope" })
+    );
+}
+
+fn sparse_fifo_def() -> TypelessProtoDef {
+    TypelessProtoDef {
+        behaviour: BehaviourDef {
+            rules: vec![
+                rule![Formula::True; 10=>500],
+                rule![Formula::True; 500=>7000],
+            ],
+        },
+        loc_kinds: map! {
+            10 => LocKind::PortPutter,
+            7000 => LocKind::PortGetter,
+            500 => LocKind::MemInitialized,
+        },
+        loc_names: map! {
+            10 => "in".to_owned(),
+            7000 => "out".to_owned(),
+        },
+        families: Default::default(),
+    }
+}
+
+#[test]
+fn sparse_loc_ids() {
+    use crate::proto::ClaimResult;
+    let def = sparse_fifo_def();
+    let loc_types = def
+        .loc_kinds
+        .keys()
+        .map(|&id| (id, TypeInfo::new::<u16>()))
+        .collect();
+    let mut b = DynProtoBuilder::new(def, loc_types);
+    b.init_memory(500, 7u16).unwrap();
+    let p = b.build().unwrap();
+    // no spaces for the gaps between LocIds
+    assert_eq!(p.r.spaces.len(), 3);
+    let ids: Vec<LocId> = p.ports().iter().map(|port| port.loc_id).collect();
+    assert_eq!(ids, vec![10, 7000]);
+    assert_eq!(p.loc_id_of("out"), Some(7000));
+    match p.claim::<u16>(11) {
+        ClaimResult::NotUnclaimed {
+            loc_id: 11,
+            name: None,
+        } => {}
+        _ => panic!("claimed a LocId in a gap"),
+    }
+    let (mut i, mut o): (Putter<u16>, Getter<u16>) = putters_getters![p => 10, 7000];
+    assert_eq!(format!("{:?}", o), "Getter { id: 7000 }");
+    crossbeam::scope(|s| {
+        s.spawn(move |_| assert!(i.put(8).is_none()));
+        assert_eq!(o.get(), 7);
+        assert_eq!(o.get(), 8);
+    })
+    .expect("Crashed!");
+}
+
+#[test]
+fn sparse_loc_id_diagnostics() {
+    use crate::proto::definition::ProtoBuildErr::*;
+    let def = sparse_fifo_def();
+    let loc_types = map! {
+        10 => TypeInfo::new::<u16>(),
+        7000 => TypeInfo::new::<u8>(),
+        500 => TypeInfo::new::<u16>(),
+    };
+    let mut b = DynProtoBuilder::new(def, loc_types);
+    b.init_memory(500, 7u16).unwrap();
+    let d = b.build_diagnosed().err().unwrap();
+    assert_eq!(
+        d.first(),
+        TypeMismatch {
+            rule_id: 1,
+            action_id: 0,
+            loc_id_putter: 500,
+            loc_id_getter: 7000,
+            putter_type: "u16",
+            getter_type: "u8",
+        }
+    );
+    assert!(d.to_string().contains("location 7000 (`out`: `u8`)"));
+}
+
+#[test]
+fn observe_events() {
+    use crate::proto::observe::ProtoEvent::{self, *};
+    let def = sparse_fifo_def();
+    let loc_types = def
+        .loc_kinds
+        .keys()
+        .map(|&id| (id, TypeInfo::new::<u16>()))
+        .collect();
+    let mut b = DynProtoBuilder::new(def, loc_types);
+    b.init_memory(500, 7u16).unwrap();
+    let p = b.build().unwrap();
+    let events = Arc::new(Mutex::new(vec![]));
+    let events2 = events.clone();
+    p.set_observer(Arc::new(move |e: ProtoEvent| events2.lock().push(e)));
+    let (mut i, mut o): (Putter<u16>, Getter<u16>) = putters_getters![p => 10, 7000];
+    assert_eq!(o.get(), 7);
+    assert!(i.put(8).is_none());
+    assert!(p.take_observer().is_some());
+    assert_eq!(o.get(), 8);
+    assert_eq!(
+        *events.lock(),
+        vec![
+            PortReady { loc_id: 7000 },
+            RuleFired { rule_id: 1 },
+            MemEmptied { loc_id: 500 },
+            PortReady { loc_id: 10 },
+            MemFilled { loc_id: 500 },
+            RuleFired { rule_id: 0 },
+        ]
+    );
+}
+
+#[test]
+fn detect_deadlock() {
+    use crate::proto::{
+        observe::{Deadlock, ProtoEvent, ProtoObserver},
+        PutTimeoutResult,
+    };
+    #[derive(Default)]
+    struct Detector(Mutex<Vec<Deadlock>>);
+    impl ProtoObserver for Detector {
+        fn observe(&self, _event: ProtoEvent) {}
+        fn deadlock(&self, deadlock: &Deadlock) {
+            self.0.lock().push(deadlock.clone());
+        }
+    }
+    // the getter of the fifo waits for a datum the putter never offers.
+    // the other putter may only pass data while the fifo is full
+    let def = TypelessProtoDef {
+        behaviour: BehaviourDef {
+            rules: vec![
+                rule![Formula::True; 0=>2],
+                rule![Formula::True; 2=>1],
+                rule![Formula::Not(Box::new(Formula::MemIsNull(2))); 3=>1],
+            ],
+        },
+        loc_kinds: map! {
+            0 => LocKind::PortPutter,
+            1 => LocKind::PortGetter,
+            2 => LocKind::MemUninitialized,
+            3 => LocKind::PortPutter,
+        },
+        loc_names: Default::default(),
+        families: Default::default(),
+    };
+    let loc_types = (0..=3).map(|id| (id, TypeInfo::new::<u8>())).collect();
+    let p = DynProtoBuilder::new(def, loc_types).build().unwrap();
+    let detector = Arc::new(Detector::default());
+    p.set_observer(detector.clone());
+    let (i, mut o, mut other): (Putter<u8>, Getter<u8>, Putter<u8>) =
+        putters_getters![p => 0, 1, 3];
+    // ports that are claimed but idle may yet act
+    assert!(matches!(
+        other.put_timeout(5, dur(20)),
+        PutTimeoutResult::Timeout(5)
+    ));
+    assert!(detector.0.lock().is_empty());
+    drop(i);
+    crossbeam::scope(|s| {
+        s.spawn(move |_| {
+            let res = other.put_timeout(6, dur(300));
+            assert!(matches!(res, PutTimeoutResult::Timeout(6)));
+        });
+        assert_eq!(o.get_timeout(dur(100)), None);
+    })
+    .expect("Crashed!");
+    assert_eq!(
+        *detector.0.lock(),
+        vec![Deadlock {
+            ports: vec![1, 3],
+            rules: vec![1, 2],
+        }]
+    );
+}
+
+#[test]
+fn snapshot_fifo() {
+    use crate::proto::{observe::ProtoSnapshot, PutTimeoutResult};
+    let def = sparse_fifo_def();
+    let loc_types = def
+        .loc_kinds
+        .keys()
+        .map(|&id| (id, TypeInfo::new::<u16>()))
+        .collect();
+    let mut b = DynProtoBuilder::new(def, loc_types);
+    b.init_memory(500, 7u16).unwrap();
+    let p = b.build().unwrap();
+    let idle = ProtoSnapshot {
+        claimed: vec![],
+        waiting: vec![],
+        tentative: vec![],
+        awaiting_state: vec![],
+        full_mems: vec![500],
+        commitments: vec![],
+        enabled_rules: vec![],
+    };
+    assert_eq!(p.snapshot(), idle);
+    let (mut i, o): (Putter<u16>, Getter<u16>) = putters_getters![p => 10, 7000];
+    crossbeam::scope(|s| {
+        s.spawn(|_| {
+            // the fifo is full
+            let res = i.put_timeout(8, dur(300));
+            assert!(matches!(res, PutTimeoutResult::Timeout(8)));
+        });
+        thread::sleep(dur(100));
+        let snapshot = p.snapshot();
+        assert_eq!(snapshot.claimed, vec![10, 7000]);
+        assert_eq!(snapshot.waiting, vec![10]);
+        assert!(snapshot.enabled_rules.is_empty());
+    })
+    .expect("Crashed!");
+    drop((i, o));
+    assert_eq!(p.snapshot(), idle);
+}
+
+#[test]
+fn rules_indexed_by_loc() {
+    use crate::proto::family;
+    use std::convert::TryInto;
+    let p = family::instantiate::<u32>(family::merger(300)).unwrap();
+    let out = p.loc_id_of("out").unwrap();
+    assert_eq!(p.r.loc_rules[out].len(), 300);
+    assert_eq!(p.r.loc_rules[7], vec![7]);
+    let mut ins: Vec<Putter<u32>> = p.claim_putters("in").unwrap();
+    let mut o: Getter<u32> = p.claim_by_name("out").try_into().unwrap();
+    crossbeam::scope(|s| {
+        let last = ins.last_mut().unwrap();
+        s.spawn(move |_| assert!(last.put(299).is_none()));
+        assert_eq!(o.get(), 299);
+    })
+    .expect("Crashed!");
+}
+
+#[test]
+fn rules_of_untouched_locs_fire() {
+    use std::convert::TryInto;
+    // the first rule involves no port, yet fires once the getter is ready
+    let def = TypelessProtoDef {
+        behaviour: BehaviourDef {
+            rules: vec![rule![Formula::True; 0=>1], rule![Formula::True; 1=>2]],
+        },
+        loc_kinds: map! {
+            0 => LocKind::MemInitialized,
+            1 => LocKind::MemUninitialized,
+            2 => LocKind::PortGetter,
+        },
+        loc_names: Default::default(),
+        families: Default::default(),
+    };
+    let loc_types = (0..=2).map(|id| (id, TypeInfo::new::<u8>())).collect();
+    let mut b = DynProtoBuilder::new(def, loc_types);
+    b.init_memory(0, 9u8).unwrap();
+    let p = b.build().unwrap();
+    let mut o: Getter<u8> = p.claim(2).try_into().unwrap();
+    assert_eq!(o.get_timeout(dur(500)), Some(9));
+}
+
+#[test]
+fn independent_regions() {
+    use std::convert::TryInto;
+    // three rules sharing no locations, the last of which moves an initialized memory cell
+    let def = TypelessProtoDef {
+        behaviour: BehaviourDef {
+            rules: vec![
+                rule![Formula::True; 0=>1],
+                rule![Formula::True; 2=>3],
+                rule![Formula::True; 4=>5],
+            ],
+        },
+        loc_kinds: map! {
+            0 => LocKind::PortPutter,
+            1 => LocKind::PortGetter,
+            2 => LocKind::PortPutter,
+            3 => LocKind::PortGetter,
+            4 => LocKind::MemInitialized,
+            5 => LocKind::PortGetter,
+        },
+        loc_names: Default::default(),
+        families: Default::default(),
+    };
+    let loc_types = (0..=5).map(|id| (id, TypeInfo::new::<u8>())).collect();
+    let mut b = DynProtoBuilder::new(def, loc_types);
+    b.init_memory(4, 9u8).unwrap();
+    let p = b.build().unwrap();
+    assert_eq!(p.num_regions(), 3);
+    assert_eq!(p.r.loc_region, vec![0, 0, 1, 1, 2, 2]);
+
+    let mut a_in: Putter<u8> = p.claim(0).try_into().unwrap();
+    let mut a_out: Getter<u8> = p.claim(1).try_into().unwrap();
+    let mut b_in: Putter<u8> = p.claim(2).try_into().unwrap();
+    let mut b_out: Getter<u8> = p.claim(3).try_into().unwrap();
+    let mut c_out: Getter<u8> = p.claim(5).try_into().unwrap();
+    // while region 0 is locked, as if mid-firing, the other regions fire all the same
+    let held = p.regions[0].lock();
+    let (done, finished) = crossbeam::channel::bounded(1);
+    thread::spawn(move || assert!(b_in.put(2).is_none()));
+    thread::spawn(move || {
+        assert_eq!(b_out.get(), 2);
+        assert_eq!(c_out.get(), 9);
+        done.send(()).unwrap();
+    });
+    assert!(finished.recv_timeout(dur(2000)).is_ok());
+    drop(held);
+    crossbeam::scope(|s| {
+        s.spawn(move |_| assert!(a_in.put(1).is_none()));
+        assert_eq!(a_out.get(), 1);
+    })
+    .expect("Crashed!");
+
+    // all rules of a merger share its getter
+    let merger = crate::proto::family::instantiate::<u32>(crate::proto::family::merger(3));
+    assert_eq!(merger.unwrap().num_regions(), 1);
+}
+
+#[test]
+fn coordinator_thread_fires() {
+    use crate::proto::observe::ProtoEvent;
+    use std::convert::TryInto;
+    // a chain of memory cells, such that each put sets off several firings
+    let def = TypelessProtoDef {
+        behaviour: BehaviourDef {
+            rules: vec![
+                rule![Formula::True; 0=>1],
+                rule![Formula::True; 1=>2],
+                rule![Formula::True; 2=>3],
+                rule![Formula::True; 3=>4],
+            ],
+        },
+        loc_kinds: map! {
+            0 => LocKind::PortPutter,
+            1 => LocKind::MemUninitialized,
+            2 => LocKind::MemUninitialized,
+            3 => LocKind::MemUninitialized,
+            4 => LocKind::PortGetter,
+        },
+        loc_names: Default::default(),
+        families: Default::default(),
+    };
+    let loc_types = (0..=4).map(|id| (id, TypeInfo::new::<u32>())).collect();
+    let mut b = DynProtoBuilder::new(def, loc_types);
+    b.coordinator_thread();
+    let p = b.build().unwrap();
+    let firers = Arc::new(Mutex::new(vec![]));
+    let firers2 = firers.clone();
+    p.set_observer(Arc::new(move |e: ProtoEvent| {
+        if let ProtoEvent::RuleFired { .. } = e {
+            let name = std::thread::current().name().map(String::from);
+            firers2.lock().push(name);
+        }
+    }));
+    let mut i: Putter<u32> = p.claim(0).try_into().unwrap();
+    let mut o: Getter<u32> = p.claim(4).try_into().unwrap();
+    for x in 0..3 {
+        assert!(i.put(x).is_none());
+        assert_eq!(o.get(), x);
+    }
+    let firers = firers.lock();
+    assert_eq!(firers.len(), 12);
+    assert!(firers
+        .iter()
+        .all(|name| name.as_deref() == Some("reo coordinator")));
+}
+
+#[test]
+fn coordinator_thread_fires_commitment() {
+    use crate::proto::observe::ProtoEvent;
+    use std::convert::TryInto;
+    let def = TypelessProtoDef {
+        behaviour: BehaviourDef {
+            rules: vec![rule![Formula::True; 0=>1]],
+        },
+        loc_kinds: map! {
+            0 => LocKind::PortPutter,
+            1 => LocKind::PortGetter,
+        },
+        loc_names: Default::default(),
+        families: Default::default(),
+    };
+    let loc_types = (0..=1).map(|id| (id, TypeInfo::new::<u32>())).collect();
+    let mut b = DynProtoBuilder::new(def, loc_types);
+    b.coordinator_thread();
+    let p = b.build().unwrap();
+    let firers = Arc::new(Mutex::new(vec![]));
+    let firers2 = firers.clone();
+    p.set_observer(Arc::new(move |e: ProtoEvent| {
+        if let ProtoEvent::RuleFired { .. } = e {
+            let name = std::thread::current().name().map(String::from);
+            firers2.lock().push(name);
+        }
+    }));
+    let mut i: Putter<u32> = p.claim(0).try_into().unwrap();
+    let mut o: Getter<u32> = p.claim(1).try_into().unwrap();
+    // offer the putter tentatively, as `PortGroup::deliberate` does
+    let id = p.r.id_map.internal(0).unwrap();
+    {
+        let mut w = p.lock_region_of(id);
+        w.active.ready.set_to(id, true);
+        w.ready_tentative.set_to(id, true);
+        w.mark_rules_of(&p.r, id);
+    }
+    let dropbox = match p.r.get_space(id) {
+        Some(crate::proto::Space::PoPu(space)) => &space.dropbox,
+        _ => unreachable!(),
+    };
+    crossbeam::scope(|s| {
+        s.spawn(move |_| assert_eq!(o.get(), 7));
+        // the coordinator commits to the rule, then the putter resolves the commitment
+        assert_eq!(dropbox.recv(), 0);
+        p.lock_region_of(id).active.ready.set_to(id, false);
+        assert!(i.put(7).is_none());
+    })
+    .expect("Crashed!");
+    assert_eq!(*firers.lock(), vec![Some("reo coordinator".to_owned())]);
+}
+
+/// The ids of the rules that fire while 6 data pass from putter 0 to getter 1.
+fn rules_fired_passing(
+    rules: Vec<RuleDef>,
+    selector: Box<dyn crate::proto::select::RuleSelector>,
+) -> Vec<usize> {
+    use crate::proto::observe::ProtoEvent;
+    use std::convert::TryInto;
+    let def = TypelessProtoDef {
+        behaviour: BehaviourDef { rules },
+        loc_kinds: map! {
+            0 => LocKind::PortPutter,
+            1 => LocKind::PortGetter,
+        },
+        loc_names: Default::default(),
+        families: Default::default(),
+    };
+    let loc_types = (0..=1).map(|id| (id, TypeInfo::new::<u32>())).collect();
+    let mut b = DynProtoBuilder::new(def, loc_types);
+    b.rule_selector(selector);
+    let p = b.build().unwrap();
+    let fired = Arc::new(Mutex::new(vec![]));
+    let fired2 = fired.clone();
+    p.set_observer(Arc::new(move |e: ProtoEvent| {
+        if let ProtoEvent::RuleFired { rule_id } = e {
+            fired2.lock().push(rule_id);
+        }
+    }));
+    let mut i: Putter<u32> = p.claim(0).try_into().unwrap();
+    let mut o: Getter<u32> = p.claim(1).try_into().unwrap();
+    crossbeam::scope(|s| {
+        s.spawn(move |_| {
+            for x in 0..6 {
+                assert!(i.put(x).is_none());
+            }
+        });
+        for x in 0..6 {
+            assert_eq!(o.get(), x);
+        }
+    })
+    .expect("Crashed!");
+    let fired = fired.lock().clone();
+    fired
+}
+
+#[test]
+fn rule_selectors() {
+    use crate::proto::select::{FirstMatch, RoundRobin, SeededRandom};
+    let same_rules = || vec![rule![Formula::True; 0=>1]; 3];
+    assert_eq!(
+        rules_fired_passing(same_rules(), Box::new(FirstMatch)),
+        vec![0; 6]
+    );
+    assert_eq!(
+        rules_fired_passing(same_rules(), Box::new(RoundRobin::default())),
+        vec![0, 1, 2, 0, 1, 2]
+    );
+    let random = rules_fired_passing(same_rules(), Box::new(SeededRandom::new(3)));
+    assert_eq!(
+        random,
+        rules_fired_passing(same_rules(), Box::new(SeededRandom::new(3)))
+    );
+    assert!(random.iter().all(|&rule_id| rule_id < 3));
+}
+
+#[test]
+fn rule_priority() {
+    use crate::proto::select::RoundRobin;
+    let mut rules = vec![rule![Formula::True; 0=>1]; 3];
+    rules[1].priority = 2;
+    rules[2].priority = -1;
+    assert_eq!(
+        rules_fired_passing(rules.clone(), Box::new(RoundRobin::default())),
+        vec![1; 6]
+    );
+    rules[0].priority = 2;
+    assert_eq!(
+        rules_fired_passing(rules, Box::new(RoundRobin::default())),
+        vec![0, 1, 0, 1, 0, 1]
+    );
+}
+
+#[test]
+fn port_closure() {
+    use crate::proto::{family, PortClosed};
+    use std::convert::TryInto;
+    let p = family::instantiate::<u32>(family::merger(2)).unwrap();
+    let out = p.loc_id_of("out").unwrap();
+    let doomed = |id| {
+        let id = p.r.id_map.internal(id).unwrap();
+        p.lock_region_of(id).port_doomed(&p.r, id)
+    };
+    let mut ins: Vec<Putter<u32>> = p.claim_putters("in").unwrap();
+    let mut o: Getter<u32> = p.claim_by_name("out").try_into().unwrap();
+    let in1 = ins.pop().unwrap();
+    let mut in0 = ins.pop().unwrap();
+    // unclaimed ports may yet be claimed, so only dropped ones close
+    assert!(!doomed(out));
+    drop(in1);
+    assert!(!doomed(out));
+    crossbeam::scope(|s| {
+        s.spawn(|_| assert_eq!(in0.try_put(3), Ok(None)));
+        assert_eq!(o.try_get(), Ok(3));
+    })
+    .expect("Crashed!");
+    // the getter is waiting when its last peer is dropped
+    crossbeam::scope(|s| {
+        s.spawn(|_| assert_eq!(o.try_get(), Err(PortClosed(()))));
+        thread::sleep(dur(50));
+        drop(in0);
+    })
+    .expect("Crashed!");
+    assert!(doomed(out));
+    assert_eq!(o.try_get(), Err(PortClosed(())));
+
+    // claiming a port again reopens it
+    let mut in0: Putter<u32> = p.claim_by_name("in[0]").try_into().unwrap();
+    drop(o);
+    assert_eq!(in0.try_put(4), Err(PortClosed(4)));
+    let mut o: Getter<u32> = p.claim(out).try_into().unwrap();
+    crossbeam::scope(|s| {
+        s.spawn(move |_| assert_eq!(in0.try_put(5), Ok(None)));
+        assert_eq!(o.get(), 5);
+    })
+    .expect("Crashed!");
+}
+
+#[test]
+fn port_closure_through_memory() {
+    use crate::primitives::FifoN;
+    use std::convert::TryInto;
+    let p = FifoN::<u32, 1>::instantiate();
+    let mut i: Putter<u32> = p.claim(0).try_into().unwrap();
+    let mut o: Getter<u32> = p.claim(1).try_into().unwrap();
+    assert_eq!(i.try_put(3), Ok(None));
+    drop(i);
+    assert_eq!(o.try_get(), Ok(3));
+    // the memory cell is empty, and only the dropped putter could fill it.
+    // yet the getter is not doomed, as its rule involves no closed port
+    let o_id = p.r.id_map.internal(1).unwrap();
+    assert!(!p.lock_region_of(o_id).port_doomed(&p.r, o_id));
+    crossbeam::scope(|s| {
+        s.spawn(|_| assert_eq!(o.try_get(), Ok(4)));
+        thread::sleep(dur(50));
+        let mut i: Putter<u32> = p.claim(0).try_into().unwrap();
+        assert_eq!(i.try_put(4), Ok(None));
+    })
+    .expect("Crashed!");
+}
@@ -0,0 +1,592 @@
+//! Recording the rules a protocol instance fires, and replaying such a trace.
+//! A trace is a stream of `Firing`s, one JSON object per line.
+//! ```ignore
+//! p.set_recorder(TraceRecorder::to_file("proto.trace")?.recording_data());
+//! // ... run the application ...
+//! p.take_recorder().unwrap().finish()?;
+//!
+//! let trace = read_trace(BufReader::new(File::open("proto.trace")?))?;
+//! replay(&fresh_instance, &trace, Duration::from_secs(1))?;
+//! ```
+
+use super::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufRead, LineWriter, Write},
+    path::Path,
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+/// A record of the protocol committing to fire a rule.
+/// LocIds are those of the protocol's definition.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Firing {
+    pub rule_id: usize,
+    /// The ports involved in the rule, ascending.
+    pub ports: Vec<LocId>,
+    /// The full memory cells of the rule's region before and after firing, ascending.
+    pub mem_before: Vec<LocId>,
+    pub mem_after: Vec<LocId>,
+    /// The JSON-serialized datum of each putter of the rule, if recorded and serializable.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub data: Vec<(LocId, String)>,
+}
+
+/// Writes the firings of the protocol instance it is installed in with `ProtoAll::set_recorder`.
+/// Recording stops at the first failed write. The error is returned by `finish`.
+pub struct TraceRecorder {
+    out: Sink,
+    record_data: bool,
+    error: Option<io::Error>,
+}
+enum Sink {
+    Write(Box<dyn Write + Send>),
+    // the firings of an instance driven by `replay`
+    Replay(Sender<Firing>),
+}
+impl TraceRecorder {
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        Self {
+            out: Sink::Write(Box::new(out)),
+            record_data: false,
+            error: None,
+        }
+    }
+    /// Creates (or truncates) the file at `path`. Every firing is flushed as it is written.
+    pub fn to_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(LineWriter::new(File::create(path)?)))
+    }
+    /// Also records the data that putters offer, as far as their types are serializable.
+    pub fn recording_data(mut self) -> Self {
+        self.record_data = true;
+        self
+    }
+    /// Flushes the output, returning the first error that occurred while recording.
+    pub fn finish(mut self) -> io::Result<()> {
+        match (self.error.take(), &mut self.out) {
+            (Some(e), _) => Err(e),
+            (None, Sink::Write(out)) => out.flush(),
+            (None, Sink::Replay(_)) => Ok(()),
+        }
+    }
+
+    /// Invoked under the lock, before `memory_bits` are assigned. LocIds are internal.
+    pub(super) fn record(&mut self, r: &ProtoR, memory_bits: &BitSet, rule: &RunRule) {
+        if self.error.is_some() {
+            return;
+        }
+        let mut after = memory_bits.clone();
+        assign_memory_bits(&mut after, rule);
+        let data = if self.record_data {
+            rule.actions
+                .iter()
+                .filter_map(|a| {
+                    let space = r.get_space_putter(a.putter)?;
+                    let ptr = space.get_ptr();
+                    if ptr.is_null() {
+                        // a tentative putter may not have offered its datum yet
+                        return None;
+                    }
+                    let datum = unsafe { space.type_info.funcs.serialize.execute(ptr)? };
+                    Some((r.id_map.external(a.putter), datum))
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+        let firing = Firing {
+            rule_id: rule.rule_id,
+            ports: ports_of(r, rule),
+            mem_before: r.full_mems(memory_bits),
+            mem_after: r.full_mems(&after),
+            data,
+        };
+        match &mut self.out {
+            Sink::Write(out) => {
+                let res = serde_json::to_writer(&mut *out, &firing)
+                    .map_err(io::Error::from)
+                    .and_then(|()| out.write_all(b"\n"));
+                if let Err(e) = res {
+                    self.error = Some(e);
+                }
+            }
+            Sink::Replay(replayed) => {
+                // replay has given up waiting if this fails
+                let _ = replayed.send(firing);
+            }
+        }
+    }
+}
+
+fn ports_of(r: &ProtoR, rule: &RunRule) -> Vec<LocId> {
+    rule.guard_ready
+        .iter_sparse()
+        .filter(|&id| matches!(r.get_space(id), Some(Space::PoPu(_)) | Some(Space::PoGe(_))))
+        .map(|id| r.id_map.external(id))
+        .collect()
+}
+
+/// Reads a trace as written by a `TraceRecorder`.
+pub fn read_trace<R: BufRead>(input: R) -> Result<Vec<Firing>, ReadTraceError> {
+    let mut trace = vec![];
+    for (i, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| ReadTraceError::Io(e.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let firing = serde_json::from_str(&line).map_err(|e| ReadTraceError::Json {
+            line: i + 1,
+            err: e.to_string(),
+        })?;
+        trace.push(firing);
+    }
+    Ok(trace)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadTraceError {
+    Io(String),
+    Json { line: usize, err: String },
+}
+impl fmt::Display for ReadTraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadTraceError::Io(e) => write!(f, "io error: {}", e),
+            ReadTraceError::Json { line, err } => write!(f, "bad firing at line {}: {}", line, err),
+        }
+    }
+}
+impl std::error::Error for ReadTraceError {}
+
+/// The first firing of a trace that the replayed protocol does not reproduce.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Index of the firing in the trace.
+    pub index: usize,
+    pub kind: DivergenceKind,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DivergenceKind {
+    /// The protocol has no rule with the recorded id, or the rule can never fire.
+    UnknownRule,
+    /// A recorded port is no port of the protocol, or was claimed by someone else.
+    Unclaimable { port: LocId },
+    /// The trace has no datum for a recorded putter, or it cannot be decoded.
+    MissingDatum { putter: LocId },
+    /// No rule fired in time.
+    RuleDisabled,
+    /// Another rule fired.
+    OtherRule { replayed: usize },
+    /// The full memory cells before firing differ from those recorded.
+    MemBefore { replayed: Vec<LocId> },
+    /// The rule involves ports other than those recorded.
+    Ports { replayed: Vec<LocId> },
+    /// The full memory cells after firing differ from those recorded.
+    MemAfter { replayed: Vec<LocId> },
+    /// The data of the putters differ from those recorded.
+    Data { replayed: Vec<(LocId, String)> },
+    /// The getter did not receive the recorded datum of its putter.
+    Delivered {
+        getter: LocId,
+        replayed: Option<String>,
+    },
+}
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use DivergenceKind::*;
+        write!(f, "firing {} diverges: ", self.index)?;
+        match &self.kind {
+            UnknownRule => write!(f, "the protocol has no such rule"),
+            Unclaimable { port } => write!(f, "port {} cannot be claimed", port),
+            MissingDatum { putter } => write!(f, "putter {} has no datum to put", putter),
+            RuleDisabled => write!(f, "no rule fires"),
+            OtherRule { replayed } => write!(f, "rule {} fires instead", replayed),
+            MemBefore { replayed } => write!(f, "memory cells {:?} are full before", replayed),
+            Ports { replayed } => write!(f, "the rule involves ports {:?}", replayed),
+            MemAfter { replayed } => write!(f, "memory cells {:?} are full after", replayed),
+            Data { replayed } => write!(f, "the putters offer {:?}", replayed),
+            Delivered { getter, replayed } => {
+                write!(f, "getter {} receives {:?}", getter, replayed)
+            }
+        }
+    }
+}
+impl std::error::Error for Divergence {}
+
+/// Drives the protocol instance through the trace, checking that it fires the recorded
+/// rules in order. For each firing, the recorded ports are made ready at once, each putter
+/// offering its recorded datum. The rule that fires must then involve the same memory cells
+/// and data as recorded, and each getter must receive the datum of its putter (unless
+/// transformed). Firings without ports must happen of their own accord.
+///
+/// The ports are claimed for the replay, and unclaimed afterwards. The instance's recorder
+/// is replaced for the replay, and removed afterwards. The instance is left in the state the
+/// trace led to. The trace must be recorded with data of deserializable types.
+/// Waits at most `patience` for a firing to happen, and for each of its ports to complete.
+pub fn replay(p: &Arc<ProtoAll>, trace: &[Firing], patience: Duration) -> Result<(), Divergence> {
+    let (replaying, replayed) = mpsc::channel();
+    p.set_recorder(TraceRecorder {
+        out: Sink::Replay(replaying),
+        record_data: true,
+        error: None,
+    });
+    p.start_coordinator();
+    let mut claimed = HashMap::default();
+    let res = trace.iter().enumerate().try_for_each(|(index, firing)| {
+        replay_firing(p, firing, patience, &replayed, &mut claimed)
+            .map_err(|kind| Divergence { index, kind })
+    });
+    let _ = p.take_recorder();
+    for (id, ClaimedPort { info, closed }) in claimed {
+        let mut w = p.lock_region_of(id);
+        w.unclaimed_ports.insert(id, info);
+        w.closed.set_to(id, closed);
+    }
+    res
+}
+
+/// A port claimed by `replay`, with the state to restore when it is unclaimed again.
+struct ClaimedPort {
+    info: PortInfo,
+    closed: bool,
+}
+
+fn replay_firing(
+    p: &Arc<ProtoAll>,
+    firing: &Firing,
+    patience: Duration,
+    replayed: &Receiver<Firing>,
+    claimed: &mut HashMap<LocId, ClaimedPort>,
+) -> Result<(), DivergenceKind> {
+    use DivergenceKind::*;
+    let r = &p.r;
+    let rule = r
+        .rules
+        .iter()
+        .find(|rule| rule.rule_id == firing.rule_id)
+        .ok_or(UnknownRule)?;
+    let mut storage = Storage::default();
+    let mut putters = vec![];
+    let mut getters = vec![];
+    for &port in firing.ports.iter() {
+        let id = claim(p, port, claimed).ok_or(Unclaimable { port })?;
+        match r.get_po_pu(id) {
+            Some(po_pu) => {
+                let datum = firing
+                    .data
+                    .iter()
+                    .find(|(putter, _)| *putter == port)
+                    .and_then(|(_, datum)| unsafe {
+                        decode(&mut storage, &po_pu.p.type_info, datum)
+                    })
+                    .ok_or(MissingDatum { putter: port })?;
+                putters.push((id, datum));
+            }
+            None => getters.push(id),
+        }
+    }
+    for &(id, datum) in putters.iter() {
+        r.get_po_pu(id).unwrap().p.set_ptr(datum);
+    }
+    let mut ports: Vec<LocId> = putters.iter().map(|&(id, _)| id).collect();
+    ports.extend(getters.iter().copied());
+    ports.sort_by_key(|&id| r.loc_region[id]);
+    let (fired, delivered) = std::thread::scope(|s| {
+        let gets: Vec<_> = getters
+            .iter()
+            .map(|&id| s.spawn(move || (id, unsafe { get_serialized(p, id, patience) })))
+            .collect();
+        for region in ports.chunk_by(|&a, &b| r.loc_region[a] == r.loc_region[b]) {
+            p.lock_region_of(region[0]).ports_ready(r, region);
+        }
+        let fired = replayed.recv_timeout(patience).ok();
+        for &(id, datum) in putters.iter() {
+            let po_pu = r.get_po_pu(id).unwrap();
+            if po_pu.await_msg_timeout(p, patience, id) == Some(1) {
+                // moved out by a getter
+                unsafe { storage.forget_inside(datum, &po_pu.p.type_info) }
+            }
+        }
+        let delivered: Vec<_> = gets.into_iter().map(|get| get.join().unwrap()).collect();
+        (fired, delivered)
+    });
+    let fired = fired.ok_or(RuleDisabled)?;
+    if fired.rule_id != firing.rule_id {
+        return Err(OtherRule {
+            replayed: fired.rule_id,
+        });
+    }
+    if fired.mem_before != firing.mem_before {
+        return Err(MemBefore {
+            replayed: fired.mem_before,
+        });
+    }
+    if fired.ports != firing.ports {
+        return Err(Ports {
+            replayed: fired.ports,
+        });
+    }
+    if fired.mem_after != firing.mem_after {
+        return Err(MemAfter {
+            replayed: fired.mem_after,
+        });
+    }
+    if fired.data != firing.data {
+        return Err(Data {
+            replayed: fired.data,
+        });
+    }
+    for (id, replayed) in delivered {
+        let putter = rule
+            .actions
+            .iter()
+            .find(|a| a.transform.is_none() && a.pg.contains(&id))
+            .map(|a| r.id_map.external(a.putter));
+        let recorded = firing.data.iter().find(|(id, _)| Some(*id) == putter);
+        if let Some((_, recorded)) = recorded {
+            if replayed.as_ref() != Some(recorded) {
+                let getter = r.id_map.external(id);
+                return Err(Delivered { getter, replayed });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Claims the port for `replay`, unless it already has. Returns its internal id.
+fn claim(p: &ProtoAll, port: LocId, claimed: &mut HashMap<LocId, ClaimedPort>) -> Option<LocId> {
+    let id = p.r.id_map.internal(port)?;
+    if !claimed.contains_key(&id) {
+        let mut w = p.lock_region_of(id);
+        let info = w.unclaimed_ports.remove(&id)?;
+        let closed = w.closed.set_to(id, false);
+        claimed.insert(id, ClaimedPort { info, closed });
+    }
+    Some(id)
+}
+
+/// Allocates the datum deserialized from `json` in the storage.
+unsafe fn decode(storage: &mut Storage, type_info: &Arc<TypeInfo>, json: &str) -> Option<*mut u8> {
+    let dest = storage.alloc(type_info);
+    if type_info.funcs.deserialize.execute(json, dest) {
+        Some(dest)
+    } else {
+        storage.forget_inside(dest, type_info);
+        None
+    }
+}
+
+/// Gets a datum with the ready getter, returning it serialized. None if no rule supplies
+/// the getter within `patience`, or the datum cannot be serialized.
+unsafe fn get_serialized(p: &ProtoAll, id: LocId, patience: Duration) -> Option<String> {
+    let po_ge = p.r.get_po_ge(id).expect("replayed getter");
+    let putter_id = po_ge.await_msg_timeout(p, patience, id)?;
+    let type_info =
+        &p.r.get_space_putter(putter_id)
+            .expect("bad putter")
+            .type_info;
+    let mut storage = Storage::default();
+    let dest = storage.alloc(type_info);
+    po_ge.get_data(p, putter_id, dest);
+    // the storage drops the datum
+    type_info.funcs.serialize.execute(dest)
+}
+
+impl ProtoAll {
+    /// Installs the recorder of this protocol instance, replacing any previous one.
+    /// All regions record to it, each firing under the lock of its region.
+    pub fn set_recorder(&self, recorder: TraceRecorder) {
+        let recorder = Arc::new(Mutex::new(recorder));
+        for w in self.regions.iter() {
+            w.lock().recorder = Some(recorder.clone());
+        }
+    }
+    /// Uninstalls and returns the recorder of this protocol instance, if it has one.
+    pub fn take_recorder(&self) -> Option<TraceRecorder> {
+        let mut taken = None;
+        for w in self.regions.iter() {
+            taken = w.lock().recorder.take().or(taken);
+        }
+        // every region has let go of its reference
+        Arc::try_unwrap(taken?).ok().map(Mutex::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::definition::{ActionDef, BehaviourDef, DynProtoBuilder, RuleDef};
+    use crate::proto::traits::HasUnclaimedPorts;
+    use std::convert::TryInto;
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// putter 0 => memory cell 2 => getter 1
+    fn fifo() -> Arc<ProtoAll> {
+        let rule = |putter, getter| RuleDef {
+            guard: Formula::True,
+            actions: vec![ActionDef {
+                putter,
+                getters: vec![getter],
+                transform: None,
+            }],
+            priority: 0,
+        };
+        let def = TypelessProtoDef {
+            behaviour: BehaviourDef {
+                rules: vec![rule(0, 2), rule(2, 1)],
+            },
+            loc_kinds: [
+                (0, LocKind::PortPutter),
+                (1, LocKind::PortGetter),
+                (2, LocKind::MemUninitialized),
+            ]
+            .iter()
+            .copied()
+            .collect(),
+            loc_names: Default::default(),
+            families: Default::default(),
+        };
+        let loc_types = (0..3).map(|id| (id, TypeInfo::new::<u32>())).collect();
+        DynProtoBuilder::new(def, loc_types).build().unwrap()
+    }
+
+    fn record_fifo() -> Vec<Firing> {
+        let p = fifo();
+        let buf = SharedBuf::default();
+        p.set_recorder(TraceRecorder::new(buf.clone()).recording_data());
+        let mut i: Putter<u32> = p.claim(0).try_into().unwrap();
+        let mut o: Getter<u32> = p.claim(1).try_into().unwrap();
+        for x in 0..3 {
+            assert!(i.put(x).is_none());
+            assert_eq!(o.get(), x);
+        }
+        p.take_recorder().unwrap().finish().unwrap();
+        let bytes = buf.0.lock().clone();
+        read_trace(&bytes[..]).unwrap()
+    }
+
+    #[test]
+    fn record_firings() {
+        let trace = record_fifo();
+        assert_eq!(trace.len(), 6);
+        assert_eq!(
+            trace[0],
+            Firing {
+                rule_id: 0,
+                ports: vec![0],
+                mem_before: vec![],
+                mem_after: vec![2],
+                data: vec![(0, "0".to_owned())],
+            }
+        );
+        assert_eq!(trace[3].rule_id, 1);
+        assert_eq!(trace[3].mem_before, vec![2]);
+        assert_eq!(trace[4].data, vec![(0, "2".to_owned())]);
+    }
+
+    const PATIENCE: Duration = Duration::from_millis(200);
+
+    #[test]
+    fn replay_runs() {
+        let trace = record_fifo();
+        let p = fifo();
+        assert_eq!(replay(&p, &trace, PATIENCE), Ok(()));
+        // the ports are unclaimed again, and the instance is ready for more
+        let mut i: Putter<u32> = p.claim(0).try_into().unwrap();
+        let mut o: Getter<u32> = p.claim(1).try_into().unwrap();
+        assert!(i.put(3).is_none());
+        assert_eq!(o.get(), 3);
+    }
+
+    #[test]
+    fn replay_diverges() {
+        let diverges = |trace: &[Firing]| replay(&fifo(), trace, PATIENCE).unwrap_err();
+        let mut trace = record_fifo();
+        trace.swap(2, 3);
+        assert_eq!(
+            diverges(&trace),
+            Divergence {
+                index: 2,
+                kind: DivergenceKind::RuleDisabled,
+            }
+        );
+        trace.swap(2, 3);
+        trace[2].rule_id = 1;
+        assert_eq!(
+            diverges(&trace).kind,
+            DivergenceKind::OtherRule { replayed: 0 }
+        );
+        trace[2].rule_id = 7;
+        assert_eq!(diverges(&trace).kind, DivergenceKind::UnknownRule);
+        trace[2].rule_id = 0;
+        trace[2].mem_before = vec![2];
+        assert_eq!(
+            diverges(&trace).kind,
+            DivergenceKind::MemBefore { replayed: vec![] }
+        );
+        trace[2].mem_before = vec![];
+        trace[2].data.clear();
+        assert_eq!(
+            diverges(&trace).kind,
+            DivergenceKind::MissingDatum { putter: 0 }
+        );
+        trace[2].data = vec![(0, "\"one\"".to_owned())];
+        assert_eq!(
+            diverges(&trace).kind,
+            DivergenceKind::MissingDatum { putter: 0 }
+        );
+    }
+
+    #[test]
+    fn replay_diverges_on_data() {
+        let mut trace = record_fifo();
+        // the datum is put in memory as recorded, but then leaves it as another
+        trace[2].data = vec![(0, "7".to_owned())];
+        assert_eq!(
+            replay(&fifo(), &trace, PATIENCE),
+            Err(Divergence {
+                index: 3,
+                kind: DivergenceKind::Data {
+                    replayed: vec![(2, "7".to_owned())],
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn replay_needs_unclaimed_ports() {
+        let trace = record_fifo();
+        let p = fifo();
+        let _o: Getter<u32> = p.claim(1).try_into().unwrap();
+        assert_eq!(
+            replay(&p, &trace, PATIENCE),
+            Err(Divergence {
+                index: 1,
+                kind: DivergenceKind::Unclaimable { port: 1 },
+            })
+        );
+        // the claim of the replay ended with it
+        let _i: Putter<u32> = p.claim(0).try_into().unwrap();
+    }
+
+    #[test]
+    fn read_malformed() {
+        let input = "\n{\"rule_id\":0,\"ports\":[],\"mem_before\":[],\"mem_after\":[]}\nnope\n";
+        match read_trace(input.as_bytes()) {
+            Err(ReadTraceError::Json { line: 3, .. }) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+}
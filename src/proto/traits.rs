@@ -1,5 +1,5 @@
 use super::*;
-use crate::proto::definition::FuncDef;
+use crate::proto::definition::{Diagnostics, FuncDef};
 
 pub trait EndlessIter {
     fn endless_iter(
@@ -17,16 +17,14 @@ impl EndlessIter for Vec<usize> {
 pub(crate) trait HasMsgDropBox {
     fn get_dropbox(&self) -> &MsgDropbox;
     fn await_msg_timeout(&self, a: &ProtoAll, timeout: Duration, my_id: LocId) -> Option<usize> {
-        println!("getting ... ");
         Some(match self.get_dropbox().recv_timeout(timeout) {
             Some(msg) => msg,
             None => {
-                if a.w.lock().active.ready.set_to(my_id, false) {
+                if a.lock_region_of(my_id).active.ready.set_to(my_id, false) {
                     // managed reverse my readiness
                     return None;
                 } else {
                     // readiness has already been consumed
-                    println!("too late");
                     self.get_dropbox().recv()
                 }
             }
@@ -89,21 +87,99 @@ impl<T: PartialEq> MaybePartialEq for T {
         self.eq(other)
     }
 }
+pub(crate) trait MaybePartialOrd {
+    const IS_DEFINED: bool;
+    fn maybe_partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering>;
+}
+impl<T> MaybePartialOrd for T {
+    default const IS_DEFINED: bool = false;
+    default fn maybe_partial_cmp(&self, _other: &Self) -> Option<std::cmp::Ordering> {
+        panic!("type isn't partial ord!")
+    }
+}
+impl<T: PartialOrd> MaybePartialOrd for T {
+    const IS_DEFINED: bool = true;
+    fn maybe_partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.partial_cmp(other)
+    }
+}
+pub(crate) trait MaybeParsable: Sized {
+    const IS_DEFINED: bool;
+    fn maybe_parse(s: &str) -> Option<Self>;
+}
+impl<T> MaybeParsable for T {
+    default const IS_DEFINED: bool = false;
+    default fn maybe_parse(_s: &str) -> Option<Self> {
+        panic!("type isn't parsable!")
+    }
+}
+impl<T: Parsable> MaybeParsable for T {
+    const IS_DEFINED: bool = true;
+    fn maybe_parse(s: &str) -> Option<Self> {
+        T::try_parse(s)
+    }
+}
+
+pub(crate) trait MaybeSerialize {
+    const IS_DEFINED: bool;
+    fn maybe_serialize(&self) -> Option<String>;
+}
+impl<T> MaybeSerialize for T {
+    default const IS_DEFINED: bool = false;
+    default fn maybe_serialize(&self) -> Option<String> {
+        None
+    }
+}
+impl<T: serde::Serialize> MaybeSerialize for T {
+    const IS_DEFINED: bool = true;
+    fn maybe_serialize(&self) -> Option<String> {
+        serde_json::to_string(self).ok()
+    }
+}
+
+pub(crate) trait MaybeDeserialize: Sized {
+    const IS_DEFINED: bool;
+    fn maybe_deserialize(s: &str) -> Option<Self>;
+}
+impl<T> MaybeDeserialize for T {
+    default const IS_DEFINED: bool = false;
+    default fn maybe_deserialize(_s: &str) -> Option<Self> {
+        None
+    }
+}
+impl<T: serde::de::DeserializeOwned> MaybeDeserialize for T {
+    const IS_DEFINED: bool = true;
+    fn maybe_deserialize(s: &str) -> Option<Self> {
+        serde_json::from_str(s).ok()
+    }
+}
 
 pub trait HasUnclaimedPorts {
     fn claim<T: 'static>(&self, id: LocId) -> ClaimResult<T>;
+    fn claim_by_name<T: 'static>(&self, name: &str) -> ClaimResult<T>;
+    /// Claims all putters of the family with the given name. See `family`.
+    /// Returns the result of the first failed claim, claiming nothing.
+    fn claim_putters<T: 'static>(&self, family: &str) -> Result<Vec<Putter<T>>, ClaimResult<T>>;
+    /// Claims all getters of the family with the given name. See `family`.
+    /// Returns the result of the first failed claim, claiming nothing.
+    fn claim_getters<T: 'static>(&self, family: &str) -> Result<Vec<Getter<T>>, ClaimResult<T>>;
 }
 impl HasUnclaimedPorts for Arc<ProtoAll> {
     fn claim<T: 'static>(&self, id: LocId) -> ClaimResult<T> {
         use ClaimResult::*;
-        let mut w = self.w.lock();
-        if let Some(x) = w.unclaimed_ports.get(&id) {
+        let name = || self.loc_name(id).map(String::from);
+        self.start_coordinator();
+        let internal = self.r.id_map.internal(id);
+        let mut w = self.lock_region_of(internal.unwrap_or(0));
+        if let Some(x) = internal.and_then(|i| w.unclaimed_ports.get(&i)) {
             if x.type_id == TypeId::of::<T>() {
                 let role = x.role;
-                let _ = w.unclaimed_ports.remove(&id);
+                let internal = internal.unwrap();
+                let _ = w.unclaimed_ports.remove(&internal);
+                w.closed.set_to(internal, false);
                 let c = PortCommon {
                     p: self.clone(),
-                    id,
+                    id: internal,
                 };
                 let phantom = Default::default();
                 match role {
@@ -111,12 +187,47 @@ impl HasUnclaimedPorts for Arc<ProtoAll> {
                     PortRole::Getter => GotGetter(Getter { c, phantom }),
                 }
             } else {
-                TypeMismatch
+                TypeMismatch {
+                    loc_id: id,
+                    name: name(),
+                }
             }
         } else {
-            NotUnclaimed
+            NotUnclaimed {
+                loc_id: id,
+                name: name(),
+            }
+        }
+    }
+    fn claim_by_name<T: 'static>(&self, name: &str) -> ClaimResult<T> {
+        match self.loc_id_of(name) {
+            Some(id) => self.claim(id),
+            None => ClaimResult::UnknownName(name.to_owned()),
         }
     }
+    fn claim_putters<T: 'static>(&self, family: &str) -> Result<Vec<Putter<T>>, ClaimResult<T>> {
+        let ids = self
+            .family_ids(family)
+            .ok_or_else(|| ClaimResult::UnknownName(family.to_owned()))?;
+        // on failure, the claimed ports are dropped, unclaiming them again
+        ids.into_iter()
+            .map(|id| match self.claim(id) {
+                ClaimResult::GotPutter(p) => Ok(p),
+                failed => Err(failed),
+            })
+            .collect()
+    }
+    fn claim_getters<T: 'static>(&self, family: &str) -> Result<Vec<Getter<T>>, ClaimResult<T>> {
+        let ids = self
+            .family_ids(family)
+            .ok_or_else(|| ClaimResult::UnknownName(family.to_owned()))?;
+        ids.into_iter()
+            .map(|id| match self.claim(id) {
+                ClaimResult::GotGetter(g) => Ok(g),
+                failed => Err(failed),
+            })
+            .collect()
+    }
 }
 
 pub trait HasProto {
@@ -146,7 +257,7 @@ impl<'a> MemFillPromise<'a> {
             })
         } else {
             self.builder.define_init_memory(self.loc_id, t);
-            Ok(unsafe { std::mem::transmute(()) })
+            Ok(PromiseFulfilled(()))
         }
     }
 }
@@ -165,7 +276,7 @@ impl<'a> FuncDefPromise<'a> {
             fnptr: unsafe { std::mem::transmute(func) },
         };
         self.builder.define_func(self.name, def);
-        unsafe { std::mem::transmute(()) }
+        PromiseFulfilled(())
     }
 
     pub fn define_arity1<R: 'static, A0: 'static>(
@@ -178,42 +289,91 @@ impl<'a> FuncDefPromise<'a> {
             fnptr: unsafe { std::mem::transmute(func) },
         };
         self.builder.define_func(self.name, def);
-        unsafe { std::mem::transmute(()) }
+        PromiseFulfilled(())
+    }
+
+    pub fn define_arity2<R: 'static, A0: 'static, A1: 'static>(
+        self,
+        func: fn(&mut MaybeUninit<R>, *const A0, *const A1),
+    ) -> PromiseFulfilled {
+        let def = FuncDef {
+            ret_info: Arc::new(TypeInfo::new::<R>()),
+            param_info: vec![
+                Arc::new(TypeInfo::new::<A0>()),
+                Arc::new(TypeInfo::new::<A1>()),
+            ],
+            fnptr: unsafe { std::mem::transmute(func) },
+        };
+        self.builder.define_func(self.name, def);
+        PromiseFulfilled(())
+    }
+
+    pub fn define_arity3<R: 'static, A0: 'static, A1: 'static, A2: 'static>(
+        self,
+        func: fn(&mut MaybeUninit<R>, *const A0, *const A1, *const A2),
+    ) -> PromiseFulfilled {
+        let def = FuncDef {
+            ret_info: Arc::new(TypeInfo::new::<R>()),
+            param_info: vec![
+                Arc::new(TypeInfo::new::<A0>()),
+                Arc::new(TypeInfo::new::<A1>()),
+                Arc::new(TypeInfo::new::<A2>()),
+            ],
+            fnptr: unsafe { std::mem::transmute(func) },
+        };
+        self.builder.define_func(self.name, def);
+        PromiseFulfilled(())
     }
-    // TODO 2 and 3
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct WrongMemFillType {
     pub expected_type: TypeId,
 }
-pub enum PromiseFulfilled {}
+/// Proof that a promise was kept. Cannot be constructed outside this module.
+pub struct PromiseFulfilled(());
 
-/// Does not enforce that used LocIds have any particular order or are contiguous,
-/// HOWEVER, leaving gaps in ID-SPACE will reduce efficiency by:
-/// 1. leaving gaps in the protocol's internal buffer (it uses a vector of Space objects)
-/// 2. making inefficient use of bitset operations
+/// Does not enforce that used LocIds have any particular order or are contiguous.
+/// Gaps in ID-SPACE cost nothing: the protocol renumbers its locations densely
+/// internally, while ports are still claimed (and reported) by the LocIds used here.
 pub trait Proto: Sized {
     fn typeless_proto_def() -> &'static TypelessProtoDef;
     fn fill_memory(loc_id: LocId, promise: MemFillPromise) -> Option<PromiseFulfilled>;
     fn def_func(func_name: &'static str, promise: FuncDefPromise) -> Option<PromiseFulfilled>;
     fn loc_type(loc_id: LocId) -> Option<TypeInfo>;
     fn try_instantiate() -> Result<Arc<ProtoAll>, ProtoBuildErr> {
+        Self::try_instantiate_diagnosed().map_err(|d| d.first())
+    }
+    /// Like `try_instantiate`, but collects all errors. See `ProtoBuilder::finish_diagnosed`.
+    fn try_instantiate_diagnosed() -> Result<Arc<ProtoAll>, Diagnostics> {
         use ProtoBuildErr::*;
+        let def = Self::typeless_proto_def();
+        let mut diagnostics = Diagnostics::new(def);
         let mut builder = ProtoBuilder::new();
-        for (&loc_id, kind_ext) in Self::typeless_proto_def().loc_kinds.iter() {
-            if let LocKind::MemInitialized = kind_ext {
-                let promise = MemFillPromise {
-                    loc_id,
-                    type_id_expected: Self::loc_type(loc_id)
-                        .ok_or(UnknownType { loc_id })?
-                        .type_id,
-                    builder: &mut builder,
-                };
-                Self::fill_memory(loc_id, promise).ok_or(MemoryFillPromiseBroken { loc_id })?;
+        let mut mem_ids: Vec<LocId> = def
+            .loc_kinds
+            .iter()
+            .filter(|(_, &kind)| kind == LocKind::MemInitialized)
+            .map(|(&loc_id, _)| loc_id)
+            .collect();
+        mem_ids.sort();
+        for loc_id in mem_ids {
+            let type_id_expected = match Self::loc_type(loc_id) {
+                Some(info) => info.type_id,
+                None => continue, // reported when building
+            };
+            let promise = MemFillPromise {
+                loc_id,
+                type_id_expected,
+                builder: &mut builder,
+            };
+            if Self::fill_memory(loc_id, promise).is_none() {
+                diagnostics.note(MemoryFillPromiseBroken { loc_id }, None, None);
             }
         }
-        Ok(Arc::new(builder.finish::<Self>()?))
+        Ok(Arc::new(
+            builder.finish_diagnosed_with::<Self>(diagnostics)?,
+        ))
     }
     fn instantiate() -> Arc<ProtoAll> {
         match Self::try_instantiate() {
@@ -236,7 +396,6 @@ pub(crate) trait DataSource<'a> {
     where
         I: ExactSizeIterator<Item = *mut u8>,
     {
-        
         use Ordering::SeqCst;
         let space = self.my_space();
         if space.type_info.is_copy {
@@ -251,44 +410,30 @@ pub(crate) trait DataSource<'a> {
                 let somebody_moved = space.move_flags.did_someone_move();
                 self.finalize(somebody_moved, fin);
             }
+        } else if out_ptrs.len() > 0 && space.move_flags.ask_for_move_permission() {
+            // won. the datum is moved out once all other getters have their clones
+            let was = space.cloner_countdown.fetch_sub(1, SeqCst);
+            if was != 1 {
+                space.mover_sema.acquire();
+            }
+            let move_to = out_ptrs.next().unwrap();
+            for out_ptr in out_ptrs {
+                self.execute_clone(out_ptr);
+            }
+            self.execute_copy(move_to);
+            self.finalize(true, fin);
         } else {
-            if out_ptrs.len() > 0 {
-                let won = !space.move_flags.ask_for_move_permission();
-                if won {
-                    let was = space.cloner_countdown.fetch_sub(1, SeqCst);
-                    if was == 1 {
-                        let move_to = out_ptrs.next().unwrap();
-                        for out_ptr in out_ptrs {
-                            self.execute_clone(out_ptr);
-                        }
-                        self.execute_copy(move_to);
-                    } else {
-                        space.mover_sema.acquire();
-                    }
-                    self.finalize(true, fin);
+            // lost, or only want a signal
+            for out_ptr in out_ptrs {
+                self.execute_clone(out_ptr);
+            }
+            let was = space.cloner_countdown.fetch_sub(1, SeqCst);
+            if was == 1 {
+                // all clones are done
+                if space.move_flags.did_someone_move() {
+                    space.mover_sema.release();
                 } else {
-                    // lose
-                    for out_ptr in out_ptrs {
-                        self.execute_clone(out_ptr);
-                    }
-                    let was = space.cloner_countdown.fetch_sub(1, SeqCst);
-                    if was == 1 {
-                        // all clones are done
-                        space.mover_sema.release();
-                    } else {
-                        // do nothing
-                    }
-                }
-            } else {
-                let was = space.cloner_countdown.fetch_sub(1, SeqCst);
-                if was == 1 {
-                    // all clones done
-                    let nobody_else_won = !space.move_flags.ask_for_move_permission();
-                    if nobody_else_won {
-                        self.finalize(false, fin);
-                    } else {
-                        space.mover_sema.release();
-                    }
+                    self.finalize(false, fin);
                 }
             }
         }
@@ -298,16 +443,19 @@ pub(crate) trait DataSource<'a> {
 impl<'a> DataSource<'a> for TempSpace {
     type Finalizer = <MemoSpace as DataSource<'a>>::Finalizer;
     fn my_space(&self) -> &PutterSpace {
-        self.0.my_space()
+        self.mem.my_space()
     }
     fn execute_copy(&self, out_ptr: *mut u8) {
-        self.0.execute_copy(out_ptr)
+        self.mem.execute_copy(out_ptr)
     }
     fn execute_clone(&self, out_ptr: *mut u8) {
-        self.0.execute_clone(out_ptr)
+        self.mem.execute_clone(out_ptr)
     }
     fn finalize(&self, someone_moved: bool, fin: Self::Finalizer) {
-        self.0.finalize(someone_moved, fin)
+        let mut w = fin.0.lock_region_of(fin.1);
+        if let Some(source) = self.make_empty(&fin.0.r, &mut w.active, !someone_moved) {
+            w.ready_set_coordinate(&fin.0.r, source);
+        }
     }
 }
 
@@ -326,7 +474,6 @@ impl<'a> DataSource<'a> for PoPuSpace {
     }
     fn finalize(&self, someone_moved: bool, _fin: Self::Finalizer) {
         let msg = if someone_moved { 1 } else { 0 };
-        println!("POPU FIN MSG = {}", msg);
         self.dropbox.send(msg);
     }
 }
@@ -345,10 +492,9 @@ impl<'a> DataSource<'a> for MemoSpace {
         unsafe { self.p.type_info.funcs.clone.execute(src, out_ptr) };
     }
     fn finalize(&self, someone_moved: bool, fin: Self::Finalizer) {
-        println!("PO GE FINALIZE CLEANUP MEM");
-        let mut w = fin.0.w.lock();
         let putter_id = fin.1;
-        self.make_empty(&mut w.active, !someone_moved, putter_id);
+        let mut w = fin.0.lock_region_of(putter_id);
+        self.make_empty(&fin.0.r, &mut w.active, !someone_moved, putter_id);
         w.ready_set_coordinate(&fin.0.r, putter_id);
     }
 }